@@ -3,9 +3,7 @@ use rusoto_s3::{
     InputSerialization, JSONInput, JSONOutput, OutputSerialization, SelectObjectContentRequest,
 };
 use std::io;
-use surf_bucket_select::model::{
-    event_stream::EventStream, select_object_content::SelectObjectContentEventStreamItem,
-};
+use surf_bucket_select::model::select_object_content::SelectObjectContentEventStreamItem;
 
 #[async_std::main]
 async fn main() -> io::Result<()> {
@@ -43,51 +41,15 @@ async fn main() -> io::Result<()> {
     let credentials_provider = rusoto_core::credential::DefaultCredentialsProvider::new()
         .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))?;
 
-    // Sign the request header
-    let request_builder = surf_bucket_select::select_object_content(
-        "http://localhost:9000".to_string(),
-        select_object_content_request,
-        Some(Box::new(credentials_provider)),
-        "eu-east-3".to_string(),
-        None,
-    )
-    .await
-    .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))?;
-
-    let req = request_builder.build();
+    // The client caches the credentials between the selects.
+    let client = surf_bucket_select::Client::new(client, "http://localhost:9000", "eu-east-3")
+        .with_credentials_provider(credentials_provider);
 
-    let mut res = client
-        .send(req)
+    let mut event_stream = client
+        .select_object_content(select_object_content_request)
         .await
         .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))?;
 
-    let body_bytes = res
-        .body_bytes()
-        .await
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
-
-    if !res.status().is_success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Interrupted,
-            format!(
-                "Curl failed with status code '{}' and response body: {}",
-                res.status(),
-                String::from_utf8(body_bytes)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
-            ),
-        ));
-    }
-
-    if body_bytes.is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::ConnectionAborted,
-            "The response body is empty or surf can't read the body.",
-        ));
-    }
-
-    let mut event_stream =
-        EventStream::<SelectObjectContentEventStreamItem>::new(body_bytes.clone());
-
     let mut data = String::default();
 
     while let Ok(Some(item)) = event_stream.try_next().await {
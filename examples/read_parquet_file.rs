@@ -0,0 +1,45 @@
+use futures::TryStreamExt;
+use rusoto_s3::JSONOutput;
+use std::io;
+use surf_bucket_select::model::select_object_content::SelectObjectContentEventStreamItem;
+use surf_bucket_select::request::SelectRequest;
+use surf_bucket_select::Client;
+
+#[async_std::main]
+async fn main() -> io::Result<()> {
+    // data/multi_lines.parquet holds the records of data/multi_lines.csv. Parquet objects are
+    // always read as a whole: no scan range and no compression type.
+    let select_object_content_request = SelectRequest::new(
+        "my-bucket",
+        "data/multi_lines.parquet",
+        "select s.number, s.string from s3object s where s.number = 20",
+    )
+    .parquet_input()
+    .json_output(JSONOutput::default())
+    .build()
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let credentials_provider = rusoto_core::credential::DefaultCredentialsProvider::new()
+        .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))?;
+
+    let client = Client::new(surf::client(), "http://localhost:9000", "eu-east-3")
+        .with_credentials_provider(credentials_provider);
+
+    let mut stream = client
+        .select_object_content(select_object_content_request)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))?;
+
+    while let Some(item) = stream
+        .try_next()
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))?
+    {
+        if let SelectObjectContentEventStreamItem::Records(records_event) = item {
+            let payload = records_event.payload.unwrap_or_default();
+            print!("{}", String::from_utf8_lossy(&payload));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! Decode the single record returned by an aggregation into a typed value.
+
+use rusoto_s3::OutputSerialization;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::query::ast::{Expr, SelectStatement};
+use crate::query::{parse, Select};
+use crate::schema::{first_char, split_csv_record};
+
+/// Value read from the single column of an aggregation result.
+pub trait FromScalar: Sized {
+    /// Convert the text of the value, `None` is the null value.
+    fn from_scalar(value: Option<&str>) -> std::result::Result<Self, String>;
+}
+
+fn required(value: Option<&str>) -> std::result::Result<&str, String> {
+    value.ok_or_else(|| "The value is null".to_string())
+}
+
+macro_rules! from_scalar_number {
+    ($($type:ty),*) => {
+        $(
+            impl FromScalar for $type {
+                fn from_scalar(value: Option<&str>) -> std::result::Result<Self, String> {
+                    let value = required(value)?.trim();
+                    if let Ok(number) = value.parse() {
+                        return Ok(number);
+                    }
+                    // Sums of integers can be written with a fractional part, `10.0`.
+                    match value.parse::<f64>() {
+                        Ok(number) if number.fract() == 0.0 && number >= <$type>::MIN as f64 && number <= <$type>::MAX as f64 => {
+                            Ok(number as $type)
+                        }
+                        _ => Err(format!("'{}' is not a valid {}", value, stringify!($type))),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_scalar_number!(i32, i64, u32, u64);
+
+impl FromScalar for f64 {
+    fn from_scalar(value: Option<&str>) -> std::result::Result<Self, String> {
+        let value = required(value)?.trim();
+        value
+            .parse()
+            .map_err(|_| format!("'{}' is not a valid f64", value))
+    }
+}
+
+impl FromScalar for bool {
+    fn from_scalar(value: Option<&str>) -> std::result::Result<Self, String> {
+        let value = required(value)?.trim();
+        match value.to_lowercase().as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(format!("'{}' is not a valid bool", value)),
+        }
+    }
+}
+
+impl FromScalar for String {
+    fn from_scalar(value: Option<&str>) -> std::result::Result<Self, String> {
+        required(value).map(ToString::to_string)
+    }
+}
+
+/// Aggregations without input records, like `SUM`, return null.
+impl<T: FromScalar> FromScalar for Option<T> {
+    fn from_scalar(value: Option<&str>) -> std::result::Result<Self, String> {
+        value.map(|value| T::from_scalar(Some(value))).transpose()
+    }
+}
+
+/// Replace the projection of the expression by the aggregation, the `FROM` and `WHERE` clauses
+/// are kept. An empty expression selects the whole object.
+pub(crate) fn aggregate_statement(expression: &str, aggregate: Expr) -> Result<SelectStatement> {
+    let select = if expression.trim().is_empty() {
+        Select::from_object()
+    } else {
+        Select::from(parse(expression)?)
+    };
+
+    Ok(select.clear_columns().column(aggregate).build())
+}
+
+/// Read the single value of the records returned by an aggregation, in CSV or JSON.
+pub(crate) fn decode_scalar<T: FromScalar>(
+    records: &[u8],
+    output_serialization: &OutputSerialization,
+) -> Result<T> {
+    let records = String::from_utf8_lossy(records);
+
+    let value = match &output_serialization.json {
+        Some(json) => {
+            let record = single_record(&records, json.record_delimiter.as_deref())?;
+            json_value(record)?
+        }
+        None => {
+            let csv = output_serialization.csv.clone().unwrap_or_default();
+            let record = single_record(&records, csv.record_delimiter.as_deref())?;
+            let quote = first_char(&csv.quote_character, '"');
+            let mut fields = split_csv_record(
+                record.trim_end_matches('\r'),
+                first_char(&csv.field_delimiter, ','),
+                quote,
+            );
+            if fields.len() != 1 {
+                return Err(Error::InvalidRecord(format!(
+                    "Expected a single column, got {}",
+                    fields.len()
+                )));
+            }
+            // An unquoted empty field is null.
+            let field = fields.remove(0);
+            if field.is_empty() && !record.starts_with(quote) {
+                None
+            } else {
+                Some(field)
+            }
+        }
+    };
+
+    T::from_scalar(value.as_deref()).map_err(Error::InvalidRecord)
+}
+
+fn single_record<'a>(records: &'a str, record_delimiter: Option<&str>) -> Result<&'a str> {
+    let mut records: Vec<&str> = records.split(record_delimiter.unwrap_or("\n")).collect();
+    // The last record ends with the delimiter.
+    if records.last() == Some(&"") {
+        records.pop();
+    }
+
+    match records.as_slice() {
+        [record] => Ok(record),
+        _ => Err(Error::InvalidRecord(format!(
+            "Expected a single record, got {}",
+            records.len()
+        ))),
+    }
+}
+
+/// The columns without alias are named `_1.._N` in the JSON records.
+fn json_value(record: &str) -> Result<Option<String>> {
+    let object = match serde_json::from_str(record) {
+        Ok(Value::Object(object)) => object,
+        Ok(value) => {
+            return Err(Error::InvalidRecord(format!(
+                "Expected a JSON object, got {}",
+                value
+            )))
+        }
+        Err(e) => return Err(Error::InvalidRecord(e.to_string())),
+    };
+    if object.len() != 1 {
+        return Err(Error::InvalidRecord(format!(
+            "Expected a single column, got {}",
+            object.len()
+        )));
+    }
+
+    Ok(match object.into_iter().next().map(|(_, value)| value) {
+        None | Some(Value::Null) => None,
+        Some(Value::String(value)) => Some(value),
+        Some(value) => Some(value.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{col, count_all, sum};
+    use rusoto_s3::{CSVOutput, JSONOutput};
+
+    fn json_output() -> OutputSerialization {
+        OutputSerialization {
+            json: Some(JSONOutput::default()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aggregate_statement_keep_filter() {
+        let statement = aggregate_statement(
+            "select s.name from s3object s where s.size > 10 limit 5",
+            sum(col("size")),
+        )
+        .unwrap();
+        assert_eq!(
+            "SELECT SUM(s.size) FROM S3Object s WHERE s.size > 10 LIMIT 5",
+            statement.to_string()
+        );
+
+        let statement = aggregate_statement("", count_all()).unwrap();
+        assert_eq!("SELECT COUNT(*) FROM S3Object s", statement.to_string());
+    }
+
+    #[test]
+    fn decode_csv() {
+        let output = OutputSerialization::default();
+        assert_eq!(42, decode_scalar::<u64>(b"42\n", &output).unwrap());
+        assert_eq!(10, decode_scalar::<u64>(b"10.0\n", &output).unwrap());
+        assert_eq!(None, decode_scalar::<Option<f64>>(b"\n", &output).unwrap());
+
+        let output = OutputSerialization {
+            csv: Some(CSVOutput {
+                record_delimiter: Some(";".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            "a,b".to_string(),
+            decode_scalar::<String>(b"\"a,b\";", &output).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_json() {
+        assert_eq!(
+            1.5,
+            decode_scalar::<f64>(b"{\"_1\":1.5}\n", &json_output()).unwrap()
+        );
+        assert_eq!(
+            Some(3),
+            decode_scalar::<Option<i64>>(b"{\"total\":3}\n", &json_output()).unwrap()
+        );
+        assert_eq!(
+            None,
+            decode_scalar::<Option<i64>>(b"{\"_1\":null}\n", &json_output()).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_reject_several_records() {
+        let result = decode_scalar::<u64>(b"1\n2\n", &OutputSerialization::default());
+        assert!(matches!(result, Err(Error::InvalidRecord(_))));
+
+        let result = decode_scalar::<u64>(b"{\"_1\":1,\"_2\":2}\n", &json_output());
+        assert!(matches!(result, Err(Error::InvalidRecord(_))));
+
+        let result = decode_scalar::<u64>(b"{\"_1\":null}\n", &json_output());
+        assert!(matches!(result, Err(Error::InvalidRecord(_))));
+    }
+}
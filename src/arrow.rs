@@ -0,0 +1,628 @@
+//! Convert the records returned by a select into Arrow record batches, as the events arrive.
+//!
+//! The schema is given or inferred from the first batch. The JSON values keep their type, the
+//! CSV fields are typed from their text: a column is `Int64`, `Float64` or `Boolean` if all its
+//! non empty fields can be read as such. The CSV columns are named `_1.._N` without schema.
+//!
+//! ```
+//! # use futures::stream::{self, StreamExt, TryStreamExt};
+//! # use rusoto_s3::{CSVOutput, OutputSerialization, RecordsEvent};
+//! # use surf_bucket_select::model::select_object_content::SelectObjectContentEventStreamItem;
+//! use surf_bucket_select::arrow::RecordBatches;
+//!
+//! # async_std::task::block_on(async {
+//! let output_serialization = OutputSerialization {
+//!     csv: Some(CSVOutput::default()),
+//!     json: None,
+//! };
+//! let events = stream::iter(vec![
+//!     SelectObjectContentEventStreamItem::Records(RecordsEvent {
+//!         payload: Some(b"a,1\nb,2\nc,".to_vec().into()),
+//!     }),
+//!     SelectObjectContentEventStreamItem::Records(RecordsEvent {
+//!         payload: Some(b"3\n".to_vec().into()),
+//!     }),
+//! ])
+//! .map(Ok);
+//!
+//! let batches: Vec<_> = RecordBatches::new()
+//!     .with_batch_size(2)
+//!     .stream(&output_serialization, events)
+//!     .unwrap()
+//!     .try_collect()
+//!     .await
+//!     .unwrap();
+//!
+//! assert_eq!(2, batches.len());
+//! assert_eq!("_2", batches[0].schema().field(1).name());
+//! assert_eq!(&arrow_schema::DataType::Int64, batches[0].schema().field(1).data_type());
+//! # });
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use arrow_array::builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use rusoto_s3::OutputSerialization;
+use serde_json::{Map, Value};
+
+use crate::error::{Error, Result};
+use crate::local::reader::{self, RecordReader};
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+use crate::sink::DEFAULT_BATCH_SIZE;
+
+/// A decoded record, the fields in the order of the select.
+pub(crate) type Record = Map<String, Value>;
+
+/// Options of the conversion of the records into record batches.
+#[derive(Clone, Debug)]
+pub struct RecordBatches {
+    schema: Option<SchemaRef>,
+    batch_size: usize,
+}
+
+impl Default for RecordBatches {
+    fn default() -> Self {
+        RecordBatches {
+            schema: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+impl RecordBatches {
+    pub fn new() -> Self {
+        RecordBatches::default()
+    }
+    /// Schema of the batches instead of the inferred one. The JSON fields are matched by name,
+    /// the CSV fields by position. The fields are converted to the type of their column.
+    pub fn with_schema(mut self, schema: SchemaRef) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+    /// Records of each batch, the last one can be smaller.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    /// Convert the `Records` events of a select with this output serialization. A batch is
+    /// returned as soon as it's full, the other events are ignored. If the select is cancelled,
+    /// the complete records received are returned in a last batch before the error.
+    pub fn stream<S>(
+        self,
+        output_serialization: &OutputSerialization,
+        events: S,
+    ) -> Result<BoxStream<'static, Result<RecordBatch>>>
+    where
+        S: Stream<Item = Result<SelectObjectContentEventStreamItem>> + Send + 'static,
+    {
+        let decoder = BatchDecoder::new(output_serialization, self.schema, self.batch_size)?;
+        let state = (events.boxed(), Some(decoder), VecDeque::new(), None);
+
+        Ok(stream::try_unfold(
+            state,
+            |(mut events, mut decoder, mut batches, mut cancelled)| async move {
+                loop {
+                    if let Some(batch) = batches.pop_front() {
+                        return Ok(Some((batch, (events, decoder, batches, cancelled))));
+                    }
+                    let current = match decoder.as_mut() {
+                        Some(decoder) => decoder,
+                        None => return cancelled.map_or(Ok(None), Err),
+                    };
+                    match events.try_next().await {
+                        Ok(Some(SelectObjectContentEventStreamItem::Records(records))) => {
+                            let payload = records.payload.unwrap_or_default();
+                            batches.extend(current.push(&payload)?);
+                        }
+                        Ok(Some(_)) => (),
+                        Ok(None) => {
+                            batches.extend(current.finish()?);
+                            decoder = None;
+                        }
+                        // The records already received are returned before the error.
+                        Err(error @ Error::Cancelled(_)) => {
+                            batches.extend(current.flush()?);
+                            decoder = None;
+                            cancelled = Some(error);
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+            },
+        )
+        .boxed())
+    }
+}
+
+/// Decoder of the payloads of the `Records` events into record batches.
+pub(crate) struct BatchDecoder {
+    reader: RecordReader,
+    schema: Option<SchemaRef>,
+    batch_size: usize,
+    pending: Vec<Record>,
+}
+
+impl BatchDecoder {
+    pub(crate) fn new(
+        output_serialization: &OutputSerialization,
+        schema: Option<SchemaRef>,
+        batch_size: usize,
+    ) -> Result<Self> {
+        if let Some(schema) = &schema {
+            check_schema(schema)?;
+        }
+        Ok(BatchDecoder {
+            reader: RecordReader::for_output(output_serialization)?,
+            schema,
+            batch_size: batch_size.max(1),
+            pending: Vec::new(),
+        })
+    }
+    /// Append the payload and return the batches it completes.
+    pub(crate) fn push(&mut self, payload: &[u8]) -> Result<Vec<RecordBatch>> {
+        self.reader.push(payload);
+        self.read(false)?;
+
+        let mut batches = Vec::new();
+        while self.pending.len() >= self.batch_size {
+            let records: Vec<Record> = self.pending.drain(..self.batch_size).collect();
+            batches.push(record_batch(&self.schema(&records), &records)?);
+        }
+        Ok(batches)
+    }
+    /// The last batch, with the records not returned yet.
+    pub(crate) fn finish(&mut self) -> Result<Option<RecordBatch>> {
+        self.read(true)?;
+        self.flush()
+    }
+    /// The batch of the complete records not returned yet, the end of the last payload is
+    /// ignored.
+    pub(crate) fn flush(&mut self) -> Result<Option<RecordBatch>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let records = std::mem::take(&mut self.pending);
+        Ok(Some(record_batch(&self.schema(&records), &records)?))
+    }
+    /// The given schema, or the one inferred from the first records.
+    pub(crate) fn schema(&mut self, records: &[Record]) -> SchemaRef {
+        let typed_strings = matches!(self.reader, RecordReader::Csv(_));
+        self.schema
+            .get_or_insert_with(|| Arc::new(infer_schema(records, typed_strings)))
+            .clone()
+    }
+    fn read(&mut self, eof: bool) -> Result<()> {
+        while let Some(record) = self.reader.next_record(eof)? {
+            let record = match record {
+                reader::Record::Csv(fields) => fields
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, field)| {
+                        let name = match &self.schema {
+                            Some(schema) if i < schema.fields().len() => {
+                                schema.field(i).name().clone()
+                            }
+                            _ => format!("_{}", i + 1),
+                        };
+                        (name, Value::String(field))
+                    })
+                    .collect(),
+                reader::Record::Json(Value::Object(record)) => record,
+                reader::Record::Json(value) => {
+                    return Err(Error::InvalidRecord(format!(
+                        "'{}' is not a JSON record",
+                        value
+                    )))
+                }
+                #[cfg(feature = "parquet")]
+                reader::Record::Parquet(_) => unreachable!("the output is CSV or JSON"),
+            };
+            self.pending.push(record);
+        }
+        Ok(())
+    }
+}
+
+/// Schema of the records: the fields in the order they first appear. A column is `Boolean`,
+/// `Int64` or `Float64` if all its values have this type, `Utf8` otherwise. With
+/// `typed_strings`, the type of a string is the one of its text and an empty string is null.
+/// All the columns are nullable.
+pub(crate) fn infer_schema(records: &[Record], typed_strings: bool) -> Schema {
+    let mut fields: Vec<(&str, Option<DataType>)> = Vec::new();
+    for record in records {
+        for (name, value) in record {
+            let data_type = match value {
+                Value::Null => None,
+                Value::Bool(_) => Some(DataType::Boolean),
+                Value::Number(number) if number.is_i64() => Some(DataType::Int64),
+                Value::Number(_) => Some(DataType::Float64),
+                Value::String(text) if typed_strings => text_type(text),
+                _ => Some(DataType::Utf8),
+            };
+            match fields.iter_mut().find(|(field, _)| field == name) {
+                None => fields.push((name, data_type)),
+                Some((_, current)) => *current = merge(current.take(), data_type),
+            }
+        }
+    }
+    Schema::new(
+        fields
+            .into_iter()
+            .map(|(name, data_type)| Field::new(name, data_type.unwrap_or(DataType::Utf8), true))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn text_type(text: &str) -> Option<DataType> {
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else if text.parse::<i64>().is_ok() {
+        Some(DataType::Int64)
+    } else if text.parse::<f64>().is_ok() {
+        Some(DataType::Float64)
+    } else if text.eq_ignore_ascii_case("true") || text.eq_ignore_ascii_case("false") {
+        Some(DataType::Boolean)
+    } else {
+        Some(DataType::Utf8)
+    }
+}
+
+fn merge(current: Option<DataType>, other: Option<DataType>) -> Option<DataType> {
+    match (current, other) {
+        (None, other) => other,
+        (current, None) => current,
+        (Some(a), Some(b)) if a == b => Some(a),
+        (Some(DataType::Int64), Some(DataType::Float64))
+        | (Some(DataType::Float64), Some(DataType::Int64)) => Some(DataType::Float64),
+        _ => Some(DataType::Utf8),
+    }
+}
+
+/// Check that the columns have a type supported by [`record_batch`].
+pub(crate) fn check_schema(schema: &Schema) -> Result<()> {
+    for field in schema.fields() {
+        match field.data_type() {
+            DataType::Boolean | DataType::Int64 | DataType::Float64 | DataType::Utf8 => (),
+            _ => return Err(unsupported(field)),
+        }
+    }
+    Ok(())
+}
+
+fn unsupported(field: &Field) -> Error {
+    Error::InvalidRequest(format!(
+        "The column '{}' can't be {}, only Boolean, Int64, Float64 and Utf8 are supported",
+        field.name(),
+        field.data_type()
+    ))
+}
+
+/// Build the batch of the records. The missing fields are null, a field which is not in the
+/// schema or can't be converted to the type of its column is an error.
+pub(crate) fn record_batch(schema: &SchemaRef, records: &[Record]) -> Result<RecordBatch> {
+    for record in records {
+        if let Some(name) = record
+            .keys()
+            .find(|name| schema.field_with_name(name).is_err())
+        {
+            return Err(Error::InvalidRecord(format!(
+                "The field '{}' is not in the schema",
+                name
+            )));
+        }
+    }
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| column(field, records))
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    RecordBatch::try_new_with_options(
+        schema.clone(),
+        columns,
+        &arrow_array::RecordBatchOptions::new().with_row_count(Some(records.len())),
+    )
+    .map_err(|e| Error::InvalidRecord(e.to_string()))
+}
+
+fn column(field: &Field, records: &[Record]) -> Result<ArrayRef> {
+    // An empty string is a null number or boolean, the CSV fields have no null.
+    let values = records.iter().map(|record| {
+        record.get(field.name()).filter(|value| match value {
+            Value::Null => false,
+            Value::String(text) => !text.is_empty() || field.data_type() == &DataType::Utf8,
+            _ => true,
+        })
+    });
+    let invalid = |value: &Value| {
+        Error::InvalidRecord(format!(
+            "'{}' of the column '{}' is not a {}",
+            value,
+            field.name(),
+            field.data_type()
+        ))
+    };
+
+    Ok(match field.data_type() {
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::with_capacity(records.len());
+            for value in values {
+                builder.append_option(
+                    value
+                        .map(|value| {
+                            match value {
+                                Value::Bool(boolean) => Some(*boolean),
+                                Value::String(text) => text.to_ascii_lowercase().parse().ok(),
+                                _ => None,
+                            }
+                            .ok_or_else(|| invalid(value))
+                        })
+                        .transpose()?,
+                );
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Int64 => {
+            let mut builder = Int64Builder::with_capacity(records.len());
+            for value in values {
+                builder.append_option(
+                    value
+                        .map(|value| {
+                            match value {
+                                Value::Number(number) => number.as_i64(),
+                                Value::String(text) => text.trim().parse().ok(),
+                                _ => None,
+                            }
+                            .ok_or_else(|| invalid(value))
+                        })
+                        .transpose()?,
+                );
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(records.len());
+            for value in values {
+                builder.append_option(
+                    value
+                        .map(|value| {
+                            match value {
+                                Value::Number(number) => number.as_f64(),
+                                Value::String(text) => text.trim().parse().ok(),
+                                _ => None,
+                            }
+                            .ok_or_else(|| invalid(value))
+                        })
+                        .transpose()?,
+                );
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::with_capacity(records.len(), records.len() * 8);
+            for value in values {
+                builder.append_option(value.map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                }));
+            }
+            Arc::new(builder.finish())
+        }
+        _ => return Err(unsupported(field)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, Float64Array, Int64Array, StringArray};
+
+    fn records(lines: &str) -> Vec<Record> {
+        lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn json_output() -> OutputSerialization {
+        OutputSerialization {
+            csv: None,
+            json: Some(rusoto_s3::JSONOutput::default()),
+        }
+    }
+
+    #[test]
+    fn decode_json_batches() {
+        let mut decoder = BatchDecoder::new(&json_output(), None, 2).unwrap();
+
+        assert!(decoder
+            .push(b"{\"name\":\"a\",\"size\":1}\n{\"na")
+            .unwrap()
+            .is_empty());
+        let batches = decoder
+            .push(b"me\":\"b\",\"size\":null}\n{\"name\":\"c\",\"size\":3}")
+            .unwrap();
+        assert_eq!(1, batches.len());
+        assert_eq!(2, batches[0].num_rows());
+        assert_eq!(&DataType::Int64, batches[0].schema().field(1).data_type());
+
+        let last = decoder.finish().unwrap().unwrap();
+        assert_eq!(1, last.num_rows());
+        assert_eq!(None, decoder.finish().unwrap());
+
+        assert!(decoder.push(b"[1]\n").is_err());
+    }
+
+    #[test]
+    fn decode_csv_with_schema() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("name", DataType::Utf8, true),
+            Field::new("size", DataType::Float64, true),
+        ]));
+        let output = OutputSerialization {
+            csv: Some(rusoto_s3::CSVOutput::default()),
+            json: None,
+        };
+        let mut decoder = BatchDecoder::new(&output, Some(schema.clone()), 10).unwrap();
+
+        assert!(decoder.push(b"\"a,b\",1\nc,\n").unwrap().is_empty());
+        let batch = decoder.finish().unwrap().unwrap();
+        assert_eq!(schema, batch.schema());
+        let names = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("a,b", names.value(0));
+        let sizes = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(1.0, sizes.value(0));
+        assert!(sizes.is_null(1));
+
+        let mut decoder = BatchDecoder::new(&output, Some(schema), 10).unwrap();
+        decoder.push(b"a,1,extra\n").unwrap();
+        assert!(matches!(decoder.finish(), Err(Error::InvalidRecord(_))));
+    }
+
+    #[async_std::test]
+    async fn stream_batches() {
+        let events = stream::iter(vec![
+            SelectObjectContentEventStreamItem::Records(rusoto_s3::RecordsEvent {
+                payload: Some(b"{\"a\":1}\n{\"a\":2}\n{\"a\":3}\n".to_vec().into()),
+            }),
+            SelectObjectContentEventStreamItem::End(rusoto_s3::EndEvent {}),
+        ])
+        .map(Ok);
+        let batches: Vec<RecordBatch> = RecordBatches::new()
+            .with_batch_size(2)
+            .stream(&json_output(), events)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![2, 1],
+            batches
+                .iter()
+                .map(RecordBatch::num_rows)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[async_std::test]
+    async fn stream_batches_cancelled() {
+        let events = stream::iter(vec![
+            Ok(SelectObjectContentEventStreamItem::Records(
+                rusoto_s3::RecordsEvent {
+                    payload: Some(b"{\"a\":1}\n{\"a\":2}\n{\"a\"".to_vec().into()),
+                },
+            )),
+            Err(Error::Cancelled(Box::default())),
+        ]);
+        let mut batches = RecordBatches::new().stream(&json_output(), events).unwrap();
+
+        assert_eq!(2, batches.try_next().await.unwrap().unwrap().num_rows());
+        assert!(matches!(batches.try_next().await, Err(Error::Cancelled(_))));
+        assert!(batches.try_next().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn infer_types() {
+        let records = records(
+            "{\"a\":1,\"b\":1,\"c\":null,\"d\":true}\n{\"a\":2,\"b\":1.5,\"c\":\"x\",\"e\":[1]}\n",
+        );
+        let schema = infer_schema(&records, false);
+
+        let types: Vec<_> = schema
+            .fields()
+            .iter()
+            .map(|field| field.data_type())
+            .collect();
+        assert_eq!(
+            vec![
+                &DataType::Int64,
+                &DataType::Float64,
+                &DataType::Utf8,
+                &DataType::Boolean,
+                &DataType::Utf8
+            ],
+            types
+        );
+    }
+
+    #[test]
+    fn infer_csv_types() {
+        let records = records(
+            "{\"_1\":\"1\",\"_2\":\"1\",\"_3\":\"\",\"_4\":\"TRUE\",\"_5\":\"x\"}\n\
+             {\"_1\":\"-2\",\"_2\":\"1.5\",\"_3\":\"3\",\"_4\":\"false\",\"_5\":\"1\"}",
+        );
+
+        let types: Vec<DataType> = infer_schema(&records, true)
+            .fields()
+            .iter()
+            .map(|field| field.data_type().clone())
+            .collect();
+        assert_eq!(
+            vec![
+                DataType::Int64,
+                DataType::Float64,
+                DataType::Int64,
+                DataType::Boolean,
+                DataType::Utf8
+            ],
+            types
+        );
+    }
+
+    #[test]
+    fn build_batch() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("name", DataType::Utf8, true),
+            Field::new("size", DataType::Int64, true),
+            Field::new("ratio", DataType::Float64, true),
+        ]));
+        let batch = record_batch(
+            &schema,
+            &records("{\"name\":\"a\",\"size\":\"3\",\"ratio\":\"0.5\"}\n{\"name\":\"b\"}\n"),
+        )
+        .unwrap();
+
+        assert_eq!(2, batch.num_rows());
+        let names = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("b", names.value(1));
+        let sizes = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(3, sizes.value(0));
+        assert!(sizes.is_null(1));
+        let ratios = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(0.5, ratios.value(0));
+
+        assert!(matches!(
+            record_batch(&schema, &records("{\"size\":\"big\"}")),
+            Err(Error::InvalidRecord(_))
+        ));
+        assert!(matches!(
+            record_batch(&schema, &records("{\"other\":1}")),
+            Err(Error::InvalidRecord(_))
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+//! `s3select`: run a select on an object and print the records.
+//!
+//! ```text
+//! s3select --endpoint http://localhost:9000 s3://my-bucket/data/multi_lines.csv \
+//!     "SELECT s.number FROM S3Object s WHERE s.number > 10" --output table
+//! ```
+
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, ValueEnum};
+use futures::TryStreamExt;
+use rusoto_core::credential::{DefaultCredentialsProvider, ProfileProvider};
+use rusoto_s3::{CSVInput, CSVOutput, JSONInput, JSONOutput, SelectObjectContentRequest, Stats};
+use surf_bucket_select::model::select_object_content::SelectObjectContentEventStreamItem;
+use surf_bucket_select::request::SelectRequest;
+use surf_bucket_select::{Client, Timeouts};
+
+mod output;
+mod repl;
+
+use output::{OutputFormat, RecordsWriter};
+use repl::Repl;
+
+/// Bucket and key of an object.
+type Object = (String, String);
+
+/// Exit code of the failed selects.
+const EXIT_FAILURE: u8 = 1;
+/// Exit code of the invalid arguments, the same as the errors of clap.
+const EXIT_USAGE: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    Csv,
+    Json,
+    Parquet,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Compression {
+    /// From the key suffix or the object metadata.
+    Auto,
+    None,
+    Gzip,
+    Bzip2,
+}
+
+/// Run an S3 Select query on an object and write the records to stdout.
+#[derive(Debug, Parser)]
+#[command(name = "s3select", version)]
+struct Args {
+    /// Object to select: `s3://bucket/key`, omitted with `--bucket` and `--key`.
+    object: Option<String>,
+    /// SQL expression, like `SELECT * FROM S3Object s LIMIT 10`. Without expression, the
+    /// queries are read from the interactive prompt.
+    sql: Option<String>,
+    #[arg(long, requires = "key")]
+    bucket: Option<String>,
+    #[arg(long, requires = "bucket")]
+    key: Option<String>,
+    /// S3 endpoint, `https://s3.<region>.amazonaws.com` by default.
+    #[arg(long, env = "S3_ENDPOINT")]
+    endpoint: Option<String>,
+    #[arg(long, env = "AWS_REGION", default_value = "us-east-1")]
+    region: String,
+    /// Profile of the AWS credentials file, the default credentials chain is used otherwise.
+    #[arg(long, env = "AWS_PROFILE")]
+    profile: Option<String>,
+    /// Send anonymous requests.
+    #[arg(long)]
+    no_sign_request: bool,
+    /// Format of the object, guessed from the key extension by default.
+    #[arg(long, value_enum)]
+    input: Option<InputFormat>,
+    #[arg(long, value_enum, default_value = "auto")]
+    compression: Compression,
+    /// CSV header: USE, IGNORE or NONE.
+    #[arg(long, default_value = "USE")]
+    header: String,
+    /// CSV field delimiter.
+    #[arg(long)]
+    delimiter: Option<String>,
+    /// CSV quote character.
+    #[arg(long)]
+    quote: Option<String>,
+    /// CSV record delimiter.
+    #[arg(long)]
+    record_delimiter: Option<String>,
+    /// JSON input type: DOCUMENT or LINES.
+    #[arg(long, default_value = "LINES")]
+    json_type: String,
+    /// Format of the records, `csv` by default and `table` in the interactive mode.
+    #[arg(long, value_enum)]
+    output: Option<OutputFormat>,
+    /// Maximum duration of the select, in seconds.
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// Read the object and evaluate the query locally if the server doesn't implement Select.
+    #[arg(long)]
+    fallback: bool,
+    /// Don't print the statistics on stderr.
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+impl Args {
+    /// Object and SQL expression. With `--bucket` and `--key` the only positional argument
+    /// is the expression.
+    fn target(&self) -> Result<(Option<Object>, Option<String>), String> {
+        match (&self.bucket, &self.key) {
+            (Some(bucket), Some(key)) => {
+                if self.sql.is_some() {
+                    return Err("Give either an s3:// URL or --bucket and --key".to_string());
+                }
+                Ok((Some((bucket.clone(), key.clone())), self.object.clone()))
+            }
+            _ => {
+                let object = self.object.as_deref().map(parse_s3_url).transpose()?;
+                Ok((object, self.sql.clone()))
+            }
+        }
+    }
+    /// Request of a single select, the object and the expression are required.
+    fn request(&self) -> Result<SelectObjectContentRequest, String> {
+        let (object, sql) = self.target()?;
+        let (bucket, key) = object.ok_or_else(|| {
+            "The object is missing, give an s3:// URL or --bucket and --key".to_string()
+        })?;
+        let sql = sql.ok_or_else(|| "The SQL expression is missing".to_string())?;
+        let input = self.input.unwrap_or_else(|| input_format(&key));
+        self.select_request(
+            bucket,
+            key,
+            sql,
+            input,
+            self.output.unwrap_or(OutputFormat::Csv),
+        )
+    }
+    /// Request with the CSV, JSON and compression options of the command line.
+    fn select_request(
+        &self,
+        bucket: String,
+        key: String,
+        sql: String,
+        input: InputFormat,
+        output: OutputFormat,
+    ) -> Result<SelectObjectContentRequest, String> {
+        let request = SelectRequest::new(bucket, key, sql);
+
+        let request = match input {
+            InputFormat::Csv => request.csv_input(CSVInput {
+                file_header_info: Some(self.header.to_uppercase()),
+                field_delimiter: self.delimiter.clone(),
+                quote_character: self.quote.clone(),
+                record_delimiter: self.record_delimiter.clone(),
+                ..Default::default()
+            }),
+            InputFormat::Json => request.json_input(JSONInput {
+                type_: Some(self.json_type.to_uppercase()),
+            }),
+            InputFormat::Parquet => request.parquet_input(),
+        };
+        let request = match self.compression {
+            Compression::Auto => request,
+            Compression::None => request.compression_type("NONE"),
+            Compression::Gzip => request.compression_type("GZIP"),
+            Compression::Bzip2 => request.compression_type("BZIP2"),
+        };
+        let request = match output {
+            OutputFormat::Csv => request.csv_output(CSVOutput::default()),
+            _ => request.json_output(JSONOutput::default()),
+        };
+
+        request.build().map_err(|e| e.to_string())
+    }
+    fn client(&self) -> Result<Client, String> {
+        let endpoint = self
+            .endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", self.region));
+        let mut timeouts = Timeouts::default();
+        if let Some(timeout) = self.timeout {
+            timeouts = timeouts.with_total(Duration::from_secs(timeout));
+        }
+        let client = Client::new(surf::client(), endpoint, self.region.clone())
+            .with_timeouts(timeouts)
+            .with_filter_continuations(true)
+            .with_get_object_fallback(self.fallback)
+            .with_compression_detection(self.compression == Compression::Auto);
+
+        if self.no_sign_request {
+            return Ok(client);
+        }
+        match &self.profile {
+            Some(profile) => {
+                let mut provider = ProfileProvider::new().map_err(|e| e.to_string())?;
+                provider.set_profile(profile.clone());
+                Ok(client.with_credentials_provider(provider))
+            }
+            None => Ok(client.with_credentials_provider(
+                DefaultCredentialsProvider::new().map_err(|e| e.to_string())?,
+            )),
+        }
+    }
+}
+
+/// Format of the object guessed from the extension of its key, CSV by default.
+fn input_format(key: &str) -> InputFormat {
+    let key = key.to_ascii_lowercase();
+    let key = key
+        .trim_end_matches(".gz")
+        .trim_end_matches(".gzip")
+        .trim_end_matches(".bz2");
+    if key.ends_with(".parquet") {
+        InputFormat::Parquet
+    } else if key.ends_with(".json") || key.ends_with(".jsonl") || key.ends_with(".ndjson") {
+        InputFormat::Json
+    } else {
+        InputFormat::Csv
+    }
+}
+
+/// Split `s3://bucket/key` into the bucket and the key.
+fn parse_s3_url(url: &str) -> Result<Object, String> {
+    let path = url
+        .strip_prefix("s3://")
+        .ok_or_else(|| format!("'{}' is not an s3:// URL", url))?;
+    match path.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+            Ok((bucket.to_string(), key.to_string()))
+        }
+        _ => Err(format!("'{}' must be s3://bucket/key", url)),
+    }
+}
+
+/// Run the select and write the records, returns the statistics of the select.
+async fn run(
+    client: &Client,
+    request: SelectObjectContentRequest,
+    writer: &mut RecordsWriter<impl Write>,
+) -> Result<Option<Stats>, Box<dyn std::error::Error>> {
+    let mut stream = client.select_object_content(request).await?;
+    let mut stats = None;
+    while let Some(event) = stream.try_next().await? {
+        match event {
+            SelectObjectContentEventStreamItem::Records(records) => {
+                writer.write(records.payload.as_deref().unwrap_or_default())?;
+            }
+            SelectObjectContentEventStreamItem::Stats(event) => stats = event.details,
+            _ => (),
+        }
+    }
+    writer.finish()?;
+    Ok(stats)
+}
+
+fn print_stats(stats: Option<&Stats>, elapsed: Duration) {
+    let value = |value: Option<i64>| value.map_or("-".to_string(), |value| value.to_string());
+    match stats {
+        Some(stats) => eprintln!(
+            "Bytes scanned: {}, processed: {}, returned: {} in {:.3}s",
+            value(stats.bytes_scanned),
+            value(stats.bytes_processed),
+            value(stats.bytes_returned),
+            elapsed.as_secs_f64()
+        ),
+        None => eprintln!("No statistics received in {:.3}s", elapsed.as_secs_f64()),
+    }
+}
+
+#[async_std::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let interactive = matches!(args.target(), Ok((_, None)));
+    let client = match args.client() {
+        Ok(client) => client,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    if interactive {
+        return match Repl::new(args, client).run().await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::from(EXIT_FAILURE)
+            }
+        };
+    }
+    let request = match args.request() {
+        Ok(request) => request,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let started_at = Instant::now();
+    let stdout = io::stdout();
+    let mut writer = RecordsWriter::new(args.output.unwrap_or(OutputFormat::Csv), stdout.lock());
+    match run(&client, request, &mut writer).await {
+        Ok(stats) => {
+            if !args.quiet {
+                print_stats(stats.as_ref(), started_at.elapsed());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            Ok(("my-bucket".to_string(), "data/part 0.csv".to_string())),
+            parse_s3_url("s3://my-bucket/data/part 0.csv")
+        );
+        assert!(parse_s3_url("s3://my-bucket").is_err());
+        assert!(parse_s3_url("https://my-bucket/key").is_err());
+    }
+
+    #[test]
+    fn build_request() {
+        let args = Args::parse_from([
+            "s3select",
+            "s3://my-bucket/data/part-0.json.gz",
+            "SELECT * FROM S3Object[*] s",
+            "--output",
+            "ndjson",
+        ]);
+        let request = args.request().unwrap();
+
+        assert_eq!("my-bucket", request.bucket);
+        assert!(request.input_serialization.json.is_some());
+        assert!(request.output_serialization.json.is_some());
+
+        let args = Args::parse_from([
+            "s3select",
+            "--bucket",
+            "my-bucket",
+            "--key",
+            "data/part-0.parquet",
+            "SELECT s.name FROM S3Object s",
+        ]);
+        let request = args.request().unwrap();
+
+        assert_eq!("data/part-0.parquet", request.key);
+        assert_eq!("SELECT s.name FROM S3Object s", request.expression);
+        assert!(request.input_serialization.parquet.is_some());
+        assert!(request.output_serialization.csv.is_some());
+    }
+}
@@ -0,0 +1,210 @@
+//! Write the records of the select to stdout in the format of `--output`.
+
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// The CSV records, as returned by S3.
+    Csv,
+    /// A JSON array of the records.
+    Json,
+    /// One JSON record per line.
+    Ndjson,
+    /// Aligned columns, printed once all the records are received.
+    Table,
+}
+
+/// Writer of the payloads of the `Records` events. The CSV payloads are copied, the other
+/// formats are selected with JSON records which are split on the new lines since a record
+/// can span two events.
+pub(crate) struct RecordsWriter<W: Write> {
+    format: OutputFormat,
+    output: W,
+    /// End of the last payload, not terminated by a new line.
+    pending: Vec<u8>,
+    records: usize,
+    /// Rows of the table, printed by `finish`.
+    rows: Vec<Map<String, Value>>,
+}
+
+impl<W: Write> RecordsWriter<W> {
+    pub(crate) fn new(format: OutputFormat, output: W) -> Self {
+        RecordsWriter {
+            format,
+            output,
+            pending: Vec::new(),
+            records: 0,
+            rows: Vec::new(),
+        }
+    }
+    pub(crate) fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        if self.format == OutputFormat::Csv {
+            return self.output.write_all(payload);
+        }
+        self.pending.extend_from_slice(payload);
+        let end = match self.pending.iter().rposition(|byte| *byte == b'\n') {
+            Some(position) => position + 1,
+            None => return Ok(()),
+        };
+        let lines: Vec<u8> = self.pending.drain(..end).collect();
+        for line in lines.split(|byte| *byte == b'\n') {
+            self.write_line(line)?;
+        }
+        Ok(())
+    }
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let record: Map<String, Value> = serde_json::from_slice(line)?;
+        match self.format {
+            OutputFormat::Csv => unreachable!("the CSV payloads are copied"),
+            OutputFormat::Json => {
+                self.output
+                    .write_all(if self.records == 0 { b"[\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut self.output, &record)?;
+            }
+            OutputFormat::Ndjson => {
+                serde_json::to_writer(&mut self.output, &record)?;
+                self.output.write_all(b"\n")?;
+            }
+            OutputFormat::Table => self.rows.push(record),
+        }
+        self.records += 1;
+        Ok(())
+    }
+    /// Number of records written, unknown for the CSV records which are not parsed.
+    pub(crate) fn records(&self) -> Option<usize> {
+        match self.format {
+            OutputFormat::Csv => None,
+            _ => Some(self.records),
+        }
+    }
+    /// Write the last record and close the JSON array or print the table.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if self.format != OutputFormat::Csv {
+            self.write_line(&pending)?;
+        }
+        match self.format {
+            OutputFormat::Json if self.records == 0 => self.output.write_all(b"[]\n")?,
+            OutputFormat::Json => self.output.write_all(b"\n]\n")?,
+            OutputFormat::Table => {
+                let rows = std::mem::take(&mut self.rows);
+                write_table(&mut self.output, &rows)?;
+            }
+            OutputFormat::Csv | OutputFormat::Ndjson => (),
+        }
+        self.output.flush()
+    }
+}
+
+/// Print the rows in aligned columns, the columns are the fields of the rows in the order
+/// they first appear.
+pub(crate) fn write_table<W: Write>(output: &mut W, rows: &[Map<String, Value>]) -> io::Result<()> {
+    let mut columns: Vec<&str> = Vec::new();
+    for row in rows {
+        for name in row.keys() {
+            if !columns.contains(&name.as_str()) {
+                columns.push(name);
+            }
+        }
+    }
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| match row.get(*column) {
+                    None => String::new(),
+                    Some(Value::String(text)) => text.clone(),
+                    Some(value) => value.to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(column.chars().count()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let write_row = |output: &mut W, row: &[&str]| -> io::Result<()> {
+        // The missing fields at the end of the row are not padded.
+        let len = row
+            .iter()
+            .rposition(|cell| !cell.is_empty())
+            .map_or(0, |i| i + 1);
+        let line: Vec<String> = row[..len]
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        writeln!(output, "{}", line.join(" | ").trim_end())
+    };
+    write_row(output, &columns)?;
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    writeln!(output, "{}", separator.join("-+-"))?;
+    for row in &cells {
+        let row: Vec<&str> = row.iter().map(String::as_str).collect();
+        write_row(output, &row)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(format: OutputFormat, payloads: &[&str]) -> String {
+        let mut output = Vec::new();
+        let mut writer = RecordsWriter::new(format, &mut output);
+        for payload in payloads {
+            writer.write(payload.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn write_split_records() {
+        let payloads = [
+            "{\"name\":\"a\",\"size\":1}\n{\"na",
+            "me\":\"bc\",\"size\":20}\n",
+        ];
+
+        assert_eq!(
+            "{\"name\":\"a\",\"size\":1}\n{\"name\":\"bc\",\"size\":20}\n",
+            write(OutputFormat::Ndjson, &payloads)
+        );
+        assert_eq!(
+            "[\n{\"name\":\"a\",\"size\":1},\n{\"name\":\"bc\",\"size\":20}\n]\n",
+            write(OutputFormat::Json, &payloads)
+        );
+        assert_eq!("[]\n", write(OutputFormat::Json, &[]));
+    }
+
+    #[test]
+    fn write_aligned_table() {
+        let payloads =
+            ["{\"name\":\"a\",\"size\":1}\n{\"name\":\"bcd\",\"size\":20,\"extra\":null}"];
+
+        assert_eq!(
+            "name | size | extra\n\
+             -----+------+------\n\
+             a    | 1\n\
+             bcd  | 20   | null\n",
+            write(OutputFormat::Table, &payloads)
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Interactive mode: several selects with the same connection and credentials.
+//!
+//! A line is either a query on the current object or a command starting with `\`.
+
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use clap::ValueEnum;
+use rusoto_s3::SelectObjectContentRequest;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use surf_bucket_select::query;
+use surf_bucket_select::Client;
+
+use super::output::{write_table, OutputFormat, RecordsWriter};
+use super::{input_format, parse_s3_url, run, Args, InputFormat, Object};
+
+const HELP: &str = "\
+SELECT ...           run the query on the current object, a trailing ';' is ignored
+\\use s3://bucket/key select another object
+\\input [FORMAT]      csv, json or parquet, guessed from the key without format
+\\format FORMAT       table, csv, json or ndjson
+\\limit [N]           add LIMIT N to the queries without limit, no limit without N
+\\schema              columns of the CSV header of the current object
+\\history             previous lines
+\\help                this help
+\\quit                leave, like Ctrl-D";
+
+/// Line read from the prompt.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Query(String),
+    Use(String),
+    Input(Option<InputFormat>),
+    Format(OutputFormat),
+    Limit(Option<u64>),
+    Schema,
+    History,
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parse the line, `None` for an empty line.
+    fn parse(line: &str) -> Result<Option<Command>, String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let command = match line.strip_prefix('\\') {
+            Some(command) => command,
+            None => {
+                let sql = line.trim_end_matches(';').trim_end();
+                return Ok(Some(Command::Query(sql.to_string())));
+            }
+        };
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (command, None),
+        };
+        let command = match (name, argument) {
+            ("use", Some(object)) => Command::Use(object.to_string()),
+            ("input", None) => Command::Input(None),
+            ("input", Some(format)) => Command::Input(Some(InputFormat::from_str(format, true)?)),
+            ("format", Some(format)) => Command::Format(OutputFormat::from_str(format, true)?),
+            ("limit", None) => Command::Limit(None),
+            ("limit", Some(limit)) => Command::Limit(Some(
+                limit
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a number of records", limit))?,
+            )),
+            ("schema", None) => Command::Schema,
+            ("history", None) => Command::History,
+            ("help", None) | ("?", None) => Command::Help,
+            ("quit", None) | ("q", None) => Command::Quit,
+            _ => return Err(format!("Unknown command '\\{}', try \\help", command)),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Add the limit to the query if it has none. The query is sent as it is if it can't be
+/// parsed, the client reports the error.
+fn with_limit(sql: &str, limit: Option<u64>) -> String {
+    match (limit, query::parse(sql)) {
+        (Some(limit), Ok(mut statement)) if statement.limit.is_none() => {
+            statement.limit = Some(limit);
+            statement.to_string()
+        }
+        _ => sql.to_string(),
+    }
+}
+
+pub(crate) struct Repl {
+    args: Args,
+    client: Client,
+    /// Bucket and key of the current object.
+    object: Option<Object>,
+    /// Input format, guessed from the key if not set.
+    input: Option<InputFormat>,
+    output: OutputFormat,
+    limit: Option<u64>,
+}
+
+impl Repl {
+    pub(crate) fn new(args: Args, client: Client) -> Self {
+        let object = args.target().ok().and_then(|(object, _)| object);
+        Repl {
+            input: args.input,
+            output: args.output.unwrap_or(OutputFormat::Table),
+            client,
+            object,
+            limit: None,
+            args,
+        }
+    }
+    /// Read and run the lines until the end of the input.
+    pub(crate) async fn run(mut self) -> Result<(), Box<dyn Error>> {
+        let mut editor = DefaultEditor::new()?;
+        let history = history_path();
+        if let Some(history) = &history {
+            // There is no history the first time.
+            let _ = editor.load_history(history);
+        }
+        eprintln!("Type \\help for the commands, Ctrl-D to leave.");
+
+        loop {
+            let line = match editor.readline(&self.prompt()) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let command = match Command::parse(&line) {
+                Ok(Some(command)) => command,
+                Ok(None) => continue,
+                Err(message) => {
+                    eprintln!("error: {}", message);
+                    continue;
+                }
+            };
+            editor.add_history_entry(line.trim())?;
+            match command {
+                Command::Quit => break,
+                Command::History => {
+                    for (i, line) in editor.history().iter().enumerate() {
+                        println!("{:>4}  {}", i + 1, line);
+                    }
+                }
+                command => {
+                    if let Err(e) = self.execute(command).await {
+                        eprintln!("error: {}", e);
+                    }
+                }
+            }
+        }
+
+        if let Some(history) = &history {
+            editor.save_history(history)?;
+        }
+        Ok(())
+    }
+    fn prompt(&self) -> String {
+        match &self.object {
+            Some((bucket, key)) => format!("s3://{}/{}> ", bucket, key),
+            None => "s3select> ".to_string(),
+        }
+    }
+    async fn execute(&mut self, command: Command) -> Result<(), Box<dyn Error>> {
+        match command {
+            Command::Query(sql) => self.query(&sql).await?,
+            Command::Use(object) => self.object = Some(parse_s3_url(&object)?),
+            Command::Input(input) => self.input = input,
+            Command::Format(output) => self.output = output,
+            Command::Limit(limit) => self.limit = limit,
+            Command::Schema => self.schema().await?,
+            Command::Help => println!("{}", HELP),
+            Command::History | Command::Quit => (),
+        }
+        Ok(())
+    }
+    /// Request on the current object with the current formats.
+    fn request(&self, sql: String) -> Result<SelectObjectContentRequest, Box<dyn Error>> {
+        let (bucket, key) = self
+            .object
+            .clone()
+            .ok_or("No object selected, use \\use s3://bucket/key")?;
+        let input = self.input.unwrap_or_else(|| input_format(&key));
+        Ok(self
+            .args
+            .select_request(bucket, key, sql, input, self.output)?)
+    }
+    async fn query(&self, sql: &str) -> Result<(), Box<dyn Error>> {
+        let request = self.request(with_limit(sql, self.limit))?;
+
+        let started_at = Instant::now();
+        let stdout = io::stdout();
+        let mut writer = RecordsWriter::new(self.output, stdout.lock());
+        let stats = run(&self.client, request, &mut writer).await?;
+        let elapsed = started_at.elapsed();
+
+        let records = writer
+            .records()
+            .map_or(String::new(), |records| format!("{} records, ", records));
+        let scanned = stats
+            .and_then(|stats| stats.bytes_scanned)
+            .map_or("unknown".to_string(), |bytes| bytes.to_string());
+        println!(
+            "({}{:.3}s, {} bytes scanned)",
+            records,
+            elapsed.as_secs_f64(),
+            scanned
+        );
+        Ok(())
+    }
+    /// Print the columns read from the header of the CSV object.
+    async fn schema(&self) -> Result<(), Box<dyn Error>> {
+        let request = self.request("SELECT * FROM S3Object".to_string())?;
+        let schema = self.client.infer_schema(&request).await?;
+
+        let rows: Vec<_> = schema
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let mut row = serde_json::Map::new();
+                row.insert("position".to_string(), format!("_{}", i + 1).into());
+                row.insert("column".to_string(), name.clone().into());
+                row
+            })
+            .collect();
+        write_table(&mut io::stdout().lock(), &rows)?;
+        Ok(())
+    }
+}
+
+/// `~/.s3select_history`, no history is kept without home directory.
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".s3select_history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Ok(None), Command::parse("  "));
+        assert_eq!(
+            Ok(Some(Command::Query("SELECT * FROM S3Object".to_string()))),
+            Command::parse("SELECT * FROM S3Object ;")
+        );
+        assert_eq!(
+            Ok(Some(Command::Use("s3://my-bucket/data.csv".to_string()))),
+            Command::parse("\\use  s3://my-bucket/data.csv")
+        );
+        assert_eq!(
+            Ok(Some(Command::Input(Some(InputFormat::Json)))),
+            Command::parse("\\input JSON")
+        );
+        assert_eq!(
+            Ok(Some(Command::Format(OutputFormat::Ndjson))),
+            Command::parse("\\format ndjson")
+        );
+        assert_eq!(
+            Ok(Some(Command::Limit(Some(20)))),
+            Command::parse("\\limit 20")
+        );
+        assert_eq!(Ok(Some(Command::Limit(None))), Command::parse("\\limit"));
+        assert!(Command::parse("\\limit ten").is_err());
+        assert!(Command::parse("\\format xml").is_err());
+        assert!(Command::parse("\\unknown").is_err());
+    }
+
+    #[test]
+    fn add_limit() {
+        assert_eq!(
+            "SELECT s.name FROM S3Object s LIMIT 10",
+            with_limit("select s.name from S3Object s", Some(10))
+        );
+        assert_eq!(
+            "SELECT * FROM S3Object LIMIT 2",
+            with_limit("SELECT * FROM S3Object LIMIT 2", Some(10))
+        );
+        assert_eq!("SELECT nothing", with_limit("SELECT nothing", Some(10)));
+        assert_eq!(
+            "select * from S3Object",
+            with_limit("select * from S3Object", None)
+        );
+    }
+}
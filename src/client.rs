@@ -0,0 +1,1387 @@
+//! Client reusing the http connection and the credentials between several selects.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use rusoto_core::credential::ProvideAwsCredentials;
+use rusoto_s3::{
+    CSVOutput, GetObjectRequest, HeadObjectOutput, HeadObjectRequest, ListObjectsV2Output,
+    ListObjectsV2Request, RequestProgress, SelectObjectContentRequest,
+};
+use surf::{RequestBuilder, StatusCode};
+
+use crate::aggregate::{aggregate_statement, decode_scalar, FromScalar};
+#[cfg(feature = "arrow")]
+use crate::arrow::RecordBatches;
+use crate::cost::{PriceTable, STORAGE_CLASS_STANDARD};
+use crate::credential::CachedCredentialsProvider;
+use crate::error::{Error, Result};
+use crate::local::{scan_range_position, select_local_at};
+use crate::model::event_stream::EventStream;
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+use crate::object::{deserialize_head_object, deserialize_list_objects_v2};
+use crate::prefix::PrefixSelect;
+use crate::query::ast::Expr;
+use crate::query::count_all;
+use crate::request::{
+    compression_from_head, compression_from_key, is_uncompressed_key, COMPRESSION_NONE,
+};
+use crate::schema::Schema;
+use crate::select::{with_deadline, AbortHandle, Budget, SelectStream, SelectSummary, Timeouts};
+#[cfg(feature = "tracing")]
+use crate::telemetry::Telemetry;
+
+/// Size of the first range read to find the header of a CSV object.
+const HEADER_RANGE_SIZE: u64 = 64 * 1024;
+/// The schema inference fails if no header is found in the first bytes.
+const MAX_HEADER_SIZE: u64 = 1024 * 1024;
+
+/// Send select requests to a bucket.
+///
+/// The credentials are cached and shared by all the clones of the client, so the credentials
+/// provider is only called when the credentials are about to expire, or once more when the
+/// server rejects them.
+#[derive(Clone)]
+pub struct Client {
+    http_client: surf::Client,
+    endpoint: String,
+    region: String,
+    credentials_provider: Option<Arc<CachedCredentialsProvider>>,
+    timeouts: Timeouts,
+    filter_continuations: bool,
+    query_validation: bool,
+    get_object_fallback: bool,
+    compression_detection: bool,
+    price_table: Option<Arc<PriceTable>>,
+    budget: Budget,
+    abort_handle: Option<AbortHandle>,
+}
+
+impl Client {
+    /// Create a client with anonymous access. The `http_client` must be built with the surf
+    /// backend of your choice.
+    pub fn new<E: Into<String>, R: Into<String>>(
+        http_client: surf::Client,
+        endpoint: E,
+        region: R,
+    ) -> Self {
+        Client {
+            http_client,
+            endpoint: endpoint.into(),
+            region: region.into(),
+            credentials_provider: None,
+            timeouts: Timeouts::default(),
+            filter_continuations: false,
+            query_validation: false,
+            get_object_fallback: false,
+            compression_detection: true,
+            price_table: None,
+            budget: Budget::default(),
+            abort_handle: None,
+        }
+    }
+    /// Sign the requests with the credentials returned by the provider. The credentials are cached.
+    pub fn with_credentials_provider<P: ProvideAwsCredentials + Send + Sync + 'static>(
+        self,
+        provider: P,
+    ) -> Self {
+        self.with_cached_credentials_provider(Arc::new(CachedCredentialsProvider::new(provider)))
+    }
+    /// Share a credentials cache with other clients.
+    pub fn with_cached_credentials_provider(
+        mut self,
+        provider: Arc<CachedCredentialsProvider>,
+    ) -> Self {
+        self.credentials_provider = Some(provider);
+        self
+    }
+    /// Timeouts applied to every select.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+    /// Hide the `Cont` keep-alive events from the select streams.
+    pub fn with_filter_continuations(mut self, filter_continuations: bool) -> Self {
+        self.filter_continuations = filter_continuations;
+        self
+    }
+    /// Check the syntax of the SQL expressions before signing the requests. Disabled by default:
+    /// the parser only knows a subset of the functions of S3 Select, a valid expression using
+    /// another one is rejected.
+    pub fn with_query_validation(mut self, query_validation: bool) -> Self {
+        self.query_validation = query_validation;
+        self
+    }
+    /// Read the object with `GetObject` and evaluate the query locally when the server doesn't
+    /// implement the select, like some S3 compatible servers.
+    pub fn with_get_object_fallback(mut self, get_object_fallback: bool) -> Self {
+        self.get_object_fallback = get_object_fallback;
+        self
+    }
+    /// Set the compression type of the requests without one from the suffix of the key, `.gz`
+    /// or `.bz2`, or else from the `Content-Encoding` and `Content-Type` of the object. Enabled
+    /// by default.
+    ///
+    /// The object metadata is read with a `HeadObject` request before the select, unless the
+    /// key has the extension of an uncompressed object like `.csv`, `.json` or `.parquet`.
+    /// Disable it, or set the compression type of the requests, to save this round trip.
+    pub fn with_compression_detection(mut self, compression_detection: bool) -> Self {
+        self.compression_detection = compression_detection;
+        self
+    }
+    /// Estimate the cost of the selects with the price of the region of the client and of the
+    /// storage class of the objects.
+    ///
+    /// The storage class is read with a `HeadObject` request before the select, the one of the
+    /// compression detection when it's sent. The selects of [`Client::select_prefix`] take it
+    /// from the listing instead.
+    pub fn with_price_table(mut self, price_table: PriceTable) -> Self {
+        self.price_table = Some(Arc::new(price_table));
+        self
+    }
+    /// Limits applied to every select, see [`SelectStream::with_budget`] for the limits of one
+    /// select.
+    ///
+    /// The bytes and the cost are known before the end of the select from the `Progress` events,
+    /// they are requested for a budget with these limits. A request disabling them, or a cost
+    /// limit without [`Client::with_price_table`], is an invalid request.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+    /// Abort the selects of the client, the ones waiting for the response and the ones streaming
+    /// events, when the handle is aborted. Give a handle to a clone of the client to abort only
+    /// its selects. An aborted handle cancels the next selects too, give a new one to the client
+    /// to run them.
+    pub fn with_abort_handle(mut self, abort_handle: AbortHandle) -> Self {
+        self.abort_handle = Some(abort_handle);
+        self
+    }
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+    /// Run the select and return the stream of events.
+    ///
+    /// The events are decoded while the response body is received.
+    pub async fn select_object_content(
+        &self,
+        select_object_content_request: SelectObjectContentRequest,
+    ) -> Result<SelectStream> {
+        self.select_object_in_storage_class(select_object_content_request, None)
+            .await
+    }
+    /// Run the select of an object whose storage class is known, from a listing for example.
+    pub(crate) async fn select_object_in_storage_class(
+        &self,
+        select_object_content_request: SelectObjectContentRequest,
+        storage_class: Option<String>,
+    ) -> Result<SelectStream> {
+        let started_at = Instant::now();
+
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+
+            let mut telemetry =
+                Telemetry::new(&select_object_content_request, &self.region, started_at);
+            let span = telemetry.span().clone();
+            match self
+                .send_select(select_object_content_request, storage_class, started_at)
+                .instrument(span)
+                .await
+            {
+                Ok(stream) => Ok(stream.with_telemetry(telemetry)),
+                Err(error) => {
+                    telemetry.error(&error);
+                    Err(error)
+                }
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        self.send_select(select_object_content_request, storage_class, started_at)
+            .await
+    }
+    /// Send the request until the abort handle of the client is aborted.
+    async fn send_select(
+        &self,
+        select_object_content_request: SelectObjectContentRequest,
+        storage_class: Option<String>,
+        started_at: Instant,
+    ) -> Result<SelectStream> {
+        let select =
+            self.send_select_request(select_object_content_request, storage_class, started_at);
+        let abort_handle = match &self.abort_handle {
+            Some(abort_handle) => abort_handle,
+            None => return select.await,
+        };
+        futures::pin_mut!(select);
+        match futures::future::select(select, Box::pin(abort_handle.aborted())).await {
+            futures::future::Either::Left((result, _)) => result,
+            futures::future::Either::Right(_) => Err(Error::Cancelled(Box::new(SelectSummary {
+                elapsed: started_at.elapsed(),
+                ..Default::default()
+            }))),
+        }
+    }
+    /// Send the request and wrap the response body, or the local evaluation.
+    async fn send_select_request(
+        &self,
+        mut select_object_content_request: SelectObjectContentRequest,
+        storage_class: Option<String>,
+        started_at: Instant,
+    ) -> Result<SelectStream> {
+        // A bad customer key is an invalid request, not an error of the server.
+        crate::sse::prepare(&self.endpoint, &mut select_object_content_request)?;
+        self.request_progress(&mut select_object_content_request)?;
+        let mut head = None;
+        if self.compression_detection {
+            head = self
+                .detect_compression(&mut select_object_content_request)
+                .await?;
+        }
+        // The price depends on the storage class, it's in the metadata of the object.
+        let storage_class = match storage_class {
+            None if self.price_table.is_some() => {
+                let head = match head {
+                    Some(head) => head,
+                    None => {
+                        self.head_object(head_request(&select_object_content_request))
+                            .await?
+                    }
+                };
+                head.storage_class
+            }
+            storage_class => storage_class,
+        };
+
+        if self.query_validation && select_object_content_request.expression_type == "SQL" {
+            crate::query::validate(&select_object_content_request.expression, None)?;
+        }
+        crate::request::validate(&select_object_content_request)?;
+
+        let mut response = self
+            .send_signed(started_at, || {
+                crate::signed_select_object_content(
+                    self.endpoint.clone(),
+                    select_object_content_request.clone(),
+                    self.credentials(),
+                    self.region.clone(),
+                    None,
+                )
+            })
+            .await?;
+
+        if self.get_object_fallback && select_not_supported(&response) {
+            return self
+                .select_with_get_object(select_object_content_request, storage_class, started_at)
+                .await;
+        }
+        if !response.status().is_success() {
+            return Err(failure("Select", &mut response).await);
+        }
+
+        Ok(self.select_stream(
+            SelectStream::new(
+                EventStream::from_reader(response.take_body()),
+                self.timeouts,
+                started_at,
+            ),
+            &select_object_content_request,
+            storage_class.as_deref(),
+        ))
+    }
+    /// Credentials provider given to the signing functions.
+    fn credentials(&self) -> Option<&(dyn ProvideAwsCredentials + Send + Sync)> {
+        self.credentials_provider
+            .as_deref()
+            .map(|provider| provider as &(dyn ProvideAwsCredentials + Send + Sync))
+    }
+    /// Sign and send the request. If the server rejects the credentials, revoked or expired
+    /// before the cache refreshed them, the cached ones are dropped and the request is signed
+    /// with new credentials and sent once more.
+    async fn send_signed<F, Fut>(&self, started_at: Instant, sign: F) -> Result<surf::Response>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = surf::Result<RequestBuilder>>,
+    {
+        let send = |request_builder: RequestBuilder| {
+            with_deadline(
+                async move { Ok(self.http_client.send(request_builder.build()).await?) },
+                self.timeouts.connect_deadline(started_at),
+            )
+        };
+
+        let mut response = send(sign().await?).await?;
+        if let Some(provider) = &self.credentials_provider {
+            if credentials_rejected(&mut response).await? {
+                log::debug!("Credentials rejected by the server, sign the request with new ones.");
+                #[cfg(feature = "tracing")]
+                crate::telemetry::retry(&self.region, "rejected_credentials");
+                provider.clear().await;
+                response = send(sign().await?).await?;
+            }
+        }
+
+        Ok(response)
+    }
+    /// Apply the options of the client to the stream of the request.
+    fn select_stream(
+        &self,
+        stream: SelectStream,
+        request: &SelectObjectContentRequest,
+        storage_class: Option<&str>,
+    ) -> SelectStream {
+        let mut stream = stream
+            .filter_continuations(self.filter_continuations)
+            .with_output_serialization(&request.output_serialization)
+            .with_budget(self.budget);
+        if let Some(abort_handle) = &self.abort_handle {
+            stream = stream.with_abort_handle(abort_handle.clone());
+        }
+        match &self.price_table {
+            Some(price_table) => {
+                let storage_class = storage_class.unwrap_or(STORAGE_CLASS_STANDARD);
+                stream.with_price(price_table.price(&self.region, storage_class))
+            }
+            None => stream,
+        }
+    }
+    /// Request the `Progress` events checked by the budget.
+    fn request_progress(&self, request: &mut SelectObjectContentRequest) -> Result<()> {
+        let budget = &self.budget;
+        if budget.cost.is_some() && self.price_table.is_none() {
+            return Err(Error::InvalidRequest(
+                "The cost budget needs a price table".to_string(),
+            ));
+        }
+        if budget.bytes_scanned.is_none()
+            && budget.bytes_returned.is_none()
+            && budget.cost.is_none()
+        {
+            return Ok(());
+        }
+
+        match &request.request_progress {
+            Some(RequestProgress {
+                enabled: Some(false),
+            }) => Err(Error::InvalidRequest(
+                "The budget needs the progress events of the select".to_string(),
+            )),
+            _ => {
+                request.request_progress = Some(RequestProgress {
+                    enabled: Some(true),
+                });
+                Ok(())
+            }
+        }
+    }
+    /// Set the compression type of the request if it's missing, return the object metadata if
+    /// they were read.
+    async fn detect_compression(
+        &self,
+        request: &mut SelectObjectContentRequest,
+    ) -> Result<Option<HeadObjectOutput>> {
+        let input = &request.input_serialization;
+        if input.compression_type.is_some() || input.parquet.is_some() {
+            return Ok(None);
+        }
+
+        let mut head = None;
+        let compression_type = match compression_from_key(&request.key) {
+            Some(compression_type) => compression_type,
+            None if is_uncompressed_key(&request.key) => COMPRESSION_NONE,
+            None => {
+                let output = self.head_object(head_request(request)).await?;
+                let compression_type = compression_from_head(&output).unwrap_or(COMPRESSION_NONE);
+                head = Some(output);
+                compression_type
+            }
+        };
+        request.input_serialization.compression_type = Some(compression_type.to_string());
+        Ok(head)
+    }
+    /// Stream the object and evaluate the select locally, the events are the events of a select.
+    ///
+    /// With a scan range, the object is read from the start of the range until the end of its
+    /// last record.
+    async fn select_with_get_object(
+        &self,
+        request: SelectObjectContentRequest,
+        storage_class: Option<String>,
+        started_at: Instant,
+    ) -> Result<SelectStream> {
+        let position = scan_range_position(&request);
+        let mut response = self
+            .get_object(GetObjectRequest {
+                bucket: request.bucket.clone(),
+                key: request.key.clone(),
+                range: (position > 0).then(|| format!("bytes={}-", position)),
+                expected_bucket_owner: request.expected_bucket_owner.clone(),
+                sse_customer_algorithm: request.sse_customer_algorithm.clone(),
+                sse_customer_key: request.sse_customer_key.clone(),
+                sse_customer_key_md5: request.sse_customer_key_md5.clone(),
+                ..Default::default()
+            })
+            .await?;
+        let events = select_local_at(response.take_body(), &request, position)?;
+
+        Ok(self.select_stream(
+            SelectStream::from_events(events, self.timeouts, started_at),
+            &request,
+            storage_class.as_deref(),
+        ))
+    }
+    /// Run the select on every object found under the prefix, the bucket and the key of the
+    /// request template are replaced for each object.
+    pub fn select_prefix<B: Into<String>, P: Into<String>>(
+        &self,
+        bucket: B,
+        prefix: P,
+        request_template: SelectObjectContentRequest,
+    ) -> PrefixSelect {
+        PrefixSelect::new(self.clone(), bucket.into(), prefix.into(), request_template)
+    }
+    /// Select the records as Arrow record batches, converted as the events arrive.
+    #[cfg(feature = "arrow")]
+    pub async fn select_record_batches(
+        &self,
+        select_object_content_request: SelectObjectContentRequest,
+        record_batches: RecordBatches,
+    ) -> Result<BoxStream<'static, Result<arrow_array::RecordBatch>>> {
+        let output_serialization = select_object_content_request.output_serialization.clone();
+        let stream = self
+            .select_object_content(select_object_content_request)
+            .await?;
+        record_batches.stream(&output_serialization, stream)
+    }
+    /// Count the records selected by the request. The `FROM` and `WHERE` clauses of the
+    /// expression are kept.
+    pub async fn select_count(
+        &self,
+        select_object_content_request: SelectObjectContentRequest,
+    ) -> Result<u64> {
+        self.select_aggregate(select_object_content_request, count_all())
+            .await
+    }
+    /// Replace the projection of the request expression by the aggregation, run the select and
+    /// decode the single record returned.
+    ///
+    /// ```no_run
+    /// # async fn sum(client: surf_bucket_select::Client, request: rusoto_s3::SelectObjectContentRequest) -> surf_bucket_select::error::Result<()> {
+    /// use surf_bucket_select::query::{col, sum};
+    ///
+    /// let total: Option<f64> = client.select_aggregate(request, sum(col("filesize"))).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn select_aggregate<T: FromScalar>(
+        &self,
+        mut select_object_content_request: SelectObjectContentRequest,
+        aggregate: Expr,
+    ) -> Result<T> {
+        if !aggregate.is_aggregate() {
+            return Err(Error::InvalidRequest(format!(
+                "{} is not an aggregation",
+                aggregate
+            )));
+        }
+
+        let request = &mut select_object_content_request;
+        request.expression = aggregate_statement(&request.expression, aggregate)?.to_string();
+        request.expression_type = "SQL".to_string();
+        if request.output_serialization.csv.is_none() && request.output_serialization.json.is_none()
+        {
+            request.output_serialization.csv = Some(CSVOutput::default());
+        }
+        let output_serialization = request.output_serialization.clone();
+
+        let mut stream = self
+            .select_object_content(select_object_content_request)
+            .await?;
+        let mut records = Vec::new();
+        while let Some(item) = stream.try_next().await? {
+            if let SelectObjectContentEventStreamItem::Records(event) = item {
+                records.extend_from_slice(event.payload.as_deref().unwrap_or_default());
+            }
+        }
+
+        decode_scalar(&records, &output_serialization)
+    }
+    /// Read the object, or a part of it with the `range` field. The response is returned as soon
+    /// as the headers are received.
+    pub async fn get_object(
+        &self,
+        mut get_object_request: GetObjectRequest,
+    ) -> Result<surf::Response> {
+        let started_at = Instant::now();
+        crate::sse::prepare_customer_key(
+            &self.endpoint,
+            get_object_request.sse_customer_algorithm.as_deref(),
+            get_object_request.sse_customer_key.as_deref(),
+            &mut get_object_request.sse_customer_key_md5,
+        )?;
+
+        let mut response = self
+            .send_signed(started_at, || {
+                crate::signed_get_object(
+                    self.endpoint.clone(),
+                    get_object_request.clone(),
+                    self.credentials(),
+                    self.region.clone(),
+                    None,
+                )
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(failure("GetObject", &mut response).await);
+        }
+
+        Ok(response)
+    }
+    /// List one page of the objects of the bucket.
+    pub async fn list_objects_v2(
+        &self,
+        list_objects_v2_request: ListObjectsV2Request,
+    ) -> Result<ListObjectsV2Output> {
+        let started_at = Instant::now();
+
+        let mut response = self
+            .send_signed(started_at, || {
+                crate::signed_list_objects_v2(
+                    self.endpoint.clone(),
+                    list_objects_v2_request.clone(),
+                    self.credentials(),
+                    self.region.clone(),
+                    None,
+                )
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(failure("ListObjectsV2", &mut response).await);
+        }
+
+        deserialize_list_objects_v2(&response.body_bytes().await?)
+    }
+    /// List all the pages of the objects of the bucket, the continuation token of each page is
+    /// used to request the next one.
+    pub fn list_objects_v2_pages(
+        &self,
+        list_objects_v2_request: ListObjectsV2Request,
+    ) -> BoxStream<'static, Result<ListObjectsV2Output>> {
+        let client = self.clone();
+        stream::try_unfold(Some(list_objects_v2_request), move |request| {
+            let client = client.clone();
+            async move {
+                let mut request = match request {
+                    Some(request) => request,
+                    None => return Ok(None),
+                };
+                let output = client.list_objects_v2(request.clone()).await?;
+                let next_request = match &output.next_continuation_token {
+                    Some(token) if output.is_truncated.unwrap_or(false) => {
+                        request.continuation_token = Some(token.clone());
+                        Some(request)
+                    }
+                    _ => None,
+                };
+                Result::Ok(Some((output, next_request)))
+            }
+        })
+        .boxed()
+    }
+    /// Read the metadata of the object: size, ETag, encoding, last modification date...
+    pub async fn head_object(
+        &self,
+        mut head_object_request: HeadObjectRequest,
+    ) -> Result<HeadObjectOutput> {
+        let started_at = Instant::now();
+        crate::sse::prepare_customer_key(
+            &self.endpoint,
+            head_object_request.sse_customer_algorithm.as_deref(),
+            head_object_request.sse_customer_key.as_deref(),
+            &mut head_object_request.sse_customer_key_md5,
+        )?;
+
+        let mut response = self
+            .send_signed(started_at, || {
+                crate::signed_head_object(
+                    self.endpoint.clone(),
+                    head_object_request.clone(),
+                    self.credentials(),
+                    self.region.clone(),
+                    None,
+                )
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(failure("HeadObject", &mut response).await);
+        }
+
+        deserialize_head_object(&response)
+    }
+    /// Read the columns of the CSV object selected by the request.
+    ///
+    /// The first bytes of the object are read with a ranged GET and the first record is parsed
+    /// with the CSV settings of the request. The columns are named `_1.._N` if the request
+    /// doesn't use the header.
+    pub async fn infer_schema(
+        &self,
+        select_object_content_request: &SelectObjectContentRequest,
+    ) -> Result<Schema> {
+        let request = select_object_content_request;
+        let input = &request.input_serialization;
+        let csv = input.csv.as_ref().ok_or_else(|| {
+            Error::InvalidRequest("The schema can only be inferred from a CSV input".to_string())
+        })?;
+        if let Some(compression_type) = input.compression_type.as_deref() {
+            if !compression_type.eq_ignore_ascii_case("NONE") {
+                return Err(Error::InvalidRequest(format!(
+                    "The schema can't be inferred from a {} compressed object",
+                    compression_type
+                )));
+            }
+        }
+
+        let mut size = HEADER_RANGE_SIZE;
+        loop {
+            let mut response = match self
+                .get_object(GetObjectRequest {
+                    bucket: request.bucket.clone(),
+                    key: request.key.clone(),
+                    range: Some(format!("bytes=0-{}", size - 1)),
+                    sse_customer_algorithm: request.sse_customer_algorithm.clone(),
+                    sse_customer_key: request.sse_customer_key.clone(),
+                    sse_customer_key_md5: request.sse_customer_key_md5.clone(),
+                    ..Default::default()
+                })
+                .await
+            {
+                Ok(response) => response,
+                // The object is empty.
+                Err(Error::Http(e))
+                    if e.status() == surf::StatusCode::RequestedRangeNotSatisfiable =>
+                {
+                    return Ok(Schema::default())
+                }
+                Err(e) => return Err(e),
+            };
+            let partial = response.status() == surf::StatusCode::PartialContent;
+            let data = response.body_bytes().await?;
+            let complete = !partial || (data.len() as u64) < size;
+
+            if let Some(schema) = Schema::from_csv_input(&data, csv, complete) {
+                return Ok(schema);
+            }
+            if size >= MAX_HEADER_SIZE {
+                return Err(Error::InvalidRequest(format!(
+                    "No CSV header found in the first {} bytes of the object",
+                    size
+                )));
+            }
+            size *= 2;
+        }
+    }
+}
+
+/// Read the metadata of the object of the select.
+fn head_request(request: &SelectObjectContentRequest) -> HeadObjectRequest {
+    HeadObjectRequest {
+        bucket: request.bucket.clone(),
+        key: request.key.clone(),
+        expected_bucket_owner: request.expected_bucket_owner.clone(),
+        sse_customer_algorithm: request.sse_customer_algorithm.clone(),
+        sse_customer_key: request.sse_customer_key.clone(),
+        sse_customer_key_md5: request.sse_customer_key_md5.clone(),
+        ..Default::default()
+    }
+}
+
+/// Check if the server rejected the select because it doesn't implement it.
+fn select_not_supported(response: &surf::Response) -> bool {
+    matches!(
+        response.status(),
+        StatusCode::NotImplemented | StatusCode::MethodNotAllowed
+    )
+}
+
+/// Check if the server rejected the credentials of the request: access denied or an expired
+/// session token. The body of the other errors is kept for [`failure`].
+async fn credentials_rejected(response: &mut surf::Response) -> Result<bool> {
+    match response.status() {
+        StatusCode::Forbidden => Ok(true),
+        StatusCode::BadRequest => {
+            let body_bytes = response.body_bytes().await?;
+            let expired =
+                String::from_utf8_lossy(&body_bytes).contains("<Code>ExpiredToken</Code>");
+            response.set_body(body_bytes);
+            Ok(expired)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Error with the body of the failed response.
+async fn failure(operation: &str, response: &mut surf::Response) -> Error {
+    let body_bytes = match response.body_bytes().await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => return Error::Http(e),
+    };
+    Error::Http(surf::Error::from_str(
+        response.status(),
+        format!(
+            "{} failed with status code '{}' and response body: {}",
+            operation,
+            response.status(),
+            String::from_utf8_lossy(&body_bytes)
+        ),
+    ))
+}
+
+/// Server answering the selects, for the tests of the modules running selects.
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::Client;
+    use crate::model::event_stream::encode_event;
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+    use surf::http::{Request, Response, StatusCode};
+
+    /// Answer the selects with the records and keep the expressions received.
+    #[derive(Debug)]
+    struct SelectServer {
+        records: Vec<&'static [u8]>,
+        expressions: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl surf::HttpClient for SelectServer {
+        async fn send(&self, mut request: Request) -> surf::Result<Response> {
+            let body = request.body_string().await?;
+            let expression = body
+                .split("<Expression>")
+                .nth(1)
+                .and_then(|rest| rest.split("</Expression>").next())
+                .unwrap_or_default()
+                .replace("&gt;", ">")
+                .replace("&lt;", "<")
+                .replace("&apos;", "'")
+                .replace("&quot;", "\"")
+                .replace("&amp;", "&");
+            self.expressions.lock().unwrap().push(expression);
+
+            let mut events = Vec::new();
+            for records in &self.records {
+                events.extend(encode_event("Records", records));
+            }
+            events.extend(encode_event("End", b""));
+            let mut response = Response::new(StatusCode::Ok);
+            response.set_body(events);
+            Ok(response)
+        }
+    }
+
+    /// Client of a server answering each select with the records, and the expressions received.
+    pub(crate) fn select_client(records: Vec<&'static [u8]>) -> (Client, Arc<Mutex<Vec<String>>>) {
+        let expressions = Arc::new(Mutex::new(Vec::new()));
+        let http_client = surf::Client::with_http_client(SelectServer {
+            records,
+            expressions: expressions.clone(),
+        });
+        (
+            Client::new(http_client, "http://localhost:9000", "us-east-1"),
+            expressions,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::select_client;
+    use super::*;
+    use crate::cost::Price;
+    use crate::model::event_stream::encode_event;
+    use crate::query::{col, sum};
+    use async_trait::async_trait;
+    use rusoto_core::credential::{AwsCredentials, CredentialsError};
+    use rusoto_s3::{CSVInput, InputSerialization, JSONOutput, OutputSerialization, ScanRange};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use surf::http::{Method, Request, Response, StatusCode};
+
+    /// Serve one object and honor the `Range` header.
+    #[derive(Debug)]
+    struct ObjectServer {
+        object: Vec<u8>,
+        ranges: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl surf::HttpClient for ObjectServer {
+        async fn send(&self, request: Request) -> surf::Result<Response> {
+            let range = request.header("range").unwrap().as_str().to_string();
+            self.ranges.lock().unwrap().push(range.clone());
+
+            let end: usize = range.trim_start_matches("bytes=0-").parse().unwrap();
+            let mut response = Response::new(StatusCode::PartialContent);
+            response.set_body(&self.object[..self.object.len().min(end + 1)]);
+            Ok(response)
+        }
+    }
+
+    /// Server without select, serving the object with `GetObject` from the start of the range.
+    #[derive(Debug)]
+    struct NoSelectServer {
+        object: &'static [u8],
+    }
+
+    #[async_trait]
+    impl surf::HttpClient for NoSelectServer {
+        async fn send(&self, request: Request) -> surf::Result<Response> {
+            if request.method() == Method::Post {
+                return Ok(Response::new(StatusCode::NotImplemented));
+            }
+            let start: usize = request.header("range").map_or(0, |range| {
+                let range = range.as_str().trim_start_matches("bytes=");
+                range.trim_end_matches('-').parse().unwrap()
+            });
+            let mut response = Response::new(StatusCode::Ok);
+            response.set_body(&self.object[start..]);
+            Ok(response)
+        }
+    }
+
+    /// Server answering `HeadObject` with the content encoding and keeping the select bodies.
+    #[derive(Debug)]
+    struct EncodedObjectServer {
+        content_encoding: &'static str,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl surf::HttpClient for EncodedObjectServer {
+        async fn send(&self, mut request: Request) -> surf::Result<Response> {
+            let body = request.body_string().await?;
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", request.method(), body));
+
+            let mut response = Response::new(StatusCode::Ok);
+            if request.method() == Method::Head {
+                response.insert_header("content-encoding", self.content_encoding);
+            } else {
+                response.set_body(encode_event("End", b""));
+            }
+            Ok(response)
+        }
+    }
+
+    fn client(object: Vec<u8>) -> (Client, Arc<Mutex<Vec<String>>>) {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let http_client = surf::Client::with_http_client(ObjectServer {
+            object,
+            ranges: ranges.clone(),
+        });
+        (
+            Client::new(http_client, "http://localhost:9000", "us-east-1"),
+            ranges,
+        )
+    }
+
+    fn request(file_header_info: &str) -> SelectObjectContentRequest {
+        SelectObjectContentRequest {
+            bucket: "my-bucket".to_string(),
+            key: "data/multi_lines.csv".to_string(),
+            input_serialization: InputSerialization {
+                csv: Some(CSVInput {
+                    file_header_info: Some(file_header_info.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[async_std::test]
+    async fn infer_schema() {
+        let (client, ranges) = client(b"number,\"long-string\",special_char\n1,abc,@\n".to_vec());
+
+        let schema = client.infer_schema(&request("USE")).await.unwrap();
+
+        assert_eq!(&["number", "long-string", "special_char"], schema.columns());
+        assert_eq!(vec!["bytes=0-65535"], *ranges.lock().unwrap());
+    }
+
+    #[async_std::test]
+    async fn infer_schema_extend_range() {
+        let mut object = vec![b'a'; 100 * 1024];
+        object.extend_from_slice(b",number\n1,2\n");
+        let (client, ranges) = client(object);
+
+        let schema = client.infer_schema(&request("NONE")).await.unwrap();
+
+        assert_eq!(&["_1", "_2"], schema.columns());
+        assert_eq!(
+            vec!["bytes=0-65535", "bytes=0-131071"],
+            *ranges.lock().unwrap()
+        );
+    }
+
+    #[async_std::test]
+    async fn infer_schema_reject_json_input() {
+        let (client, _) = client(Vec::new());
+        let request = SelectObjectContentRequest::default();
+
+        assert!(matches!(
+            client.infer_schema(&request).await,
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+
+    /// Server which never answers.
+    #[derive(Debug)]
+    struct SilentServer;
+
+    #[async_trait]
+    impl surf::HttpClient for SilentServer {
+        async fn send(&self, _request: Request) -> surf::Result<Response> {
+            futures::future::pending().await
+        }
+    }
+
+    #[async_std::test]
+    async fn select_abort_before_response() {
+        let abort_handle = AbortHandle::new();
+        let client = Client::new(
+            surf::Client::with_http_client(SilentServer),
+            "http://localhost:9000",
+            "us-east-1",
+        )
+        .with_abort_handle(abort_handle.clone());
+
+        async_std::task::spawn(async move {
+            futures_timer::Delay::new(std::time::Duration::from_millis(20)).await;
+            abort_handle.abort();
+        });
+        let request = SelectObjectContentRequest {
+            expression: "SELECT * FROM S3Object".to_string(),
+            expression_type: "SQL".to_string(),
+            ..request("USE")
+        };
+
+        assert!(matches!(
+            client.select_object_content(request).await,
+            Err(Error::Cancelled(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn select_count() {
+        let (client, expressions) = select_client(vec![b"4", b"2\n"]);
+        let request = SelectObjectContentRequest {
+            expression: "select s.name from s3object s where s.size > 10".to_string(),
+            ..request("USE")
+        };
+
+        assert_eq!(42, client.select_count(request).await.unwrap());
+        assert_eq!(
+            vec!["SELECT COUNT(*) FROM S3Object s WHERE s.size > 10"],
+            *expressions.lock().unwrap()
+        );
+    }
+
+    #[async_std::test]
+    async fn select_aggregate_json() {
+        let (client, _) = select_client(vec![b"{\"_1\":1024.5}\n"]);
+        let request = SelectObjectContentRequest {
+            output_serialization: OutputSerialization {
+                json: Some(JSONOutput::default()),
+                ..Default::default()
+            },
+            ..request("USE")
+        };
+
+        let total: Option<f64> = client
+            .select_aggregate(request, sum(col("filesize")))
+            .await
+            .unwrap();
+        assert_eq!(Some(1024.5), total);
+    }
+
+    #[async_std::test]
+    async fn select_aggregate_reject_several_records() {
+        let (client, _) = select_client(vec![b"1\n2\n"]);
+
+        assert!(matches!(
+            client.select_count(request("USE")).await,
+            Err(Error::InvalidRecord(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn select_aggregate_reject_projection() {
+        let (client, _) = select_client(Vec::new());
+
+        assert!(matches!(
+            client
+                .select_aggregate::<u64>(request("USE"), col("filesize"))
+                .await,
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn select_scan_range_with_get_object() {
+        let http_client = surf::Client::with_http_client(NoSelectServer {
+            object: b"aa\nbb\ncc\n",
+        });
+        let client = Client::new(http_client, "http://localhost:9000", "us-east-1")
+            .with_get_object_fallback(true);
+        let mut select_request = request("NONE");
+        select_request.expression = "SELECT s._1 FROM S3Object s".to_string();
+        select_request.expression_type = "SQL".to_string();
+        select_request.output_serialization.csv = Some(CSVOutput::default());
+        select_request.scan_range = Some(ScanRange {
+            start: Some(3),
+            end: Some(4),
+        });
+
+        let events: Vec<_> = client
+            .select_object_content(select_request)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            &events[0],
+            SelectObjectContentEventStreamItem::Records(records)
+                if records.payload.as_deref() == Some(&b"bb\n"[..])
+        ));
+        match &events[1] {
+            SelectObjectContentEventStreamItem::Stats(stats) => {
+                assert_eq!(Some(7), stats.details.as_ref().unwrap().bytes_scanned)
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    #[async_std::test]
+    async fn select_with_get_object_fallback() {
+        let http_client = surf::Client::with_http_client(NoSelectServer {
+            object: b"name,number\na,10\nb,20\n",
+        });
+        let client = Client::new(http_client, "http://localhost:9000", "us-east-1");
+        let mut select_request = request("USE");
+        select_request.expression = "SELECT s.name FROM S3Object s WHERE s.number = 20".to_string();
+        select_request.expression_type = "SQL".to_string();
+        select_request.output_serialization.csv = Some(CSVOutput::default());
+
+        assert!(client
+            .select_object_content(select_request.clone())
+            .await
+            .is_err());
+
+        let events: Vec<_> = client
+            .with_get_object_fallback(true)
+            .select_object_content(select_request)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(3, events.len());
+        assert!(matches!(
+            &events[0],
+            SelectObjectContentEventStreamItem::Records(records)
+                if records.payload.as_deref() == Some(&b"b\n"[..])
+        ));
+        assert!(matches!(
+            &events[1],
+            SelectObjectContentEventStreamItem::Stats(_)
+        ));
+        assert!(matches!(
+            &events[2],
+            SelectObjectContentEventStreamItem::End(_)
+        ));
+    }
+
+    #[async_std::test]
+    async fn select_detect_compression() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let http_client = surf::Client::with_http_client(EncodedObjectServer {
+            content_encoding: "gzip",
+            requests: requests.clone(),
+        });
+        let client = Client::new(http_client, "http://localhost:9000", "us-east-1");
+        let mut select_request = request("USE");
+        select_request.key = "data/multi_lines".to_string();
+
+        client
+            .select_object_content(select_request)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(2, requests.len());
+            assert!(requests[0].starts_with("HEAD"));
+            assert!(requests[1].contains("<CompressionType>GZIP</CompressionType>"));
+        }
+
+        // No HeadObject for the extension of an uncompressed object.
+        client
+            .select_object_content(request("USE"))
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        assert_eq!(3, requests.len());
+        assert!(requests[2].contains("<CompressionType>NONE</CompressionType>"));
+    }
+
+    /// Server answering `HeadObject` with the storage class and the selects with statistics.
+    #[derive(Debug)]
+    struct StorageClassServer {
+        methods: Arc<Mutex<Vec<Method>>>,
+    }
+
+    #[async_trait]
+    impl surf::HttpClient for StorageClassServer {
+        async fn send(&self, request: Request) -> surf::Result<Response> {
+            self.methods.lock().unwrap().push(request.method());
+
+            let mut response = Response::new(StatusCode::Ok);
+            if request.method() == Method::Head {
+                response.insert_header("x-amz-storage-class", "STANDARD_IA");
+            } else {
+                let mut events = encode_event(
+                    "Stats",
+                    b"<Stats><BytesScanned>1073741824</BytesScanned>\
+                        <BytesReturned>0</BytesReturned></Stats>",
+                );
+                events.extend(encode_event("End", b""));
+                response.set_body(events);
+            }
+            Ok(response)
+        }
+    }
+
+    #[async_std::test]
+    async fn select_price_of_storage_class() {
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let http_client = surf::Client::with_http_client(StorageClassServer {
+            methods: methods.clone(),
+        });
+        let client =
+            Client::new(http_client, "http://localhost:9000", "us-east-1").with_price_table(
+                PriceTable::default().with_price("us-east-1", "STANDARD_IA", Price::new(0.01, 0.0)),
+            );
+
+        let mut stream = client.select_object_content(request("USE")).await.unwrap();
+        while stream.try_next().await.unwrap().is_some() {}
+
+        assert_eq!(Some(0.01), stream.estimated_cost());
+        assert_eq!(vec![Method::Head, Method::Post], *methods.lock().unwrap());
+    }
+
+    #[async_std::test]
+    async fn select_budget_request_progress() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let http_client = surf::Client::with_http_client(EncodedObjectServer {
+            content_encoding: "",
+            requests: requests.clone(),
+        });
+        let client = Client::new(http_client, "http://localhost:9000", "us-east-1")
+            .with_budget(Budget::default().with_bytes_scanned(1024));
+
+        client.select_object_content(request("USE")).await.unwrap();
+        assert!(requests.lock().unwrap()[0]
+            .contains("<RequestProgress><Enabled>true</Enabled></RequestProgress>"));
+
+        let mut select_request = request("USE");
+        select_request.request_progress = Some(RequestProgress {
+            enabled: Some(false),
+        });
+        assert!(matches!(
+            client.select_object_content(select_request).await,
+            Err(Error::InvalidRequest(_))
+        ));
+
+        // The cost isn't known without price.
+        let client = client.with_budget(Budget::default().with_cost(0.01));
+        assert!(matches!(
+            client.select_object_content(request("USE")).await,
+            Err(Error::InvalidRequest(_))
+        ));
+        assert_eq!(1, requests.lock().unwrap().len());
+    }
+
+    #[async_std::test]
+    async fn select_query_validation() {
+        let (client, expressions) = select_client(Vec::new());
+        let mut select_request = request("USE");
+        select_request.expression_type = "SQL".to_string();
+        select_request.expression = "SELECT NEW_FUNCTION(s._1) FROM S3Object s".to_string();
+
+        client
+            .select_object_content(select_request.clone())
+            .await
+            .unwrap();
+        assert_eq!(1, expressions.lock().unwrap().len());
+
+        assert!(matches!(
+            client
+                .with_query_validation(true)
+                .select_object_content(select_request)
+                .await,
+            Err(Error::InvalidQuery(_))
+        ));
+        assert_eq!(1, expressions.lock().unwrap().len());
+    }
+
+    #[async_std::test]
+    async fn select_reject_invalid_customer_key() {
+        let (client, expressions) = select_client(Vec::new());
+        let mut select_request = request("USE");
+        select_request.sse_customer_algorithm = Some("AES256".to_string());
+        select_request.sse_customer_key = Some("c2hvcnQ=".to_string());
+
+        assert!(matches!(
+            client.select_object_content(select_request).await,
+            Err(Error::InvalidRequest(message)) if message.contains("32 bytes")
+        ));
+        assert!(expressions.lock().unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn select_reject_scan_range_on_compressed_key() {
+        let (client, expressions) = select_client(Vec::new());
+        let mut select_request = request("USE");
+        select_request.key = "data/multi_lines.csv.bz2".to_string();
+        select_request.scan_range = Some(ScanRange {
+            start: Some(0),
+            end: Some(1024),
+        });
+
+        assert!(matches!(
+            client
+                .with_compression_detection(true)
+                .select_object_content(select_request)
+                .await,
+            Err(Error::InvalidRequest(message)) if message.contains("BZIP2")
+        ));
+        assert!(expressions.lock().unwrap().is_empty());
+    }
+
+    /// Server rejecting the selects signed with the first tokens and keeping the tokens received.
+    #[derive(Debug)]
+    struct RejectTokenServer {
+        status: StatusCode,
+        rejected: usize,
+        tokens: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl surf::HttpClient for RejectTokenServer {
+        async fn send(&self, request: Request) -> surf::Result<Response> {
+            let token = request
+                .header("x-amz-security-token")
+                .unwrap()
+                .as_str()
+                .to_string();
+            let mut tokens = self.tokens.lock().unwrap();
+            tokens.push(token);
+
+            if tokens.len() <= self.rejected {
+                let mut response = Response::new(self.status);
+                response.set_body("<Error><Code>ExpiredToken</Code></Error>");
+                return Ok(response);
+            }
+            let mut response = Response::new(StatusCode::Ok);
+            response.set_body(encode_event("End", b""));
+            Ok(response)
+        }
+    }
+
+    struct TokenProvider {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ProvideAwsCredentials for TokenProvider {
+        async fn credentials(&self) -> std::result::Result<AwsCredentials, CredentialsError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(AwsCredentials::new(
+                "access_key",
+                "secret_key",
+                Some(format!("token-{}", call)),
+                Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+            ))
+        }
+    }
+
+    fn reject_token_client(
+        status: StatusCode,
+        rejected: usize,
+    ) -> (Client, Arc<Mutex<Vec<String>>>) {
+        let tokens = Arc::new(Mutex::new(Vec::new()));
+        let http_client = surf::Client::with_http_client(RejectTokenServer {
+            status,
+            rejected,
+            tokens: tokens.clone(),
+        });
+        let client = Client::new(http_client, "http://localhost:9000", "us-east-1")
+            .with_credentials_provider(TokenProvider {
+                calls: AtomicUsize::new(0),
+            });
+        (client, tokens)
+    }
+
+    #[async_std::test]
+    async fn select_refresh_rejected_credentials() {
+        let (client, tokens) = reject_token_client(StatusCode::BadRequest, 1);
+
+        let events: Vec<_> = client
+            .select_object_content(request("USE"))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(1, events.len());
+        assert_eq!(vec!["token-0", "token-1"], *tokens.lock().unwrap());
+
+        // The refreshed credentials are cached for the next requests.
+        client.select_object_content(request("USE")).await.unwrap();
+        assert_eq!("token-1", tokens.lock().unwrap()[2]);
+    }
+
+    #[async_std::test]
+    async fn select_retry_rejected_credentials_once() {
+        let (client, tokens) = reject_token_client(StatusCode::Forbidden, 2);
+
+        assert!(matches!(
+            client.select_object_content(request("USE")).await,
+            Err(Error::Http(err)) if err.status() == StatusCode::Forbidden
+        ));
+        assert_eq!(vec!["token-0", "token-1"], *tokens.lock().unwrap());
+    }
+}
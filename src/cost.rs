@@ -0,0 +1,126 @@
+//! Estimate the cost of the selects from the bytes scanned and returned.
+//!
+//! S3 Select bills the data scanned and the data returned per GB, at a price depending on the
+//! region and the storage class of the object. The prices change, check them on the AWS pricing
+//! page and give yours to [`PriceTable::with_price`].
+//!
+//! ```
+//! use rusoto_s3::Stats;
+//! use surf_bucket_select::cost::{Price, PriceTable, STORAGE_CLASS_STANDARD};
+//!
+//! let prices = PriceTable::default().with_price(
+//!     "eu-west-3",
+//!     STORAGE_CLASS_STANDARD,
+//!     Price::new(0.00225, 0.0008),
+//! );
+//! let stats = Stats {
+//!     bytes_scanned: Some(10 * 1024 * 1024 * 1024),
+//!     bytes_processed: None,
+//!     bytes_returned: Some(1024 * 1024 * 1024),
+//! };
+//!
+//! let cost = prices.price("eu-west-3", STORAGE_CLASS_STANDARD).cost(&stats);
+//! assert!((cost - 0.0233).abs() < 1e-9);
+//! ```
+
+use std::collections::HashMap;
+
+use rusoto_s3::Stats;
+
+/// Storage class of the objects without `x-amz-storage-class`.
+pub const STORAGE_CLASS_STANDARD: &str = "STANDARD";
+
+const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// Price of a select, in dollars per GB.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Price {
+    pub scanned_per_gb: f64,
+    pub returned_per_gb: f64,
+}
+
+impl Price {
+    pub fn new(scanned_per_gb: f64, returned_per_gb: f64) -> Self {
+        Price {
+            scanned_per_gb,
+            returned_per_gb,
+        }
+    }
+    /// Cost of the bytes scanned and returned, the missing statistics cost nothing.
+    pub fn cost(&self, stats: &Stats) -> f64 {
+        let gb = |bytes: Option<i64>| bytes.unwrap_or_default().max(0) as f64 / GB;
+        gb(stats.bytes_scanned) * self.scanned_per_gb
+            + gb(stats.bytes_returned) * self.returned_per_gb
+    }
+}
+
+/// Prices per region and storage class.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriceTable {
+    default: Price,
+    prices: HashMap<(String, String), Price>,
+}
+
+/// The price of the S3 Standard storage class in `us-east-1` for every region and storage
+/// class: $0.002 per GB scanned and $0.0007 per GB returned.
+impl Default for PriceTable {
+    fn default() -> Self {
+        PriceTable::new(Price::new(0.002, 0.0007))
+    }
+}
+
+impl PriceTable {
+    /// Table with the price of all the regions and storage classes.
+    pub fn new(default: Price) -> Self {
+        PriceTable {
+            default,
+            prices: HashMap::new(),
+        }
+    }
+    /// Price of a region and a storage class, like `STANDARD` or `STANDARD_IA`.
+    pub fn with_price<R: Into<String>, S: Into<String>>(
+        mut self,
+        region: R,
+        storage_class: S,
+        price: Price,
+    ) -> Self {
+        self.prices
+            .insert((region.into(), storage_class.into().to_uppercase()), price);
+        self
+    }
+    /// The price of the region and the storage class, the default one if it's not in the table.
+    pub fn price(&self, region: &str, storage_class: &str) -> Price {
+        self.prices
+            .get(&(region.to_string(), storage_class.to_uppercase()))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_per_region_and_storage_class() {
+        let standard_ia = Price::new(0.002, 0.01);
+        let prices = PriceTable::default().with_price("us-east-1", "standard_ia", standard_ia);
+
+        assert_eq!(standard_ia, prices.price("us-east-1", "STANDARD_IA"));
+        assert_eq!(
+            Price::new(0.002, 0.0007),
+            prices.price("us-east-1", STORAGE_CLASS_STANDARD)
+        );
+        assert_eq!(
+            Price::new(0.002, 0.0007),
+            prices.price("eu-west-1", "STANDARD_IA")
+        );
+
+        let stats = Stats {
+            bytes_scanned: Some(512 * 1024 * 1024),
+            bytes_processed: Some(512 * 1024 * 1024),
+            bytes_returned: None,
+        };
+        assert_eq!(0.001, prices.price("eu-west-1", "STANDARD").cost(&stats));
+    }
+}
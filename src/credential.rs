@@ -0,0 +1,243 @@
+//! Credentials fetching and caching shared by the select requests.
+
+use std::time::Duration;
+
+use async_std::sync::Mutex;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusoto_core::credential::{AwsCredentials, CredentialsError, ProvideAwsCredentials};
+
+/// Refresh the cached credentials this long before they expire.
+const DEFAULT_REFRESH_AHEAD: Duration = Duration::from_secs(5 * 60);
+/// The credentials expiring within this margin are expired for [`fetch_credentials`].
+const EXPIRY_MARGIN: Duration = Duration::from_secs(20);
+
+/// Credentials provider that keeps the last credentials in memory.
+///
+/// IMDS or STS providers do a network round-trip for every `credentials()` call. This provider
+/// asks the inner provider only when the cached credentials are about to expire. Wrap it in an
+/// `Arc` to share the cache between several clients or tasks: concurrent callers wait for a
+/// single refresh instead of calling the inner provider each.
+pub struct CachedCredentialsProvider {
+    provider: Box<dyn ProvideAwsCredentials + Send + Sync>,
+    cache: Mutex<Option<AwsCredentials>>,
+    refresh_ahead: Duration,
+    timeout: Option<Duration>,
+}
+
+impl CachedCredentialsProvider {
+    pub fn new<P: ProvideAwsCredentials + Send + Sync + 'static>(provider: P) -> Self {
+        CachedCredentialsProvider {
+            provider: Box::new(provider),
+            cache: Mutex::new(None),
+            refresh_ahead: DEFAULT_REFRESH_AHEAD,
+            timeout: None,
+        }
+    }
+    /// Refresh the credentials when they expire in less than `refresh_ahead`, at least 20
+    /// seconds: the credentials expiring sooner are refreshed before each request anyway.
+    pub fn with_refresh_ahead(mut self, refresh_ahead: Duration) -> Self {
+        self.refresh_ahead = refresh_ahead.max(EXPIRY_MARGIN);
+        self
+    }
+    /// Maximum duration to wait for the inner provider.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Drop the cached credentials. The next call asks the inner provider.
+    pub async fn clear(&self) {
+        *self.cache.lock().await = None;
+    }
+    fn is_fresh(&self, credentials: &AwsCredentials) -> bool {
+        match credentials.expires_at() {
+            Some(expires_at) => !expires_before(
+                expires_at,
+                chrono::Duration::from_std(self.refresh_ahead)
+                    .unwrap_or_else(|_| chrono::Duration::zero()),
+            ),
+            None => true,
+        }
+    }
+}
+
+#[async_trait]
+impl ProvideAwsCredentials for CachedCredentialsProvider {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        // The lock is kept during the refresh so only one task calls the inner provider.
+        let mut cache = self.cache.lock().await;
+
+        if let Some(credentials) = cache.as_ref() {
+            if self.is_fresh(credentials) {
+                return Ok(credentials.clone());
+            }
+        }
+
+        log::debug!("Refresh the cached credentials.");
+        // The region of the request isn't known here, the retry isn't counted in the metrics.
+        let credentials = fetch_credentials(self.provider.as_ref(), self.timeout, None).await?;
+        *cache = Some(credentials.clone());
+
+        Ok(credentials)
+    }
+}
+
+/// Fetch credentials from the provider.
+///
+/// Temporary credentials can expire while a long select is running. If the provider hands back
+/// credentials that are already expired (a stale cache for example), they are requested once more
+/// before giving up, so that a retried select is signed with fresh credentials. The retry is
+/// counted in the metrics of the `region` with the `tracing` feature.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) async fn fetch_credentials(
+    provider: &(dyn ProvideAwsCredentials + Send + Sync),
+    timeout: Option<Duration>,
+    region: Option<&str>,
+) -> Result<AwsCredentials, CredentialsError> {
+    let mut credentials = request_credentials(provider, timeout).await?;
+
+    if credentials_are_expired(&credentials) {
+        log::debug!("Credentials expired, request new ones to the provider.");
+        #[cfg(feature = "tracing")]
+        if let Some(region) = region {
+            crate::telemetry::retry(region, "expired_credentials");
+        }
+        credentials = request_credentials(provider, timeout).await?;
+
+        if credentials_are_expired(&credentials) {
+            return Err(CredentialsError {
+                message: "The credentials provider returned expired credentials".to_owned(),
+            });
+        }
+    }
+
+    Ok(credentials)
+}
+
+async fn request_credentials(
+    provider: &(dyn ProvideAwsCredentials + Send + Sync),
+    timeout: Option<Duration>,
+) -> Result<AwsCredentials, CredentialsError> {
+    if let Some(to) = timeout {
+        async_std::future::timeout(to, provider.credentials())
+            .await
+            .map_err(|_| CredentialsError {
+                message: "Timeout getting credentials".to_owned(),
+            })
+            .and_then(std::convert::identity)
+    } else {
+        provider.credentials().await
+    }
+    .map_err(|err| CredentialsError {
+        message: format!("Couldn't connect to credentials provider: {}", err),
+    })
+}
+
+/// Check if the credentials expire before the request can reach the server.
+fn credentials_are_expired(credentials: &AwsCredentials) -> bool {
+    match credentials.expires_at() {
+        Some(expires_at) => expires_before(
+            expires_at,
+            chrono::Duration::from_std(EXPIRY_MARGIN).unwrap_or_else(|_| chrono::Duration::zero()),
+        ),
+        None => false,
+    }
+}
+
+fn expires_before(expires_at: &DateTime<Utc>, delay: chrono::Duration) -> bool {
+    *expires_at < Utc::now() + delay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct CountingProvider {
+        calls: Arc<AtomicUsize>,
+        lifetime: chrono::Duration,
+    }
+
+    #[async_trait]
+    impl ProvideAwsCredentials for CountingProvider {
+        async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            async_std::task::sleep(Duration::from_millis(10)).await;
+            Ok(AwsCredentials::new(
+                "access_key",
+                "secret_key",
+                Some(format!("token-{}", call)),
+                Some(Utc::now() + self.lifetime),
+            ))
+        }
+    }
+
+    fn provider(lifetime: chrono::Duration) -> (CachedCredentialsProvider, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider = CachedCredentialsProvider::new(CountingProvider {
+            calls: calls.clone(),
+            lifetime,
+        });
+        (provider, calls)
+    }
+
+    #[async_std::test]
+    async fn credentials_are_reused() {
+        let (provider, calls) = provider(chrono::Duration::hours(1));
+
+        let first = provider.credentials().await.unwrap();
+        let second = provider.credentials().await.unwrap();
+
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        assert_eq!(first.token(), second.token());
+    }
+
+    #[async_std::test]
+    async fn credentials_are_refreshed_ahead_of_expiry() {
+        let (provider, calls) = provider(chrono::Duration::minutes(2));
+
+        provider.credentials().await.unwrap();
+        let second = provider.credentials().await.unwrap();
+
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert_eq!(&Some("token-1".to_string()), second.token());
+    }
+
+    #[test]
+    fn refresh_ahead_is_at_least_the_expiry_margin() {
+        let (provider, _) = provider(chrono::Duration::hours(1));
+        let provider = provider.with_refresh_ahead(Duration::from_secs(1));
+
+        assert_eq!(EXPIRY_MARGIN, provider.refresh_ahead);
+    }
+
+    #[async_std::test]
+    async fn credentials_are_refreshed_once_under_concurrency() {
+        let (provider, calls) = provider(chrono::Duration::hours(1));
+        let provider = Arc::new(provider);
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let provider = provider.clone();
+                async_std::task::spawn(async move { provider.credentials().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[async_std::test]
+    async fn clear() {
+        let (provider, calls) = provider(chrono::Duration::hours(1));
+
+        provider.credentials().await.unwrap();
+        provider.clear().await;
+        provider.credentials().await.unwrap();
+
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,538 @@
+//! Query an object with DataFusion. The projection, the filters and the limit of the scans are
+//! done by S3 Select, the rest of the plan is evaluated locally.
+//!
+//! ```no_run
+//! # async fn query(client: surf_bucket_select::Client, request: rusoto_s3::SelectObjectContentRequest) -> Result<(), Box<dyn std::error::Error>> {
+//! use std::sync::Arc;
+//! use datafusion::functions_aggregate::expr_fn::sum;
+//! use datafusion::prelude::{col, lit, SessionContext};
+//! use surf_bucket_select::datafusion::S3SelectTable;
+//!
+//! let table = S3SelectTable::try_new(client, request).await?;
+//! let context = SessionContext::new();
+//! let batches = context
+//!     .read_table(Arc::new(table))?
+//!     .filter(col("size").gt(lit(10)))?
+//!     .aggregate(vec![col("name")], vec![sum(col("size"))])?
+//!     .collect()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::any::Any;
+use std::convert::TryFrom;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use arrow_array::{RecordBatch, RecordBatchOptions};
+use arrow_schema::{DataType, Schema, SchemaRef};
+use async_trait::async_trait;
+use datafusion::catalog::{Session, TableProvider};
+use datafusion::common::{DataFusionError, ScalarValue};
+use datafusion::execution::TaskContext;
+use datafusion::logical_expr::{
+    Expr as LogicalExpr, Operator, TableProviderFilterPushDown, TableType,
+};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::{ExecutionPlan, SendableRecordBatchStream};
+use futures::stream::{self, TryStreamExt};
+use rusoto_s3::{JSONOutput, OutputSerialization, SelectObjectContentRequest};
+
+use crate::arrow::{check_schema, infer_schema, Record, RecordBatches};
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+use crate::query::ast::{self, BinaryOperator, Literal, UnaryOperator};
+use crate::query::{col, lit, parse, Select};
+
+/// Records read to infer the schema of a table.
+pub const DEFAULT_SAMPLE_SIZE: u64 = 100;
+
+/// Table of the records selected from an object.
+///
+/// The `FROM` and `WHERE` clauses of the expression of the request are kept, the projection
+/// and the limit are replaced by the ones of each scan. The records are returned in JSON.
+#[derive(Clone)]
+pub struct S3SelectTable {
+    client: Client,
+    request: SelectObjectContentRequest,
+    schema: SchemaRef,
+}
+
+impl Debug for S3SelectTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3SelectTable")
+            .field("bucket", &self.request.bucket)
+            .field("key", &self.request.key)
+            .field("schema", &self.schema)
+            .finish()
+    }
+}
+
+impl S3SelectTable {
+    /// Table with the given schema. The columns are the fields of the JSON records, or the CSV
+    /// columns named by the header or `_1.._N`. They are `Boolean`, `Int64`, `Float64` or `Utf8`.
+    pub fn new(
+        client: Client,
+        request: SelectObjectContentRequest,
+        schema: SchemaRef,
+    ) -> Result<Self> {
+        check_schema(&schema)?;
+        statement(&request.expression)?;
+        Ok(S3SelectTable {
+            client,
+            request,
+            schema,
+        })
+    }
+    /// Table with the schema inferred from the first [`DEFAULT_SAMPLE_SIZE`] records.
+    pub async fn try_new(client: Client, request: SelectObjectContentRequest) -> Result<Self> {
+        S3SelectTable::with_sample_size(client, request, DEFAULT_SAMPLE_SIZE).await
+    }
+    /// Table with the schema inferred from the first `sample_size` records. The CSV fields are
+    /// typed from their text as in [`crate::arrow`].
+    pub async fn with_sample_size(
+        client: Client,
+        request: SelectObjectContentRequest,
+        sample_size: u64,
+    ) -> Result<Self> {
+        let sample = Select::from(statement(&request.expression)?)
+            .clear_columns()
+            .limit(sample_size.max(1))
+            .build();
+        let mut events = client
+            .select_object_content(json_request(&request, sample.to_string()))
+            .await?;
+        let mut payload = Vec::new();
+        while let Some(event) = events.try_next().await? {
+            if let SelectObjectContentEventStreamItem::Records(records) = event {
+                payload.extend_from_slice(records.payload.as_deref().unwrap_or_default());
+            }
+        }
+
+        let records = payload
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .map(serde_json::from_slice)
+            .collect::<serde_json::Result<Vec<Record>>>()
+            .map_err(|e| Error::InvalidRecord(e.to_string()))?;
+        if records.is_empty() {
+            return Err(Error::InvalidRequest(
+                "No record selected to infer the schema of the table".to_string(),
+            ));
+        }
+        let schema = infer_schema(&records, request.input_serialization.csv.is_some());
+
+        S3SelectTable::new(client, request, Arc::new(schema))
+    }
+    /// Expression of a scan. The filters which can't be converted are ignored.
+    pub(crate) fn expression(
+        &self,
+        projection: &Schema,
+        filters: &[LogicalExpr],
+        limit: Option<usize>,
+    ) -> Result<String> {
+        let mut select = Select::from(statement(&self.request.expression)?).clear_columns();
+        select = match projection.fields().is_empty() {
+            // The records are counted, any column gives one record per row.
+            true => select.column(col(self.schema.field(0).name())),
+            false => select.columns(projection.fields().iter().map(|field| field.name())),
+        };
+        for filter in filters {
+            if let Some(filter) = self.filter(filter) {
+                select = select.filter(filter);
+            }
+        }
+        // The limit of the request is replaced, it would apply before the local filters.
+        let mut statement = select.build();
+        statement.limit = limit.map(|limit| limit as u64);
+        Ok(statement.to_string())
+    }
+    /// Convert a filter to S3 Select SQL, `None` if it can't be done by S3 with the same result.
+    fn filter(&self, expr: &LogicalExpr) -> Option<ast::Expr> {
+        let boxed = |expr: &LogicalExpr| self.filter(expr).map(Box::new);
+
+        Some(match expr {
+            LogicalExpr::Column(column) => {
+                let field = self.schema.field_with_name(&column.name).ok()?;
+                let expr = col(field.name());
+                let data_type = match field.data_type() {
+                    DataType::Int64 => ast::DataType::Int,
+                    DataType::Float64 => ast::DataType::Float,
+                    DataType::Boolean => ast::DataType::Bool,
+                    _ => return Some(expr),
+                };
+                if self.request.input_serialization.csv.is_none() {
+                    return Some(expr);
+                }
+                // The CSV fields are text and an empty field is a null value.
+                ast::Expr::Cast {
+                    expr: Box::new(ast::Expr::Function {
+                        name: "NULLIF".to_string(),
+                        args: vec![expr, lit("")],
+                    }),
+                    data_type,
+                }
+            }
+            LogicalExpr::Literal(value, _) => ast::Expr::Literal(literal(value)?),
+            LogicalExpr::BinaryExpr(binary) => {
+                let op = match binary.op {
+                    Operator::Eq => BinaryOperator::Eq,
+                    Operator::NotEq => BinaryOperator::NotEq,
+                    Operator::Lt => BinaryOperator::Lt,
+                    Operator::LtEq => BinaryOperator::LtEq,
+                    Operator::Gt => BinaryOperator::Gt,
+                    Operator::GtEq => BinaryOperator::GtEq,
+                    Operator::And => BinaryOperator::And,
+                    Operator::Or => BinaryOperator::Or,
+                    _ => return None,
+                };
+                ast::Expr::Binary {
+                    left: boxed(&binary.left)?,
+                    op,
+                    right: boxed(&binary.right)?,
+                }
+            }
+            LogicalExpr::Not(expr) => ast::Expr::Unary {
+                op: UnaryOperator::Not,
+                expr: boxed(expr)?,
+            },
+            LogicalExpr::IsNull(expr) => ast::Expr::IsNull {
+                expr: boxed(expr)?,
+                negated: false,
+            },
+            LogicalExpr::IsNotNull(expr) => ast::Expr::IsNull {
+                expr: boxed(expr)?,
+                negated: true,
+            },
+            LogicalExpr::Like(like) if !like.case_insensitive => ast::Expr::Like {
+                expr: boxed(&like.expr)?,
+                pattern: boxed(&like.pattern)?,
+                escape: like
+                    .escape_char
+                    .map(|escape| Box::new(lit(escape.to_string()))),
+                negated: like.negated,
+            },
+            LogicalExpr::Between(between) => ast::Expr::Between {
+                expr: boxed(&between.expr)?,
+                low: boxed(&between.low)?,
+                high: boxed(&between.high)?,
+                negated: between.negated,
+            },
+            LogicalExpr::InList(in_list) => ast::Expr::InList {
+                expr: boxed(&in_list.expr)?,
+                list: in_list
+                    .list
+                    .iter()
+                    .map(|expr| self.filter(expr))
+                    .collect::<Option<_>>()?,
+                negated: in_list.negated,
+            },
+            _ => return None,
+        })
+    }
+}
+
+#[async_trait]
+impl TableProvider for S3SelectTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[LogicalExpr],
+        limit: Option<usize>,
+    ) -> datafusion::common::Result<Arc<dyn ExecutionPlan>> {
+        let schema = match projection {
+            Some(projection) => Arc::new(self.schema.project(projection)?),
+            None => self.schema.clone(),
+        };
+        let expression = self.expression(&schema, filters, limit).map_err(external)?;
+        let partition = SelectPartition {
+            client: self.client.clone(),
+            request: json_request(&self.request, expression),
+            schema: schema.clone(),
+            selected: match schema.fields().is_empty() {
+                true => Arc::new(self.schema.project(&[0])?),
+                false => schema.clone(),
+            },
+        };
+
+        Ok(Arc::new(StreamingTableExec::try_new(
+            schema,
+            vec![Arc::new(partition)],
+            None,
+            Vec::new(),
+            false,
+            limit,
+        )?))
+    }
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&LogicalExpr],
+    ) -> datafusion::common::Result<Vec<TableProviderFilterPushDown>> {
+        Ok(filters
+            .iter()
+            .map(|filter| match self.filter(filter) {
+                Some(_) => TableProviderFilterPushDown::Exact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+}
+
+/// The single partition of a scan: the records of one select.
+struct SelectPartition {
+    client: Client,
+    request: SelectObjectContentRequest,
+    schema: SchemaRef,
+    /// Columns of the records, the first one of the table if no column is projected.
+    selected: SchemaRef,
+}
+
+impl Debug for SelectPartition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SelectPartition")
+            .field("expression", &self.request.expression)
+            .finish()
+    }
+}
+
+impl PartitionStream for SelectPartition {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+    fn execute(&self, context: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let client = self.client.clone();
+        let request = self.request.clone();
+        let schema = self.schema.clone();
+        let record_batches = RecordBatches::new()
+            .with_batch_size(context.session_config().batch_size())
+            .with_schema(self.selected.clone());
+        let batches =
+            stream::once(
+                async move { client.select_record_batches(request, record_batches).await },
+            )
+            .try_flatten()
+            .and_then({
+                let schema = schema.clone();
+                move |batch| {
+                    let batch = match schema.fields().is_empty() {
+                        true => RecordBatch::try_new_with_options(
+                            schema.clone(),
+                            Vec::new(),
+                            &RecordBatchOptions::new().with_row_count(Some(batch.num_rows())),
+                        )
+                        .map_err(|e| Error::InvalidRecord(e.to_string())),
+                        false => Ok(batch),
+                    };
+                    futures::future::ready(batch)
+                }
+            })
+            .map_err(external);
+
+        Box::pin(RecordBatchStreamAdapter::new(schema, batches))
+    }
+}
+
+/// The expression of the request parsed, `SELECT * FROM S3Object s` if it's empty.
+fn statement(expression: &str) -> Result<ast::SelectStatement> {
+    Ok(match expression.trim().is_empty() {
+        true => Select::from_object().build(),
+        false => parse(expression)?,
+    })
+}
+
+/// The request with the expression and JSON records.
+fn json_request(
+    request: &SelectObjectContentRequest,
+    expression: String,
+) -> SelectObjectContentRequest {
+    SelectObjectContentRequest {
+        expression,
+        expression_type: "SQL".to_string(),
+        output_serialization: OutputSerialization {
+            csv: None,
+            json: Some(JSONOutput {
+                record_delimiter: Some("\n".to_string()),
+            }),
+        },
+        ..request.clone()
+    }
+}
+
+fn literal(value: &ScalarValue) -> Option<Literal> {
+    if value.is_null() {
+        return Some(Literal::Null);
+    }
+    Some(match value {
+        ScalarValue::Boolean(Some(value)) => Literal::Bool(*value),
+        ScalarValue::Int8(Some(value)) => Literal::Int((*value).into()),
+        ScalarValue::Int16(Some(value)) => Literal::Int((*value).into()),
+        ScalarValue::Int32(Some(value)) => Literal::Int((*value).into()),
+        ScalarValue::Int64(Some(value)) => Literal::Int(*value),
+        ScalarValue::UInt8(Some(value)) => Literal::Int((*value).into()),
+        ScalarValue::UInt16(Some(value)) => Literal::Int((*value).into()),
+        ScalarValue::UInt32(Some(value)) => Literal::Int((*value).into()),
+        ScalarValue::UInt64(Some(value)) => Literal::Int(i64::try_from(*value).ok()?),
+        ScalarValue::Float32(Some(value)) if value.is_finite() => Literal::Float((*value).into()),
+        ScalarValue::Float64(Some(value)) if value.is_finite() => Literal::Float(*value),
+        ScalarValue::Utf8(Some(value))
+        | ScalarValue::LargeUtf8(Some(value))
+        | ScalarValue::Utf8View(Some(value)) => Literal::String(value.clone()),
+        _ => return None,
+    })
+}
+
+fn external(error: Error) -> DataFusionError {
+    DataFusionError::External(Box::new(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::select_client;
+    use arrow_array::StringArray;
+    use datafusion::prelude::{col as column, lit as value, SessionContext};
+    use rusoto_s3::{CSVInput, InputSerialization};
+
+    fn request() -> SelectObjectContentRequest {
+        SelectObjectContentRequest {
+            bucket: "my-bucket".to_string(),
+            key: "files.csv".to_string(),
+            input_serialization: InputSerialization {
+                csv: Some(CSVInput {
+                    file_header_info: Some("USE".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            arrow_schema::Field::new("name", DataType::Utf8, true),
+            arrow_schema::Field::new("size", DataType::Int64, true),
+        ]))
+    }
+
+    #[tokio::test]
+    async fn push_down_scan() {
+        let (client, expressions) =
+            select_client(vec![b"{\"name\":\"abc\"}\n{\"name\":\"ade\"}\n"]);
+        let table = S3SelectTable::new(client, request(), schema()).unwrap();
+        let context = SessionContext::new();
+
+        let batches = context
+            .read_table(Arc::new(table))
+            .unwrap()
+            .filter(
+                column("size")
+                    .gt(value(10i64))
+                    .and(column("name").like(value("a%"))),
+            )
+            .unwrap()
+            .select_columns(&["name"])
+            .unwrap()
+            .limit(0, Some(5))
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+
+        let names = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(
+            vec![Some("abc"), Some("ade")],
+            names.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                "SELECT s.name FROM S3Object s WHERE CAST(NULLIF(s.size, '') AS INT) > 10 \
+                 AND s.name LIKE 'a%' LIMIT 5"
+            ],
+            *expressions.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_locally() {
+        let (client, expressions) = select_client(vec![b"{\"name\":\"a\"}\n{\"name\":\"b\"}\n"]);
+        let table = S3SelectTable::new(client, request(), schema()).unwrap();
+        let context = SessionContext::new();
+        let files = context.read_table(Arc::new(table)).unwrap();
+
+        let count = files
+            .clone()
+            .filter(column("name").ilike(value("B")))
+            .unwrap()
+            .count()
+            .await
+            .unwrap();
+        assert_eq!(1, count);
+
+        // Without column, the first one is selected to count the records.
+        assert_eq!(2, files.count().await.unwrap());
+        assert_eq!(
+            vec!["SELECT s.name FROM S3Object s"; 2],
+            *expressions.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn infer_table_schema() {
+        let (client, expressions) = select_client(vec![
+            b"{\"name\":\"a\",\"size\":\"1\"}\n{\"name\":\"b\",\"size\":\"\"}\n",
+        ]);
+        let request = SelectObjectContentRequest {
+            expression: "select s.name from s3object s where s.size > 10".to_string(),
+            ..request()
+        };
+
+        let table = S3SelectTable::try_new(client, request).await.unwrap();
+
+        assert_eq!(schema(), table.schema());
+        assert_eq!(
+            vec!["SELECT * FROM S3Object s WHERE s.size > 10 LIMIT 100"],
+            *expressions.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_filters() {
+        let (client, _) = select_client(Vec::new());
+        let table = S3SelectTable::new(client, request(), schema()).unwrap();
+        let filter = |expr: LogicalExpr| table.filter(&expr).map(|expr| expr.to_string());
+
+        assert_eq!(
+            Some("name IN ('a', 'b') OR name IS NULL".to_string()),
+            filter(
+                column("name")
+                    .in_list(vec![value("a"), value("b")], false)
+                    .or(column("name").is_null())
+            )
+        );
+        assert_eq!(
+            Some("NOT CAST(NULLIF(size, '') AS INT) BETWEEN 1 AND 10".to_string()),
+            filter(!column("size").between(value(1), value(10)))
+        );
+        assert_eq!(None, filter(column("size") + value(1)));
+        assert_eq!(None, filter(column("name").ilike(value("a%"))));
+        assert_eq!(None, filter(column("unknown").eq(value(1))));
+    }
+}
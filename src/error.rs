@@ -0,0 +1,95 @@
+//! Errors returned by the client.
+
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use rusoto_core::RusotoError;
+
+use crate::query::QueryError;
+use crate::select::{Limit, SelectSummary};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request is rejected before being signed.
+    InvalidRequest(String),
+    /// The expression is not valid S3 Select SQL.
+    InvalidQuery(QueryError),
+    /// The http request failed or the server returned an error status.
+    Http(surf::Error),
+    /// The event stream is malformed or can't be read.
+    Stream(Box<RusotoError<()>>),
+    /// The response headers are not received in time.
+    ConnectTimeout(Duration),
+    /// The server doesn't send the first event in time.
+    FirstEventTimeout(Duration),
+    /// No event, not even a keep-alive, is received in time.
+    IdleTimeout(Duration),
+    /// The select takes more time than allowed.
+    TotalTimeout(Duration),
+    /// The records returned by the select can't be decoded.
+    InvalidRecord(String),
+    /// The response of the server can't be read.
+    InvalidResponse(String),
+    /// The records can't be written to the output of a sink.
+    Output(std::io::Error),
+    /// The select exceeded a limit of its budget and was stopped, with what it returned.
+    BudgetExceeded(Limit, Box<SelectSummary>),
+    /// The select was aborted with its `AbortHandle`, with what it returned.
+    Cancelled(Box<SelectSummary>),
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+            Error::InvalidQuery(err) => write!(f, "Invalid query: {}", err),
+            Error::Http(err) => write!(f, "{}", err),
+            Error::Stream(err) => match err.as_ref() {
+                RusotoError::ParseError(msg) => write!(f, "{}", msg),
+                RusotoError::HttpDispatch(err) => write!(f, "{}", err),
+                err => write!(f, "{:?}", err),
+            },
+            Error::ConnectTimeout(timeout) => {
+                write!(f, "Timeout waiting for the response after {:?}", timeout)
+            }
+            Error::FirstEventTimeout(timeout) => {
+                write!(f, "Timeout waiting for the first event after {:?}", timeout)
+            }
+            Error::IdleTimeout(timeout) => {
+                write!(f, "No event received during {:?}", timeout)
+            }
+            Error::TotalTimeout(timeout) => {
+                write!(f, "The select didn't finish within {:?}", timeout)
+            }
+            Error::InvalidRecord(msg) => write!(f, "Invalid record: {}", msg),
+            Error::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
+            Error::Output(err) => write!(f, "Can't write the records: {}", err),
+            Error::BudgetExceeded(limit, _) => {
+                write!(f, "The select exceeded its budget of {}", limit)
+            }
+            Error::Cancelled(_) => write!(f, "The select was cancelled"),
+        }
+    }
+}
+
+impl From<QueryError> for Error {
+    fn from(err: QueryError) -> Error {
+        Error::InvalidQuery(err)
+    }
+}
+
+impl From<RusotoError<()>> for Error {
+    fn from(err: RusotoError<()>) -> Error {
+        Error::Stream(Box::new(err))
+    }
+}
+
+impl From<surf::Error> for Error {
+    fn from(err: surf::Error) -> Error {
+        Error::Http(err)
+    }
+}
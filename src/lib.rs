@@ -5,59 +5,82 @@ use std::collections::BTreeMap;
 use std::time::Duration;
 
 use rusoto_core::Region;
-use rusoto_core::credential::{Anonymous, CredentialsError};
+use rusoto_core::credential::Anonymous;
 use rusoto_core::encoding::ContentEncoding;
 use rusoto_core::param::ServiceParams;
 use rusoto_core::{credential::ProvideAwsCredentials, signature::SignedRequest};
-use rusoto_s3::{SelectObjectContentRequest, SelectObjectContentRequestSerializer};
+use rusoto_s3::{GetObjectRequest, HeadObjectRequest, ListObjectsV2Request, SelectObjectContentRequest, SelectObjectContentRequestSerializer};
 use surf::http::Method;
 use surf::{RequestBuilder, Url};
 use xml::EventWriter;
-use serde::{Serialize, Deserialize};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 
 pub type Params = BTreeMap<String, Option<String>>;
 
+pub mod aggregate;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod client;
+pub mod cost;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+pub mod credential;
+pub mod error;
+pub mod local;
+// The models are kept in the style of the rusoto code they come from.
+#[allow(
+    clippy::explicit_auto_deref,
+    clippy::from_over_into,
+    clippy::needless_borrow,
+    clippy::result_large_err,
+    clippy::useless_asref
+)]
 pub mod model;
+pub mod object;
+pub mod prefix;
+pub mod query;
+pub mod request;
+pub mod schema;
+pub mod select;
+pub mod sink;
+pub mod sse;
+#[cfg(feature = "tracing")]
+pub mod telemetry;
 
-#[derive(Serialize, Deserialize)]
-struct QueryParam {
-    select: String,
-    #[serde(rename = "select-type")]
-    select_type: u32
-}
+pub use aggregate::FromScalar;
+pub use client::Client;
+pub use error::Error;
+pub use prefix::{ObjectEvent, PrefixSelect};
+pub use select::{AbortHandle, Budget, SelectStream, SelectSummary, Timeouts};
+pub use sse::ServerSideEncryption;
 
 pub async fn select_object_content(hostname: String, 
-        select_object_content_request: SelectObjectContentRequest,
+        mut select_object_content_request: SelectObjectContentRequest,
         credentials_provider: Option<Box<dyn ProvideAwsCredentials + Send + Sync>>,
         region: String,
         timeout: Option<Duration>) -> surf::Result<RequestBuilder> {
-    let uri: Url = format!("{}/{}/{}", hostname, select_object_content_request.bucket, select_object_content_request.key).parse().unwrap();    
-
-    let region = Region::Custom {
-        name: region.to_owned(),
-        endpoint: uri.host_str().unwrap_or("").to_owned(),
-    };
+    sse::prepare(&hostname, &mut select_object_content_request)?;
+    signed_select_object_content(
+        hostname,
+        select_object_content_request,
+        credentials_provider.as_deref(),
+        region,
+        timeout,
+    )
+    .await
+}
 
-    let mut signed_request = SignedRequest::new("POST", "s3", &region, uri.path());
-    
-    signed_request.add_optional_header(
-        "x-amz-server-side-encryption-customer-algorithm",
-        select_object_content_request
-            .sse_customer_algorithm
-            .as_ref(),
-    );
-    signed_request.add_optional_header(
-        "x-amz-server-side-encryption-customer-key",
-        select_object_content_request.sse_customer_key.as_ref(),
-    );
-    signed_request.add_optional_header(
-        "x-amz-server-side-encryption-customer-key-MD5",
-        select_object_content_request.sse_customer_key_md5.as_ref(),
-    );
+/// Sign the select, the SSE-C fields must be checked by [`sse::prepare`] first.
+pub(crate) async fn signed_select_object_content(
+    hostname: String,
+    select_object_content_request: SelectObjectContentRequest,
+    credentials_provider: Option<&(dyn ProvideAwsCredentials + Send + Sync)>,
+    region: String,
+    timeout: Option<Duration>,
+) -> surf::Result<RequestBuilder> {
     let mut params = Params::new();
     params.put_key("select");
     params.put("select-type", "2");
-    signed_request.set_params(params);
 
     let mut writer = EventWriter::new(Vec::new());
     SelectObjectContentRequestSerializer::serialize(
@@ -67,68 +90,598 @@ pub async fn select_object_content(hostname: String,
         "http://s3.amazonaws.com/doc/2006-03-01/",
     )?;
 
-    let paylaod = writer.into_inner();
+    let request = select_object_content_request;
+    signed_request(
+        &hostname,
+        Method::Post,
+        &object_path(&request.bucket, &request.key),
+        params,
+        &[
+            (
+                "x-amz-server-side-encryption-customer-algorithm",
+                request.sse_customer_algorithm.as_ref(),
+            ),
+            (
+                "x-amz-server-side-encryption-customer-key",
+                request.sse_customer_key.as_ref(),
+            ),
+            (
+                "x-amz-server-side-encryption-customer-key-MD5",
+                request.sse_customer_key_md5.as_ref(),
+            ),
+        ],
+        Some(writer.into_inner()),
+        credentials_provider,
+        &region,
+        timeout,
+    )
+    .await
+}
+
+/// Build the signed request reading the object, or a part of it with the `range` field.
+pub async fn get_object(hostname: String,
+        mut get_object_request: GetObjectRequest,
+        credentials_provider: Option<Box<dyn ProvideAwsCredentials + Send + Sync>>,
+        region: String,
+        timeout: Option<Duration>) -> surf::Result<RequestBuilder> {
+    sse::prepare_customer_key(
+        &hostname,
+        get_object_request.sse_customer_algorithm.as_deref(),
+        get_object_request.sse_customer_key.as_deref(),
+        &mut get_object_request.sse_customer_key_md5,
+    )?;
+    signed_get_object(
+        hostname,
+        get_object_request,
+        credentials_provider.as_deref(),
+        region,
+        timeout,
+    )
+    .await
+}
+
+/// Sign the request, the SSE-C fields must be checked by [`sse::prepare_customer_key`] first.
+pub(crate) async fn signed_get_object(
+    hostname: String,
+    get_object_request: GetObjectRequest,
+    credentials_provider: Option<&(dyn ProvideAwsCredentials + Send + Sync)>,
+    region: String,
+    timeout: Option<Duration>,
+) -> surf::Result<RequestBuilder> {
+    let request = get_object_request;
+    let mut params = Params::new();
+    if let Some(version_id) = &request.version_id {
+        params.put("versionId", version_id);
+    }
+    if let Some(part_number) = request.part_number {
+        params.put("partNumber", part_number);
+    }
+
+    signed_request(
+        &hostname,
+        Method::Get,
+        &object_path(&request.bucket, &request.key),
+        params,
+        &[
+            ("range", request.range.as_ref()),
+            ("if-match", request.if_match.as_ref()),
+            ("if-none-match", request.if_none_match.as_ref()),
+            (
+                "x-amz-server-side-encryption-customer-algorithm",
+                request.sse_customer_algorithm.as_ref(),
+            ),
+            (
+                "x-amz-server-side-encryption-customer-key",
+                request.sse_customer_key.as_ref(),
+            ),
+            (
+                "x-amz-server-side-encryption-customer-key-MD5",
+                request.sse_customer_key_md5.as_ref(),
+            ),
+        ],
+        None,
+        credentials_provider,
+        &region,
+        timeout,
+    )
+    .await
+}
+
+/// Build the signed request listing one page of the objects of the bucket. The body of the
+/// response is read with [`object::deserialize_list_objects_v2`], its `next_continuation_token`
+/// gives the next page.
+pub async fn list_objects_v2(hostname: String,
+        list_objects_v2_request: ListObjectsV2Request,
+        credentials_provider: Option<Box<dyn ProvideAwsCredentials + Send + Sync>>,
+        region: String,
+        timeout: Option<Duration>) -> surf::Result<RequestBuilder> {
+    signed_list_objects_v2(
+        hostname,
+        list_objects_v2_request,
+        credentials_provider.as_deref(),
+        region,
+        timeout,
+    )
+    .await
+}
+
+pub(crate) async fn signed_list_objects_v2(
+    hostname: String,
+    list_objects_v2_request: ListObjectsV2Request,
+    credentials_provider: Option<&(dyn ProvideAwsCredentials + Send + Sync)>,
+    region: String,
+    timeout: Option<Duration>,
+) -> surf::Result<RequestBuilder> {
+    let request = list_objects_v2_request;
+    let mut params = Params::new();
+    params.put("list-type", "2");
+    if let Some(continuation_token) = &request.continuation_token {
+        params.put("continuation-token", continuation_token);
+    }
+    if let Some(delimiter) = &request.delimiter {
+        params.put("delimiter", delimiter);
+    }
+    if let Some(encoding_type) = &request.encoding_type {
+        params.put("encoding-type", encoding_type);
+    }
+    if let Some(fetch_owner) = &request.fetch_owner {
+        params.put("fetch-owner", fetch_owner);
+    }
+    if let Some(max_keys) = &request.max_keys {
+        params.put("max-keys", max_keys);
+    }
+    if let Some(prefix) = &request.prefix {
+        params.put("prefix", prefix);
+    }
+    if let Some(start_after) = &request.start_after {
+        params.put("start-after", start_after);
+    }
+
+    signed_request(
+        &hostname,
+        Method::Get,
+        &format!("/{}", request.bucket),
+        params,
+        &[
+            ("x-amz-expected-bucket-owner", request.expected_bucket_owner.as_ref()),
+            ("x-amz-request-payer", request.request_payer.as_ref()),
+        ],
+        None,
+        credentials_provider,
+        &region,
+        timeout,
+    )
+    .await
+}
+
+/// Build the signed request reading the metadata of the object. The headers of the response are
+/// read with [`object::deserialize_head_object`].
+pub async fn head_object(hostname: String,
+        mut head_object_request: HeadObjectRequest,
+        credentials_provider: Option<Box<dyn ProvideAwsCredentials + Send + Sync>>,
+        region: String,
+        timeout: Option<Duration>) -> surf::Result<RequestBuilder> {
+    sse::prepare_customer_key(
+        &hostname,
+        head_object_request.sse_customer_algorithm.as_deref(),
+        head_object_request.sse_customer_key.as_deref(),
+        &mut head_object_request.sse_customer_key_md5,
+    )?;
+    signed_head_object(
+        hostname,
+        head_object_request,
+        credentials_provider.as_deref(),
+        region,
+        timeout,
+    )
+    .await
+}
+
+/// Sign the request, the SSE-C fields must be checked by [`sse::prepare_customer_key`] first.
+pub(crate) async fn signed_head_object(
+    hostname: String,
+    head_object_request: HeadObjectRequest,
+    credentials_provider: Option<&(dyn ProvideAwsCredentials + Send + Sync)>,
+    region: String,
+    timeout: Option<Duration>,
+) -> surf::Result<RequestBuilder> {
+    let request = head_object_request;
+    let mut params = Params::new();
+    if let Some(version_id) = &request.version_id {
+        params.put("versionId", version_id);
+    }
+    if let Some(part_number) = request.part_number {
+        params.put("partNumber", part_number);
+    }
+
+    signed_request(
+        &hostname,
+        Method::Head,
+        &object_path(&request.bucket, &request.key),
+        params,
+        &[
+            ("range", request.range.as_ref()),
+            ("if-match", request.if_match.as_ref()),
+            ("if-modified-since", request.if_modified_since.as_ref()),
+            ("if-none-match", request.if_none_match.as_ref()),
+            ("if-unmodified-since", request.if_unmodified_since.as_ref()),
+            ("x-amz-expected-bucket-owner", request.expected_bucket_owner.as_ref()),
+            ("x-amz-request-payer", request.request_payer.as_ref()),
+            (
+                "x-amz-server-side-encryption-customer-algorithm",
+                request.sse_customer_algorithm.as_ref(),
+            ),
+            (
+                "x-amz-server-side-encryption-customer-key",
+                request.sse_customer_key.as_ref(),
+            ),
+            (
+                "x-amz-server-side-encryption-customer-key-MD5",
+                request.sse_customer_key_md5.as_ref(),
+            ),
+        ],
+        None,
+        credentials_provider,
+        &region,
+        timeout,
+    )
+    .await
+}
 
-    signed_request.set_payload(Some(paylaod.clone()));
-    signed_request.set_content_type("application/xml; charset=utf-8".to_string());
+/// Path of the object, not encoded: the key is encoded once with the canonical path.
+fn object_path(bucket: &str, key: &str) -> String {
+    format!("/{}/{}", bucket, key)
+}
+
+/// Sign a request sent to the bucket with the path style.
+///
+/// The query string is encoded like the canonical query string of the signature, otherwise the
+/// endpoint computes another signature.
+#[allow(clippy::too_many_arguments)]
+async fn signed_request(
+    hostname: &str,
+    method: Method,
+    path: &str,
+    params: Params,
+    headers: &[(&str, Option<&String>)],
+    payload: Option<Vec<u8>>,
+    credentials_provider: Option<&(dyn ProvideAwsCredentials + Send + Sync)>,
+    region: &str,
+    timeout: Option<Duration>,
+) -> surf::Result<RequestBuilder> {
+    let mut uri: Url = hostname.parse()?;
+    let path = format!(
+        "{}{}",
+        percent_decode_str(uri.path().trim_end_matches('/')).decode_utf8_lossy(),
+        path
+    );
+
+    let region = Region::Custom {
+        name: region.to_owned(),
+        endpoint: uri.host_str().unwrap_or("").to_owned(),
+    };
+
+    let mut signed_request = SignedRequest::new(method.as_ref(), "s3", &region, &path);
+    // The path sent is the signed one, the characters of the key like `?` or `#` are encoded.
+    uri.set_path(&signed_request.canonical_path());
+
+    for (name, value) in headers {
+        signed_request.add_optional_header(name, value.as_ref());
+    }
+
+    let query = canonical_query_string(&params);
+    signed_request.set_params(params);
+
+    if let Some(payload) = &payload {
+        signed_request.set_payload(Some(payload.clone()));
+        signed_request.set_content_type("application/xml; charset=utf-8".to_string());
+    }
 
     let encoding = ContentEncoding::default();
     encoding.encode(&mut signed_request);
 
     if let Some(provider) = credentials_provider {
-        let credentials = if let Some(to) = timeout {
-            async_std::future::timeout(to, provider.credentials())
-                .await
-                .map_err(|_| CredentialsError {
-                    message: "Timeout getting credentials".to_owned(),
-                })
-                .and_then(std::convert::identity)
-        } else {
-            provider.credentials().await
-        }
-        .map_err(|err| CredentialsError {
-            message: format!("Couldn't connect to credentials provider: {}", err).to_owned(),
-        })?;
+        let credentials = credential::fetch_credentials(provider, timeout, Some(region.name())).await?;
         if credentials.is_anonymous() {
             signed_request.complement();
         } else {
+            // Adds the `x-amz-security-token` header when the credentials come from STS.
             signed_request.sign(&credentials);
         }
     } else {
         signed_request.complement();
     }
 
-    let mut request_builder = RequestBuilder::new(Method::Post, uri);
+    if !query.is_empty() {
+        uri.set_query(Some(&query));
+    }
+    let mut request_builder = RequestBuilder::new(method, uri);
 
     for (key, value) in signed_request.headers() {
         request_builder = request_builder.header(key.clone().as_str(), canonical_values(value));
     }
 
-    let query = QueryParam {
-        select: "".to_string(),
-        select_type: 2
-    };
-    request_builder = request_builder.query(&query)?;
-    request_builder = request_builder.body(paylaod);
+    if let Some(payload) = payload {
+        request_builder = request_builder.body(payload);
+    }
 
     Ok(request_builder)
 }
 
+/// Encode the parameters sorted by name with the strict URI encoding of the signature.
+fn canonical_query_string(params: &Params) -> String {
+    params
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(key, STRICT_ENCODE_SET),
+                utf8_percent_encode(value.as_deref().unwrap_or(""), STRICT_ENCODE_SET)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// Unreserved characters of the RFC 3986 are not encoded.
+const STRICT_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
 /// Canonicalizes values into the AWS Canonical Form.
 ///
 /// Read more about it: [HERE](http://docs.aws.amazon.com/general/latest/gr/sigv4-create-canonical-request.html)
 fn canonical_values(values: &[Vec<u8>]) -> String {
     let mut st = String::new();
     for v in values {
-        let s = std::str::from_utf8(v).unwrap();
+        let s = String::from_utf8_lossy(v);
         if !st.is_empty() {
             st.push(',')
         }
         if s.starts_with('\"') {
-            st.push_str(s);
+            st.push_str(&s);
         } else {
             st.push_str(s.replace("  ", " ").trim());
         }
     }
     st
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use chrono::Utc;
+    use rusoto_core::credential::{AwsCredentials, CredentialsError, StaticProvider};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const TOKEN: &str = "FwoGZXIvYXdzEBYaDH+sts/session+token==";
+
+    struct ExpiringProvider {
+        calls: AtomicUsize,
+        fresh_after: usize,
+    }
+
+    #[async_trait]
+    impl ProvideAwsCredentials for ExpiringProvider {
+        async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let expires_at = if call >= self.fresh_after {
+                Utc::now() + chrono::Duration::hours(1)
+            } else {
+                Utc::now() - chrono::Duration::minutes(1)
+            };
+            Ok(AwsCredentials::new(
+                "access_key",
+                "secret_key",
+                Some(format!("token-{}", call)),
+                Some(expires_at),
+            ))
+        }
+    }
+
+    fn request() -> SelectObjectContentRequest {
+        SelectObjectContentRequest {
+            bucket: "my-bucket".to_owned(),
+            key: "data/multi_lines.csv".to_owned(),
+            expression: "select * from s3object".to_owned(),
+            expression_type: "SQL".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[async_std::test]
+    async fn select_object_content_forward_session_token() {
+        let provider = StaticProvider::new(
+            "access_key".to_owned(),
+            "secret_key".to_owned(),
+            Some(TOKEN.to_owned()),
+            None,
+        );
+
+        let request = select_object_content(
+            "http://localhost:9000".to_string(),
+            request(),
+            Some(Box::new(provider)),
+            "us-east-1".to_string(),
+            None,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        assert_eq!(TOKEN, request.header("x-amz-security-token").unwrap().as_str());
+        let authorization = request.header("authorization").unwrap().as_str();
+        assert!(authorization.contains("x-amz-security-token"));
+    }
+
+    #[async_std::test]
+    async fn select_object_content_refresh_expired_credentials() {
+        let provider = ExpiringProvider {
+            calls: AtomicUsize::new(0),
+            fresh_after: 1,
+        };
+
+        let request = select_object_content(
+            "http://localhost:9000".to_string(),
+            request(),
+            Some(Box::new(provider)),
+            "us-east-1".to_string(),
+            None,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        assert_eq!("token-1", request.header("x-amz-security-token").unwrap().as_str());
+    }
+
+    #[async_std::test]
+    async fn select_object_content_reject_expired_credentials() {
+        let provider = ExpiringProvider {
+            calls: AtomicUsize::new(0),
+            fresh_after: usize::MAX,
+        };
+
+        let result = select_object_content(
+            "http://localhost:9000".to_string(),
+            request(),
+            Some(Box::new(provider)),
+            "us-east-1".to_string(),
+            None,
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[async_std::test]
+    async fn select_object_content_query_string() {
+        let request = select_object_content(
+            "http://localhost:9000".to_string(),
+            request(),
+            None,
+            "us-east-1".to_string(),
+            None,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        assert_eq!(Some("select=&select-type=2"), request.url().query());
+        assert_eq!("/my-bucket/data/multi_lines.csv", request.url().path());
+    }
+
+    #[async_std::test]
+    async fn select_object_content_encode_key() {
+        let request = select_object_content(
+            "http://localhost:9000".to_string(),
+            SelectObjectContentRequest {
+                key: "dir/a b?c#d é.csv".to_owned(),
+                ..request()
+            },
+            None,
+            "us-east-1".to_string(),
+            None,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        assert_eq!(Some("select=&select-type=2"), request.url().query());
+        assert_eq!(None, request.url().fragment());
+        assert_eq!(
+            "/my-bucket/dir/a%20b%3Fc%23d%20%C3%A9.csv",
+            request.url().path()
+        );
+        // The signature is computed on the path sent.
+        let signed_request = SignedRequest::new(
+            "POST",
+            "s3",
+            &Region::UsEast1,
+            "/my-bucket/dir/a b?c#d é.csv",
+        );
+        assert_eq!(signed_request.canonical_path(), request.url().path());
+    }
+
+    #[async_std::test]
+    async fn get_object_sign_range() {
+        let provider = StaticProvider::new_minimal("access_key".to_owned(), "secret_key".to_owned());
+
+        let request = get_object(
+            "http://localhost:9000".to_string(),
+            GetObjectRequest {
+                bucket: "my-bucket".to_owned(),
+                key: "data/multi_lines.csv".to_owned(),
+                range: Some("bytes=0-1023".to_owned()),
+                version_id: Some("3/L4kqtJl40Nr8X8gdRQBpUMLUo".to_owned()),
+                ..Default::default()
+            },
+            Some(Box::new(provider)),
+            "us-east-1".to_string(),
+            None,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        assert_eq!(Method::Get, request.method());
+        assert_eq!("bytes=0-1023", request.header("range").unwrap().as_str());
+        assert_eq!(
+            Some("versionId=3%2FL4kqtJl40Nr8X8gdRQBpUMLUo"),
+            request.url().query()
+        );
+        let authorization = request.header("authorization").unwrap().as_str();
+        assert!(authorization.contains("range"));
+    }
+
+    #[async_std::test]
+    async fn list_objects_v2_query_string() {
+        let request = list_objects_v2(
+            "http://localhost:9000".to_string(),
+            ListObjectsV2Request {
+                bucket: "my-bucket".to_owned(),
+                prefix: Some("data/year=2021/".to_owned()),
+                continuation_token: Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J+wm36Hy4vbOwM=".to_owned()),
+                ..Default::default()
+            },
+            None,
+            "us-east-1".to_string(),
+            None,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        assert_eq!(Method::Get, request.method());
+        assert_eq!("/my-bucket", request.url().path());
+        assert_eq!(
+            Some("continuation-token=1ueGcxLPRx1Tr%2FXYExHnhbYLgveDs2J%2Bwm36Hy4vbOwM%3D&list-type=2&prefix=data%2Fyear%3D2021%2F"),
+            request.url().query()
+        );
+    }
+
+    #[async_std::test]
+    async fn head_object_method() {
+        let request = head_object(
+            "http://localhost:9000".to_string(),
+            HeadObjectRequest {
+                bucket: "my-bucket".to_owned(),
+                key: "data/multi_lines.csv".to_owned(),
+                ..Default::default()
+            },
+            None,
+            "us-east-1".to_string(),
+            None,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        assert_eq!(Method::Head, request.method());
+        assert_eq!("/my-bucket/data/multi_lines.csv", request.url().path());
+        assert_eq!(None, request.url().query());
+    }
+}
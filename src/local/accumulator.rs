@@ -0,0 +1,136 @@
+//! Aggregate functions of the local evaluation.
+
+use std::cmp::Ordering;
+
+use crate::error::{Error, Result};
+
+use super::value::Value;
+
+/// State of an aggregate function, updated with the value of each selected record.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Accumulator {
+    Count(i64),
+    Sum(Option<Value>),
+    Avg { sum: f64, count: u64 },
+    Min(Option<Value>),
+    Max(Option<Value>),
+}
+
+impl Accumulator {
+    pub(crate) fn new(name: &str) -> Result<Self> {
+        match name {
+            "COUNT" => Ok(Accumulator::Count(0)),
+            "SUM" => Ok(Accumulator::Sum(None)),
+            "AVG" => Ok(Accumulator::Avg { sum: 0.0, count: 0 }),
+            "MIN" => Ok(Accumulator::Min(None)),
+            "MAX" => Ok(Accumulator::Max(None)),
+            _ => Err(Error::InvalidRequest(format!(
+                "{} is not an aggregate function",
+                name
+            ))),
+        }
+    }
+    /// Add the value of a record, the null and missing values are ignored. `COUNT(*)` is
+    /// updated with `TRUE`.
+    pub(crate) fn update(&mut self, value: Value) -> Result<()> {
+        if value.is_null_or_missing() {
+            return Ok(());
+        }
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum(sum) => {
+                let number = number(&value)?;
+                *sum = Some(match sum.take() {
+                    None => number,
+                    Some(Value::Int(a)) => match number {
+                        Value::Int(b) => a
+                            .checked_add(b)
+                            .map_or(Value::Float(a as f64 + b as f64), Value::Int),
+                        b => Value::Float(a as f64 + b.as_f64().unwrap_or(f64::NAN)),
+                    },
+                    Some(a) => Value::Float(
+                        a.as_f64().unwrap_or(f64::NAN) + number.as_f64().unwrap_or(f64::NAN),
+                    ),
+                });
+            }
+            Accumulator::Avg { sum, count } => {
+                *sum += number(&value)?.as_f64().unwrap_or(f64::NAN);
+                *count += 1;
+            }
+            Accumulator::Min(min) => keep(min, value, Ordering::Less)?,
+            Accumulator::Max(max) => keep(max, value, Ordering::Greater)?,
+        }
+        Ok(())
+    }
+    /// Result of the aggregation, null if no value was added, except for `COUNT`.
+    pub(crate) fn finish(self) -> Value {
+        match self {
+            Accumulator::Count(count) => Value::Int(count),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { sum, count } => Value::Float(sum / count as f64),
+            Accumulator::Sum(value) | Accumulator::Min(value) | Accumulator::Max(value) => {
+                value.unwrap_or(Value::Null)
+            }
+        }
+    }
+}
+
+fn number(value: &Value) -> Result<Value> {
+    value
+        .as_number()
+        .ok_or_else(|| Error::InvalidRecord(format!("'{}' is not a number", value)))
+}
+
+/// Keep the value if it's before, or after, the current one.
+fn keep(current: &mut Option<Value>, value: Value, ordering: Ordering) -> Result<()> {
+    // The CSV fields are compared as numbers when they can be.
+    let value = value.as_number().unwrap_or(value);
+    let replace = match current {
+        Some(current) => match value.compare(current) {
+            Some(order) => order == ordering,
+            None => {
+                return Err(Error::InvalidRecord(format!(
+                    "Can't compare '{}' with '{}'",
+                    value, current
+                )))
+            }
+        },
+        None => true,
+    };
+    if replace {
+        *current = Some(value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregate(name: &str, values: &[Value]) -> Value {
+        let mut accumulator = Accumulator::new(name).unwrap();
+        for value in values {
+            accumulator.update(value.clone()).unwrap();
+        }
+        accumulator.finish()
+    }
+
+    #[test]
+    fn aggregate_values() {
+        let values = [
+            Value::String("10".to_string()),
+            Value::Null,
+            Value::Int(2),
+            Value::String("3.5".to_string()),
+        ];
+
+        assert_eq!(Value::Int(3), aggregate("COUNT", &values));
+        assert_eq!(Value::Float(15.5), aggregate("SUM", &values));
+        assert_eq!(Value::Int(12), aggregate("SUM", &values[..3]));
+        assert_eq!(Value::Float(15.5 / 3.0), aggregate("AVG", &values));
+        assert_eq!(Value::Int(2), aggregate("MIN", &values));
+        assert_eq!(Value::Int(10), aggregate("MAX", &values));
+        assert_eq!(Value::Null, aggregate("MAX", &[Value::Missing]));
+        assert_eq!(Value::Int(0), aggregate("COUNT", &[]));
+    }
+}
@@ -0,0 +1,668 @@
+//! Evaluate the statement on the records.
+
+use std::cmp::Ordering;
+
+use crate::error::{Error, Result};
+use crate::query::ast::*;
+use crate::schema::positional_index;
+
+use super::accumulator::Accumulator;
+use super::functions;
+use super::reader::Record;
+use super::value::Value;
+
+/// Record being evaluated.
+pub(crate) enum Row<'a> {
+    Csv {
+        fields: &'a [String],
+        header: Option<&'a [String]>,
+    },
+    /// JSON document or Parquet row.
+    Document(Value),
+    /// Results of the aggregate functions, read by the parameters replacing them.
+    Aggregated(&'a [Value]),
+}
+
+impl<'a> Row<'a> {
+    /// Rows of the record, the JSON records are read at the path of the `FROM` clause.
+    pub(crate) fn from_record(
+        record: &'a Record,
+        header: Option<&'a [String]>,
+        from: &FromClause,
+    ) -> Vec<Row<'a>> {
+        match record {
+            Record::Csv(fields) => vec![Row::Csv { fields, header }],
+            #[cfg(feature = "parquet")]
+            Record::Parquet(value) => vec![Row::Document(value.clone())],
+            Record::Json(value) => {
+                let mut values = vec![Value::from(value.clone())];
+                for step in &from.path {
+                    values = values
+                        .into_iter()
+                        .flat_map(|value| match (step, value) {
+                            (PathStep::Wildcard, Value::List(values)) => values,
+                            (PathStep::Wildcard, value) => vec![value],
+                            (step, value) => vec![get(value, step)],
+                        })
+                        .filter(|value| *value != Value::Missing)
+                        .collect();
+                }
+                values.into_iter().map(Row::Document).collect()
+            }
+        }
+    }
+    /// Whole record, a CSV record gives a structure with its column names.
+    fn value(&self) -> Value {
+        match self {
+            Row::Csv { fields, header } => Value::Struct(
+                fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, field)| (column_name(*header, i), Value::String(field.clone())))
+                    .collect(),
+            ),
+            Row::Document(value) => value.clone(),
+            Row::Aggregated(_) => Value::Missing,
+        }
+    }
+    fn column(&self, column: &ColumnRef) -> Value {
+        let (first, rest) = match column.path.split_first() {
+            Some(path) => path,
+            None => return self.value(),
+        };
+        match self {
+            Row::Csv { fields, header } => {
+                let name = match (first, rest) {
+                    (PathStep::Key(name), []) => name,
+                    _ => return Value::Missing,
+                };
+                header
+                    .and_then(|header| header.iter().position(|column| name.matches(column)))
+                    .or_else(|| positional_index(&name.name))
+                    .and_then(|index| fields.get(index))
+                    .map_or(Value::Missing, |field| Value::String(field.clone()))
+            }
+            Row::Document(value) => {
+                let mut value = get(value.clone(), first);
+                for step in rest {
+                    value = get(value, step);
+                }
+                value
+            }
+            Row::Aggregated(_) => Value::Missing,
+        }
+    }
+}
+
+fn column_name(header: Option<&[String]>, index: usize) -> String {
+    header
+        .and_then(|header| header.get(index).cloned())
+        .unwrap_or_else(|| format!("_{}", index + 1))
+}
+
+fn get(value: Value, step: &PathStep) -> Value {
+    match (step, value) {
+        (PathStep::Key(name), Value::Struct(fields)) => fields
+            .into_iter()
+            .find(|(field, _)| name.matches(field))
+            .map_or(Value::Missing, |(_, value)| value),
+        (PathStep::Index(index), Value::List(values)) => values
+            .into_iter()
+            .nth(*index as usize)
+            .unwrap_or(Value::Missing),
+        _ => Value::Missing,
+    }
+}
+
+/// Aggregate function of the projection with its argument.
+struct Aggregate {
+    arg: Expr,
+    accumulator: Accumulator,
+}
+
+/// Statement prepared for the local evaluation. The aggregate functions of the projection are
+/// replaced by parameters set with their results.
+pub(crate) struct Query {
+    statement: SelectStatement,
+    aggregates: Option<Vec<Aggregate>>,
+}
+
+impl Query {
+    pub(crate) fn new(mut statement: SelectStatement) -> Result<Self> {
+        let mut aggregates = None;
+        if let Projection::Items(items) = &mut statement.projection {
+            if items.iter().any(|item| item.expr.is_aggregate()) {
+                let mut functions = Vec::new();
+                for item in items.iter_mut() {
+                    item.expr.walk_mut(&mut |expr| match expr {
+                        Expr::Function { name, args } if is_aggregate_function(name) => {
+                            let parameter = Expr::Parameter(functions.len());
+                            let args = std::mem::take(args);
+                            functions.push((name.clone(), args));
+                            *expr = parameter;
+                        }
+                        _ => (),
+                    });
+                }
+                aggregates = Some(
+                    functions
+                        .into_iter()
+                        .map(|(name, args)| {
+                            Ok(Aggregate {
+                                arg: args.into_iter().next().unwrap_or(Expr::Wildcard),
+                                accumulator: Accumulator::new(&name.to_uppercase())?,
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                );
+            }
+        }
+
+        Ok(Query {
+            statement,
+            aggregates,
+        })
+    }
+    /// Columns read by the query, `None` if the whole records are needed.
+    pub(crate) fn columns(&self) -> Option<Vec<Identifier>> {
+        if self.statement.projection == Projection::All {
+            return None;
+        }
+        let mut columns = Vec::new();
+        let mut whole_record = false;
+        let mut visit = |expr: &Expr| {
+            if let Expr::Column(column) = expr {
+                match column.name() {
+                    Some(name) => columns.push(name.clone()),
+                    None => whole_record = true,
+                }
+            }
+        };
+        self.statement.walk(&mut visit);
+        for aggregate in self.aggregates.iter().flatten() {
+            aggregate.arg.walk(&mut visit);
+        }
+        if whole_record {
+            None
+        } else {
+            Some(columns)
+        }
+    }
+    /// Check if the query returns a single record with the results of aggregate functions.
+    pub(crate) fn is_aggregate(&self) -> bool {
+        self.aggregates.is_some()
+    }
+    /// Add the row selected to the aggregations.
+    pub(crate) fn accumulate(&mut self, row: &Row) -> Result<()> {
+        for aggregate in self.aggregates.iter_mut().flatten() {
+            let value = evaluate(&aggregate.arg, row)?;
+            aggregate.accumulator.update(value)?;
+        }
+        Ok(())
+    }
+    /// Project the results of the aggregations, once all the rows are accumulated.
+    pub(crate) fn aggregate_result(&mut self) -> Result<Value> {
+        let results: Vec<Value> = self
+            .aggregates
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|aggregate| aggregate.accumulator.finish())
+            .collect();
+        self.project(&Row::Aggregated(&results))
+    }
+    pub(crate) fn from(&self) -> &FromClause {
+        &self.statement.from
+    }
+    pub(crate) fn limit(&self) -> Option<u64> {
+        self.statement.limit
+    }
+    /// Check the `WHERE` clause.
+    pub(crate) fn matches(&self, row: &Row) -> Result<bool> {
+        match &self.statement.filter {
+            Some(filter) => Ok(evaluate(filter, row)? == Value::Bool(true)),
+            None => Ok(true),
+        }
+    }
+    /// Compute the projection, as a structure with the names of the output columns.
+    pub(crate) fn project(&self, row: &Row) -> Result<Value> {
+        let items = match &self.statement.projection {
+            Projection::All => return Ok(row.value()),
+            Projection::Items(items) => items,
+        };
+
+        let mut fields = Vec::with_capacity(items.len());
+        for (i, item) in items.iter().enumerate() {
+            let name = match (&item.alias, &item.expr) {
+                (Some(alias), _) => alias.name.clone(),
+                (None, Expr::Column(column)) => match column.path.last() {
+                    Some(PathStep::Key(name)) => name.name.clone(),
+                    _ => format!("_{}", i + 1),
+                },
+                _ => format!("_{}", i + 1),
+            };
+            fields.push((name, evaluate(&item.expr, row)?));
+        }
+        Ok(Value::Struct(fields))
+    }
+}
+
+/// Evaluate the expression on the row. The comparisons with `NULL` or `MISSING` are `NULL`.
+pub(crate) fn evaluate(expr: &Expr, row: &Row) -> Result<Value> {
+    let value = match expr {
+        Expr::Literal(literal) => match literal {
+            Literal::Null => Value::Null,
+            Literal::Missing => Value::Missing,
+            Literal::Bool(value) => Value::Bool(*value),
+            Literal::Int(value) => Value::Int(*value),
+            Literal::Float(value) => Value::Float(*value),
+            Literal::String(value) => Value::String(value.clone()),
+        },
+        Expr::Column(column) => row.column(column),
+        // Argument of `COUNT(*)`, every row is counted.
+        Expr::Wildcard => Value::Bool(true),
+        Expr::Parameter(index) => match row {
+            Row::Aggregated(results) => results.get(*index).cloned().unwrap_or(Value::Missing),
+            _ => {
+                return Err(Error::InvalidRequest(format!(
+                    "Unbound parameter {}",
+                    index
+                )))
+            }
+        },
+        Expr::Unary { op, expr } => {
+            let value = evaluate(expr, row)?;
+            match op {
+                UnaryOperator::Not => match value {
+                    Value::Bool(value) => Value::Bool(!value),
+                    _ => Value::Null,
+                },
+                UnaryOperator::Minus => arithmetic(&Value::Int(0), BinaryOperator::Minus, &value)?,
+            }
+        }
+        Expr::Binary { left, op, right } => {
+            let left = evaluate(left, row)?;
+            match op {
+                // `FALSE AND x` is false and `TRUE OR x` is true, whatever x is.
+                BinaryOperator::And if left == Value::Bool(false) => left,
+                BinaryOperator::Or if left == Value::Bool(true) => left,
+                BinaryOperator::And | BinaryOperator::Or => {
+                    let right = evaluate(right, row)?;
+                    match (left, right) {
+                        (_, Value::Bool(false)) if *op == BinaryOperator::And => Value::Bool(false),
+                        (_, Value::Bool(true)) if *op == BinaryOperator::Or => Value::Bool(true),
+                        (Value::Bool(_), Value::Bool(value)) => Value::Bool(value),
+                        _ => Value::Null,
+                    }
+                }
+                _ => binary(&left, *op, &evaluate(right, row)?)?,
+            }
+        }
+        Expr::IsNull { expr, negated } => {
+            Value::Bool(evaluate(expr, row)?.is_null_or_missing() != *negated)
+        }
+        Expr::IsMissing { expr, negated } => {
+            Value::Bool((evaluate(expr, row)? == Value::Missing) != *negated)
+        }
+        Expr::Like {
+            expr,
+            pattern,
+            escape,
+            negated,
+        } => {
+            let value = evaluate(expr, row)?;
+            let pattern = evaluate(pattern, row)?;
+            let escape = match escape {
+                Some(escape) => match evaluate(escape, row)? {
+                    Value::String(escape) if escape.chars().count() == 1 => escape.chars().next(),
+                    value => {
+                        return Err(Error::InvalidRecord(format!(
+                            "The escape of LIKE must be a single character, got {}",
+                            value
+                        )))
+                    }
+                },
+                None => None,
+            };
+            match (value, pattern) {
+                (Value::String(value), Value::String(pattern)) => {
+                    Value::Bool(like(&value, &pattern, escape) != *negated)
+                }
+                _ => Value::Null,
+            }
+        }
+        Expr::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => {
+            let value = evaluate(expr, row)?;
+            let low = value.compare(&evaluate(low, row)?);
+            let high = value.compare(&evaluate(high, row)?);
+            match (low, high) {
+                (Some(low), Some(high)) => {
+                    Value::Bool((low != Ordering::Less && high != Ordering::Greater) != *negated)
+                }
+                _ => Value::Null,
+            }
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let value = evaluate(expr, row)?;
+            if value.is_null_or_missing() {
+                Value::Null
+            } else {
+                let mut found = false;
+                for item in list {
+                    found |= value.compare(&evaluate(item, row)?) == Some(Ordering::Equal);
+                }
+                Value::Bool(found != *negated)
+            }
+        }
+        Expr::Function { name, args } => match (name.as_str(), args.as_slice()) {
+            ("DATE_ADD", [Expr::DatePart(part), quantity, timestamp]) => {
+                functions::date_add(*part, evaluate(quantity, row)?, evaluate(timestamp, row)?)?
+            }
+            ("DATE_DIFF", [Expr::DatePart(part), from, to]) => {
+                functions::date_diff(*part, evaluate(from, row)?, evaluate(to, row)?)?
+            }
+            // The aggregate functions are replaced by parameters in the projection.
+            (name, _) if is_aggregate_function(name) => {
+                return Err(Error::InvalidRequest(format!(
+                    "The aggregate function {} is only allowed in the projection",
+                    name
+                )))
+            }
+            (name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| evaluate(arg, row))
+                    .collect::<Result<Vec<_>>>()?;
+                functions::call(name, args)?
+            }
+        },
+        Expr::Cast { expr, data_type } => functions::cast(evaluate(expr, row)?, *data_type)?,
+        Expr::Extract { part, expr } => functions::extract(*part, evaluate(expr, row)?)?,
+        Expr::Trim {
+            spec,
+            characters,
+            expr,
+        } => {
+            let characters = match characters {
+                Some(characters) => Some(evaluate(characters, row)?),
+                None => None,
+            };
+            match (evaluate(expr, row)?, characters) {
+                (Value::String(value), None) => Value::String(functions::trim(&value, *spec, None)),
+                (Value::String(value), Some(Value::String(characters))) => {
+                    Value::String(functions::trim(&value, *spec, Some(&characters)))
+                }
+                _ => Value::Null,
+            }
+        }
+        Expr::Case {
+            operand,
+            branches,
+            default,
+        } => {
+            let operand = match operand {
+                Some(operand) => Some(evaluate(operand, row)?),
+                None => None,
+            };
+            let mut result = None;
+            for (condition, value) in branches {
+                let condition = evaluate(condition, row)?;
+                let selected = match &operand {
+                    Some(operand) => operand.compare(&condition) == Some(Ordering::Equal),
+                    None => condition == Value::Bool(true),
+                };
+                if selected {
+                    result = Some(evaluate(value, row)?);
+                    break;
+                }
+            }
+            match (result, default) {
+                (Some(result), _) => result,
+                (None, Some(default)) => evaluate(default, row)?,
+                (None, None) => Value::Null,
+            }
+        }
+        Expr::DatePart(part) => {
+            return Err(Error::InvalidRequest(format!(
+                "The date part {} is only allowed in DATE_ADD and DATE_DIFF",
+                part
+            )))
+        }
+    };
+    Ok(value)
+}
+
+fn binary(left: &Value, op: BinaryOperator, right: &Value) -> Result<Value> {
+    if left.is_null_or_missing() || right.is_null_or_missing() {
+        return Ok(Value::Null);
+    }
+    let ordering = left.compare(right);
+    let value = match op {
+        BinaryOperator::Eq => Value::Bool(ordering == Some(Ordering::Equal)),
+        BinaryOperator::NotEq => Value::Bool(ordering != Some(Ordering::Equal)),
+        BinaryOperator::Lt => ordering.map_or(Value::Null, |o| Value::Bool(o == Ordering::Less)),
+        BinaryOperator::LtEq => {
+            ordering.map_or(Value::Null, |o| Value::Bool(o != Ordering::Greater))
+        }
+        BinaryOperator::Gt => ordering.map_or(Value::Null, |o| Value::Bool(o == Ordering::Greater)),
+        BinaryOperator::GtEq => ordering.map_or(Value::Null, |o| Value::Bool(o != Ordering::Less)),
+        BinaryOperator::Concat => Value::String(format!("{}{}", left, right)),
+        _ => arithmetic(left, op, right)?,
+    };
+    Ok(value)
+}
+
+fn arithmetic(left: &Value, op: BinaryOperator, right: &Value) -> Result<Value> {
+    if left.is_null_or_missing() || right.is_null_or_missing() {
+        return Ok(Value::Null);
+    }
+    let (left, right) = match (left.as_number(), right.as_number()) {
+        (Some(left), Some(right)) => (left, right),
+        _ => {
+            return Err(Error::InvalidRecord(format!(
+                "Can't compute {} {} {}",
+                left, op, right
+            )))
+        }
+    };
+
+    if let (Value::Int(a), Value::Int(b)) = (&left, &right) {
+        let result = match op {
+            BinaryOperator::Plus => a.checked_add(*b),
+            BinaryOperator::Minus => a.checked_sub(*b),
+            BinaryOperator::Multiply => a.checked_mul(*b),
+            BinaryOperator::Divide | BinaryOperator::Modulo if *b == 0 => {
+                return Err(Error::InvalidRecord("Division by zero".to_string()))
+            }
+            BinaryOperator::Divide => a.checked_div(*b),
+            BinaryOperator::Modulo => a.checked_rem(*b),
+            _ => None,
+        };
+        return result
+            .map(Value::Int)
+            .ok_or_else(|| Error::InvalidRecord(format!("Overflow computing {} {} {}", a, op, b)));
+    }
+
+    let (a, b) = (
+        left.as_f64().unwrap_or(f64::NAN),
+        right.as_f64().unwrap_or(f64::NAN),
+    );
+    Ok(Value::Float(match op {
+        BinaryOperator::Plus => a + b,
+        BinaryOperator::Minus => a - b,
+        BinaryOperator::Multiply => a * b,
+        BinaryOperator::Divide => a / b,
+        BinaryOperator::Modulo => a % b,
+        _ => f64::NAN,
+    }))
+}
+
+/// Match the `LIKE` pattern: `%` is any sequence of characters and `_` any character.
+///
+/// The characters after a `%` are matched greedily, a mismatch resumes after the last `%` one
+/// character further, so the match is linear in the length of the value for each `%`.
+pub(crate) fn like(value: &str, pattern: &str, escape: Option<char>) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let pattern = like_tokens(pattern, escape);
+
+    let (mut v, mut p) = (0, 0);
+    // Position in the pattern after the last `%` and position in the value it matches from.
+    let mut any: Option<(usize, usize)> = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(LikeToken::Any) => {
+                p += 1;
+                any = Some((p, v));
+            }
+            Some(LikeToken::One) => {
+                v += 1;
+                p += 1;
+            }
+            Some(LikeToken::Char(c)) if *c == value[v] => {
+                v += 1;
+                p += 1;
+            }
+            _ => match any {
+                Some((any_p, any_v)) => {
+                    p = any_p;
+                    v = any_v + 1;
+                    any = Some((any_p, v));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|token| *token == LikeToken::Any)
+}
+
+/// Part of a `LIKE` pattern.
+#[derive(PartialEq, Eq)]
+enum LikeToken {
+    /// `%`
+    Any,
+    /// `_`
+    One,
+    Char(char),
+}
+
+fn like_tokens(pattern: &str, escape: Option<char>) -> Vec<LikeToken> {
+    let mut chars = pattern.chars().peekable();
+    let mut tokens = Vec::new();
+    while let Some(c) = chars.next() {
+        // A trailing escape character is a literal.
+        if let Some(literal) = chars.next_if(|_| Some(c) == escape) {
+            tokens.push(LikeToken::Char(literal));
+            continue;
+        }
+        tokens.push(match c {
+            '%' => LikeToken::Any,
+            '_' => LikeToken::One,
+            c => LikeToken::Char(c),
+        });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::parse;
+
+    fn row(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(ToString::to_string).collect()
+    }
+
+    fn eval_csv(expression: &str, fields: &[String], header: &[String]) -> Value {
+        let statement = parse(&format!("SELECT {} FROM S3Object s", expression)).unwrap();
+        let query = Query::new(statement).unwrap();
+        let row = Row::Csv {
+            fields,
+            header: Some(header),
+        };
+        match query.project(&row).unwrap() {
+            Value::Struct(mut fields) => fields.remove(0).1,
+            value => value,
+        }
+    }
+
+    #[test]
+    fn evaluate_csv_expressions() {
+        let header = row(&["number", "long-string"]);
+        let fields = row(&["20", "it's a string"]);
+
+        let cases = [
+            ("s.number = 20", Value::Bool(true)),
+            ("s._1 + 1", Value::Int(21)),
+            ("s.number / 8.0", Value::Float(2.5)),
+            ("s.\"long-string\" LIKE 'it%string'", Value::Bool(true)),
+            ("s.number BETWEEN 10 AND 15", Value::Bool(false)),
+            ("s.number IN (10, 20)", Value::Bool(true)),
+            ("s.unknown IS MISSING", Value::Bool(true)),
+            ("s.unknown = 1", Value::Null),
+            ("s.unknown = 1 OR s.number > 1", Value::Bool(true)),
+            ("NOT (s.unknown = 1) AND s.number > 1", Value::Null),
+            ("s._2 || '!'", Value::String("it's a string!".to_string())),
+        ];
+        for (expression, expected) in cases.iter() {
+            assert_eq!(
+                *expected,
+                eval_csv(expression, &fields, &header),
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn evaluate_json_path() {
+        let statement = parse("SELECT r.name, r.tags[1] FROM S3Object[*].results[*] r").unwrap();
+        let query = Query::new(statement).unwrap();
+        let record = Record::Json(serde_json::json!([
+            {"results": [{"name": "a", "tags": ["x", "y"]}, {"name": "b"}]}
+        ]));
+
+        let projected: Vec<Value> = Row::from_record(&record, None, query.from())
+            .iter()
+            .map(|row| query.project(row).unwrap())
+            .collect();
+
+        assert_eq!(
+            vec![
+                Value::Struct(vec![
+                    ("name".to_string(), Value::String("a".to_string())),
+                    ("_2".to_string(), Value::String("y".to_string())),
+                ]),
+                Value::Struct(vec![
+                    ("name".to_string(), Value::String("b".to_string())),
+                    ("_2".to_string(), Value::Missing),
+                ]),
+            ],
+            projected
+        );
+    }
+
+    #[test]
+    fn like_pattern() {
+        assert!(like("100%", "100!%", Some('!')));
+        assert!(!like("1000", "100!%", Some('!')));
+        assert!(like("file.csv", "file.___", None));
+        assert!(like("", "%", None));
+        assert!(like("abcabd", "%ab_", None));
+        assert!(like("a%b", "%!%%", Some('!')));
+        assert!(!like("ab", "a%c", None));
+
+        // The repeated `%` don't backtrack.
+        let value = "a".repeat(1000);
+        assert!(!like(&value, &format!("{}b", "%a".repeat(50)), None));
+        assert!(like(&value, &"%a".repeat(50), None));
+    }
+}
@@ -0,0 +1,457 @@
+//! Scalar functions, casts and timestamps of the local evaluation.
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Months, NaiveDate, NaiveDateTime, Timelike, Utc,
+};
+
+use crate::error::{Error, Result};
+use crate::query::ast::{DataType, DatePart, TrimSpec};
+
+use super::value::Value;
+
+/// Call the scalar function with the evaluated arguments. The arguments count is checked by the
+/// parser, `DATE_ADD` and `DATE_DIFF` are called with [`date_add`] and [`date_diff`].
+pub(crate) fn call(name: &str, args: Vec<Value>) -> Result<Value> {
+    let mut args = args.into_iter();
+    let mut arg = || args.next().unwrap_or(Value::Missing);
+
+    let value = match name {
+        "COALESCE" => {
+            let first = std::iter::once(arg())
+                .chain(args)
+                .find(|value| !value.is_null_or_missing());
+            first.unwrap_or(Value::Null)
+        }
+        "NULLIF" => {
+            let (value, other) = (arg(), arg());
+            if value.compare(&other) == Some(std::cmp::Ordering::Equal) {
+                Value::Null
+            } else {
+                value
+            }
+        }
+        "UTCNOW" => Value::Timestamp(Utc::now().into()),
+        "TO_TIMESTAMP" => cast(arg(), DataType::Timestamp)?,
+        "TO_STRING" => match (timestamp(arg())?, arg()) {
+            (Some(timestamp), Value::String(pattern)) => {
+                Value::String(format_timestamp(&timestamp, &pattern)?)
+            }
+            (None, _) => Value::Null,
+            (_, pattern) => return Err(invalid_argument(name, &pattern)),
+        },
+        "CHAR_LENGTH" | "CHARACTER_LENGTH" => match arg() {
+            Value::String(value) => Value::Int(value.chars().count() as i64),
+            value if value.is_null_or_missing() => Value::Null,
+            value => return Err(invalid_argument(name, &value)),
+        },
+        "LOWER" | "UPPER" => match arg() {
+            Value::String(value) if name == "LOWER" => Value::String(value.to_lowercase()),
+            Value::String(value) => Value::String(value.to_uppercase()),
+            value if value.is_null_or_missing() => Value::Null,
+            value => return Err(invalid_argument(name, &value)),
+        },
+        "SUBSTRING" => {
+            let (value, start, length) = (arg(), arg(), arg());
+            let start = start.as_number().and_then(|start| integer(&start));
+            let length = match length {
+                Value::Missing => Some(None),
+                length => length
+                    .as_number()
+                    .and_then(|length| integer(&length))
+                    .map(Some),
+            };
+            match (value, start, length) {
+                (Value::String(value), Some(start), Some(length)) => {
+                    Value::String(substring(&value, start, length))
+                }
+                _ => Value::Null,
+            }
+        }
+        _ => {
+            return Err(Error::InvalidRequest(format!(
+                "The function {} is not supported by the local evaluation",
+                name
+            )))
+        }
+    };
+    Ok(value)
+}
+
+fn invalid_argument(name: &str, value: &Value) -> Error {
+    Error::InvalidRecord(format!("Invalid argument for {}: '{}'", name, value))
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int(value) => Some(*value),
+        Value::Float(value) => Some(*value as i64),
+        _ => None,
+    }
+}
+
+/// Timestamp of the argument, the strings are parsed. `None` if the value is null or missing.
+fn timestamp(value: Value) -> Result<Option<DateTime<FixedOffset>>> {
+    match cast(value, DataType::Timestamp)? {
+        Value::Timestamp(timestamp) => Ok(Some(timestamp)),
+        _ => Ok(None),
+    }
+}
+
+/// `SUBSTRING` with a start position from 1. The characters before the first one are counted
+/// in the length.
+pub(crate) fn substring(value: &str, start: i64, length: Option<i64>) -> String {
+    let first = start.max(1);
+    let take = match length {
+        Some(length) => start
+            .saturating_add(length.max(0))
+            .saturating_sub(first)
+            .max(0) as usize,
+        None => usize::MAX,
+    };
+    value.chars().skip(first as usize - 1).take(take).collect()
+}
+
+/// `TRIM`, the spaces are removed by default.
+pub(crate) fn trim(value: &str, spec: Option<TrimSpec>, characters: Option<&str>) -> String {
+    let characters: Vec<char> = characters.unwrap_or(" ").chars().collect();
+    let is_trimmed = |c: char| characters.contains(&c);
+    match spec.unwrap_or(TrimSpec::Both) {
+        TrimSpec::Leading => value.trim_start_matches(is_trimmed).to_string(),
+        TrimSpec::Trailing => value.trim_end_matches(is_trimmed).to_string(),
+        TrimSpec::Both => value.trim_matches(is_trimmed).to_string(),
+    }
+}
+
+/// `CAST(value AS data_type)`, null and missing values stay null.
+pub(crate) fn cast(value: Value, data_type: DataType) -> Result<Value> {
+    if value.is_null_or_missing() {
+        return Ok(Value::Null);
+    }
+    let cast = match (data_type, &value) {
+        (DataType::Bool, Value::Bool(_)) => Some(value.clone()),
+        (DataType::Bool, Value::String(text)) => match text.trim().to_lowercase().as_str() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        (DataType::Bool, Value::Int(number)) => Some(Value::Bool(*number != 0)),
+        (DataType::Int | DataType::Integer, Value::Bool(value)) => Some(Value::Int(*value as i64)),
+        (DataType::Int | DataType::Integer, Value::Float(number)) => {
+            Some(Value::Int(*number as i64))
+        }
+        (DataType::Int | DataType::Integer, _) => match value.as_number() {
+            Some(Value::Float(number)) => Some(Value::Int(number as i64)),
+            number => number,
+        },
+        (DataType::Float | DataType::Decimal | DataType::Numeric, _) => {
+            value.as_f64().map(Value::Float)
+        }
+        (DataType::String, Value::String(_)) => Some(value.clone()),
+        (DataType::String, Value::List(_) | Value::Struct(_)) => None,
+        (DataType::String, _) => Some(Value::String(value.to_string())),
+        (DataType::Timestamp, Value::Timestamp(_)) => Some(value.clone()),
+        (DataType::Timestamp, Value::String(text)) => parse_timestamp(text).map(Value::Timestamp),
+        _ => None,
+    };
+    cast.ok_or_else(|| Error::InvalidRecord(format!("Can't cast '{}' as {}", value, data_type)))
+}
+
+/// Parse the timestamps written like `2021-01-31T10:20:30.5+01:00`. The time, the day and the
+/// month can be omitted: `2021-01-31T`, `2021-01T`, `2021T`. The timezone is UTC by default.
+pub(crate) fn parse_timestamp(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.trim();
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+        return Some(timestamp);
+    }
+    for format in &["%Y-%m-%dT%H:%M%:z", "%Y-%m-%dT%H:%M:%S%.f%:z"] {
+        let text = text.replace('Z', "+00:00");
+        if let Ok(timestamp) = DateTime::parse_from_str(&text, format) {
+            return Some(timestamp);
+        }
+    }
+    if let Ok(timestamp) = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Some(timestamp.and_utc().into());
+    }
+
+    let date = text.strip_suffix('T').unwrap_or(text);
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next().map_or(Some(1), |month| month.parse().ok())?;
+    let day = parts.next().map_or(Some(1), |day| day.parse().ok())?;
+    if date.matches('-').count() < 2 && !text.ends_with('T') {
+        // `2021` or `2021-01` without the `T` are not timestamps.
+        return None;
+    }
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().into())
+}
+
+/// `EXTRACT(part FROM timestamp)`
+pub(crate) fn extract(part: DatePart, value: Value) -> Result<Value> {
+    let timestamp = match timestamp(value)? {
+        Some(timestamp) => timestamp,
+        None => return Ok(Value::Null),
+    };
+    let offset = timestamp.offset().local_minus_utc() / 60;
+    Ok(Value::Int(match part {
+        DatePart::Year => timestamp.year() as i64,
+        DatePart::Month => timestamp.month() as i64,
+        DatePart::Day => timestamp.day() as i64,
+        DatePart::Hour => timestamp.hour() as i64,
+        DatePart::Minute => timestamp.minute() as i64,
+        DatePart::Second => timestamp.second() as i64,
+        DatePart::TimezoneHour => (offset / 60) as i64,
+        DatePart::TimezoneMinute => (offset % 60) as i64,
+    }))
+}
+
+/// `DATE_ADD(part, quantity, timestamp)`
+pub(crate) fn date_add(part: DatePart, quantity: Value, value: Value) -> Result<Value> {
+    let quantity = match quantity.as_number() {
+        Some(Value::Int(quantity)) => quantity,
+        None if quantity.is_null_or_missing() => return Ok(Value::Null),
+        _ => return Err(invalid_argument("DATE_ADD", &quantity)),
+    };
+    match timestamp(value)? {
+        Some(timestamp) => Ok(Value::Timestamp(add(part, quantity, timestamp)?)),
+        None => Ok(Value::Null),
+    }
+}
+
+fn add(
+    part: DatePart,
+    quantity: i64,
+    timestamp: DateTime<FixedOffset>,
+) -> Result<DateTime<FixedOffset>> {
+    let months = |months: i64| {
+        let count = Months::new(months.unsigned_abs().min(u32::MAX as u64) as u32);
+        if months < 0 {
+            timestamp.checked_sub_months(count)
+        } else {
+            timestamp.checked_add_months(count)
+        }
+    };
+    let duration =
+        |duration: Option<Duration>| duration.and_then(|d| timestamp.checked_add_signed(d));
+    let result = match part {
+        DatePart::Year => quantity.checked_mul(12).and_then(months),
+        DatePart::Month => months(quantity),
+        DatePart::Day => duration(Duration::try_days(quantity)),
+        DatePart::Hour => duration(Duration::try_hours(quantity)),
+        DatePart::Minute => duration(Duration::try_minutes(quantity)),
+        DatePart::Second => duration(Duration::try_seconds(quantity)),
+        DatePart::TimezoneHour | DatePart::TimezoneMinute => None,
+    };
+    result.ok_or_else(|| {
+        Error::InvalidRecord(format!("Can't add {} {} to {}", quantity, part, timestamp))
+    })
+}
+
+/// `DATE_DIFF(part, from, to)`, the number of complete date parts between the timestamps.
+pub(crate) fn date_diff(part: DatePart, from: Value, to: Value) -> Result<Value> {
+    match (timestamp(from)?, timestamp(to)?) {
+        (Some(from), Some(to)) => Ok(Value::Int(diff(part, from, to))),
+        _ => Ok(Value::Null),
+    }
+}
+
+fn diff(part: DatePart, from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> i64 {
+    let months = || {
+        let (from, to) = (from.naive_utc(), to.naive_utc());
+        let mut months =
+            (to.year() - from.year()) as i64 * 12 + to.month() as i64 - from.month() as i64;
+        let rest = |date: NaiveDateTime| (date.day(), date.time());
+        if months > 0 && rest(to) < rest(from) {
+            months -= 1;
+        } else if months < 0 && rest(to) > rest(from) {
+            months += 1;
+        }
+        months
+    };
+    let duration = to.signed_duration_since(from);
+    match part {
+        DatePart::Year => months() / 12,
+        DatePart::Month => months(),
+        DatePart::Day => duration.num_days(),
+        DatePart::Hour => duration.num_hours(),
+        DatePart::Minute => duration.num_minutes(),
+        DatePart::Second | DatePart::TimezoneHour | DatePart::TimezoneMinute => {
+            duration.num_seconds()
+        }
+    }
+}
+
+/// Format the timestamp with the patterns of `TO_STRING`, like `yyyy-MM-dd'T'HH:mm:ssXXX`.
+pub(crate) fn format_timestamp(timestamp: &DateTime<FixedOffset>, pattern: &str) -> Result<String> {
+    const MONTHS: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut output = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\'' {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|c| *c == '\'')
+                .map_or(chars.len(), |end| i + 1 + end);
+            output.extend(&chars[i + 1..end]);
+            i = end + 1;
+            continue;
+        }
+        if !c.is_ascii_alphabetic() {
+            output.push(c);
+            i += 1;
+            continue;
+        }
+
+        let count = chars[i..].iter().take_while(|other| **other == c).count();
+        i += count;
+        let number = |value: i64| format!("{:0width$}", value, width = count);
+        let offset = timestamp.offset().local_minus_utc();
+        let text = match c {
+            'y' if count == 2 => format!("{:02}", timestamp.year() % 100),
+            'y' => number(timestamp.year() as i64),
+            'M' if count == 3 => MONTHS[timestamp.month0() as usize][..3].to_string(),
+            'M' if count == 4 => MONTHS[timestamp.month0() as usize].to_string(),
+            'M' if count == 5 => MONTHS[timestamp.month0() as usize][..1].to_string(),
+            'M' => number(timestamp.month() as i64),
+            'd' => number(timestamp.day() as i64),
+            'a' => (if timestamp.hour() < 12 { "AM" } else { "PM" }).to_string(),
+            'h' => number(timestamp.hour12().1 as i64),
+            'H' => number(timestamp.hour() as i64),
+            'm' => number(timestamp.minute() as i64),
+            's' => number(timestamp.second() as i64),
+            'S' => {
+                let nanos = format!("{:09}", timestamp.nanosecond() % 1_000_000_000);
+                format!("{:0<width$}", &nanos[..count.min(9)], width = count)
+            }
+            'n' => timestamp.nanosecond().to_string(),
+            'X' if offset == 0 => "Z".to_string(),
+            'X' | 'x' => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let (hours, minutes) = (offset.abs() / 3600, offset.abs() % 3600 / 60);
+                match count {
+                    1 if minutes == 0 => format!("{}{:02}", sign, hours),
+                    1 | 2 | 4 => format!("{}{:02}{:02}", sign, hours, minutes),
+                    _ => format!("{}{:02}:{:02}", sign, hours, minutes),
+                }
+            }
+            c => {
+                return Err(Error::InvalidRecord(format!(
+                    "Invalid timestamp pattern '{}': unknown letter {}",
+                    pattern, c
+                )))
+            }
+        };
+        output.push_str(&text);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(text: &str) -> DateTime<FixedOffset> {
+        parse_timestamp(text).unwrap()
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(
+            timestamp("2021-01-31T10:20:30+01:00"),
+            timestamp("2021-01-31T09:20:30Z")
+        );
+        assert_eq!(
+            timestamp("2021-01-31T10:20Z"),
+            timestamp("2021-01-31T10:20:00.000Z")
+        );
+        assert_eq!(timestamp("2021T"), timestamp("2021-01-01T00:00:00Z"));
+        assert_eq!(timestamp("2021-03T"), timestamp("2021-03-01T"));
+        assert_eq!(None, parse_timestamp("2021"));
+        assert_eq!(None, parse_timestamp("not a timestamp"));
+    }
+
+    #[test]
+    fn date_functions() {
+        let value = Value::String("2021-01-31T10:20:30.250-05:30".to_string());
+
+        assert_eq!(
+            Value::Int(2021),
+            extract(DatePart::Year, value.clone()).unwrap()
+        );
+        assert_eq!(
+            Value::Int(-30),
+            extract(DatePart::TimezoneMinute, value.clone()).unwrap()
+        );
+        assert_eq!(
+            Value::Timestamp(timestamp("2021-02-28T10:20:30.250-05:30")),
+            date_add(DatePart::Month, Value::Int(1), value.clone()).unwrap()
+        );
+        assert_eq!(
+            Value::Int(11),
+            date_diff(
+                DatePart::Month,
+                Value::String("2020-02-01T".to_string()),
+                value.clone()
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "31 Jan 2021 10:20:30.25 -05:30",
+            format_timestamp(
+                &timestamp("2021-01-31T10:20:30.250-05:30"),
+                "d MMM yyyy HH:mm:ss.SS XXX"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn string_functions() {
+        assert_eq!("bcd", substring("abcdef", 2, Some(3)));
+        assert_eq!("a", substring("abcdef", -1, Some(3)));
+        assert_eq!("def", substring("abcdef", 4, None));
+        assert_eq!("120", trim("00120", Some(TrimSpec::Leading), Some("0")));
+        assert_eq!("x", trim("  x ", None, None));
+    }
+
+    #[test]
+    fn cast_values() {
+        let text = |text: &str| Value::String(text.to_string());
+
+        assert_eq!(Value::Int(12), cast(text(" 12 "), DataType::Int).unwrap());
+        assert_eq!(Value::Int(12), cast(text("12.7"), DataType::Int).unwrap());
+        assert_eq!(
+            Value::Float(1.5),
+            cast(text("1.5"), DataType::Float).unwrap()
+        );
+        assert_eq!(
+            Value::Bool(true),
+            cast(text("TRUE"), DataType::Bool).unwrap()
+        );
+        assert_eq!(
+            text("1.5"),
+            cast(Value::Float(1.5), DataType::String).unwrap()
+        );
+        assert_eq!(Value::Null, cast(Value::Missing, DataType::Int).unwrap());
+        assert!(matches!(
+            cast(text("abc"), DataType::Int),
+            Err(Error::InvalidRecord(_))
+        ));
+    }
+}
@@ -0,0 +1,648 @@
+//! Evaluate a select on the content of an object read locally, when the server can't run it or
+//! to test the queries without a bucket.
+//!
+//! The records are read with the `InputSerialization` of the request and written with its
+//! `OutputSerialization`. The events produced are the events of a select: the `Records` are
+//! followed by the `Stats` and the `End` events.
+//!
+//! ```
+//! # async_std::task::block_on(async {
+//! use futures::TryStreamExt;
+//! use rusoto_s3::{CSVInput, CSVOutput, InputSerialization, OutputSerialization, ScanRange};
+//! use rusoto_s3::SelectObjectContentRequest;
+//! use surf_bucket_select::model::select_object_content::SelectObjectContentEventStreamItem;
+//!
+//! let request = SelectObjectContentRequest {
+//!     expression: "SELECT UPPER(s.name) FROM S3Object s WHERE s.number > 10".to_string(),
+//!     expression_type: "SQL".to_string(),
+//!     input_serialization: InputSerialization {
+//!         csv: Some(CSVInput {
+//!             file_header_info: Some("USE".to_string()),
+//!             ..Default::default()
+//!         }),
+//!         ..Default::default()
+//!     },
+//!     output_serialization: OutputSerialization {
+//!         csv: Some(CSVOutput::default()),
+//!         ..Default::default()
+//!     },
+//!     ..Default::default()
+//! };
+//! let object: &[u8] = b"name,number\na,10\nb,20\n";
+//!
+//! let mut stream = surf_bucket_select::local::select_local(object, &request)?;
+//! while let Some(event) = stream.try_next().await? {
+//!     if let SelectObjectContentEventStreamItem::Records(records) = event {
+//!         assert_eq!(Some(&b"B\n"[..]), records.payload.as_deref());
+//!     }
+//! }
+//! # Ok::<(), surf_bucket_select::Error>(())
+//! # }).unwrap();
+//! ```
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_compression::futures::bufread::{BzDecoder, GzipDecoder};
+use futures::io::{AsyncRead, AsyncReadExt, BufReader};
+use futures::stream::{self, BoxStream};
+use futures::task::{Context, Poll};
+use futures::{StreamExt, TryStreamExt};
+use rusoto_s3::{
+    EndEvent, Progress, ProgressEvent, RecordsEvent, ScanRange, SelectObjectContentRequest, Stats,
+    StatsEvent,
+};
+
+use crate::error::{Error, Result};
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+use crate::request::{COMPRESSION_BZIP2, COMPRESSION_GZIP, COMPRESSION_NONE};
+use crate::schema::FILE_HEADER_USE;
+
+mod accumulator;
+mod eval;
+mod functions;
+#[cfg(feature = "parquet")]
+mod parquet_reader;
+pub(crate) mod reader;
+mod value;
+mod writer;
+
+pub use value::Value;
+
+use eval::{Query, Row};
+use reader::RecordReader;
+use writer::RecordWriter;
+
+/// Size of the chunks read from the object, the records of a chunk are sent in one event.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reader of the decompressed content of the object.
+fn decompress<R: AsyncRead + Unpin + Send + 'static>(
+    reader: R,
+    compression_type: Option<&str>,
+) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let compression_type = compression_type.unwrap_or(COMPRESSION_NONE);
+    if compression_type.eq_ignore_ascii_case(COMPRESSION_NONE) {
+        Ok(Box::new(reader))
+    } else if compression_type.eq_ignore_ascii_case(COMPRESSION_GZIP) {
+        Ok(Box::new(GzipDecoder::new(BufReader::new(reader))))
+    } else if compression_type.eq_ignore_ascii_case(COMPRESSION_BZIP2) {
+        Ok(Box::new(BzDecoder::new(BufReader::new(reader))))
+    } else {
+        Err(Error::InvalidRequest(format!(
+            "The {} compression is not supported by the local evaluation",
+            compression_type
+        )))
+    }
+}
+
+/// Count the bytes read from the object, before the decompression.
+struct CountingReader<R> {
+    reader: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.reader).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = poll {
+            self.count.fetch_add(read as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+/// Run the select of the request on the object content. The bucket and the key are ignored.
+/// The `GZIP` and `BZIP2` objects are decompressed while they are read.
+///
+/// The query is checked and the serializations are validated before the stream is returned,
+/// the records are read while the stream is polled.
+///
+/// With a scan range, the records starting in the range are selected like S3 does: the first
+/// partial record is skipped and the last record is read until its end. The range must have a
+/// start, the last bytes of an object can't be found without its size, and must start at 0 if
+/// the CSV header is used, the header is the first line of the object.
+pub fn select_local<R: AsyncRead + Unpin + Send + 'static>(
+    reader: R,
+    request: &SelectObjectContentRequest,
+) -> Result<BoxStream<'static, Result<SelectObjectContentEventStreamItem>>> {
+    select_local_at(reader, request, 0)
+}
+
+/// Run the select on the content of the object from the `position`, the one given by
+/// [`scan_range_position`] for a ranged read.
+pub(crate) fn select_local_at<R: AsyncRead + Unpin + Send + 'static>(
+    reader: R,
+    request: &SelectObjectContentRequest,
+    position: u64,
+) -> Result<BoxStream<'static, Result<SelectObjectContentEventStreamItem>>> {
+    if request.expression_type != "SQL" {
+        return Err(Error::InvalidRequest(format!(
+            "The expression type '{}' is not supported",
+            request.expression_type
+        )));
+    }
+    crate::request::validate(request)?;
+    let range = match &request.scan_range {
+        Some(scan_range) => Some(RangeFilter::new(request, scan_range, position)?),
+        None => None,
+    };
+
+    let bytes_scanned = Arc::new(AtomicU64::new(0));
+    let reader = decompress(
+        CountingReader {
+            reader,
+            count: bytes_scanned.clone(),
+        },
+        request.input_serialization.compression_type.as_deref(),
+    )?;
+    let query = Query::new(crate::query::parse(&request.expression)?)?;
+    let mut records = RecordReader::new(&request.input_serialization)?;
+    records.project(query.columns());
+
+    let evaluation = Evaluation {
+        reader,
+        records,
+        writer: RecordWriter::new(&request.output_serialization)?,
+        query,
+        progress: request
+            .request_progress
+            .as_ref()
+            .and_then(|progress| progress.enabled)
+            .unwrap_or(false),
+        range,
+        bytes_scanned,
+        bytes_processed: 0,
+        bytes_returned: 0,
+        returned_records: 0,
+        finished: false,
+    };
+
+    Ok(stream::try_unfold(evaluation, |mut evaluation| async move {
+        if evaluation.finished {
+            return Result::Ok(None);
+        }
+        let events = evaluation.next_events().await?;
+        Ok(Some((stream::iter(events.into_iter().map(Ok)), evaluation)))
+    })
+    .try_flatten()
+    .boxed())
+}
+
+struct Evaluation<R> {
+    reader: R,
+    records: RecordReader,
+    writer: RecordWriter,
+    query: Query,
+    progress: bool,
+    range: Option<RangeFilter>,
+    /// Bytes read from the object, the compressed ones for a compressed object.
+    bytes_scanned: Arc<AtomicU64>,
+    bytes_processed: u64,
+    bytes_returned: u64,
+    returned_records: u64,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> Evaluation<R> {
+    /// Read the next chunk and return the events of its records. The object is no more read
+    /// once the limit is reached.
+    async fn next_events(&mut self) -> Result<Vec<SelectObjectContentEventStreamItem>> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = self
+            .reader
+            .read(&mut chunk)
+            .await
+            .map_err(|e| Error::Http(e.into()))?;
+        let mut eof = read == 0;
+        match &mut self.range {
+            Some(range) => {
+                let mut records = Vec::with_capacity(read);
+                eof |= !range.filter(&chunk[..read], &mut records);
+                self.records.push(&records);
+            }
+            None => self.records.push(&chunk[..read]),
+        }
+        self.bytes_processed += read as u64;
+
+        let mut output = Vec::new();
+        let mut limit_reached = self.limit_reached();
+        while !limit_reached {
+            let record = match self.records.next_record(eof)? {
+                Some(record) => record,
+                None => break,
+            };
+            for row in Row::from_record(&record, self.records.header(), self.query.from()) {
+                limit_reached = self.limit_reached();
+                if limit_reached {
+                    break;
+                }
+                if !self.query.matches(&row)? {
+                    continue;
+                }
+                if self.query.is_aggregate() {
+                    self.query.accumulate(&row)?;
+                } else {
+                    self.writer.write(&self.query.project(&row)?, &mut output);
+                    self.returned_records += 1;
+                }
+            }
+        }
+        if eof && self.query.is_aggregate() && !self.limit_reached() {
+            let result = self.query.aggregate_result()?;
+            self.writer.write(&result, &mut output);
+            self.returned_records += 1;
+        }
+        self.bytes_returned += output.len() as u64;
+
+        let mut events = Vec::new();
+        if !output.is_empty() {
+            events.push(SelectObjectContentEventStreamItem::Records(RecordsEvent {
+                payload: Some(output.into()),
+            }));
+        }
+        let bytes_scanned = self.bytes_scanned.load(Ordering::Relaxed);
+        if self.progress && !eof {
+            events.push(SelectObjectContentEventStreamItem::Progress(
+                ProgressEvent {
+                    details: Some(Progress {
+                        bytes_processed: Some(self.bytes_processed as i64),
+                        bytes_returned: Some(self.bytes_returned as i64),
+                        bytes_scanned: Some(bytes_scanned as i64),
+                    }),
+                },
+            ));
+        }
+        if eof || limit_reached {
+            self.finished = true;
+            events.push(SelectObjectContentEventStreamItem::Stats(StatsEvent {
+                details: Some(Stats {
+                    bytes_processed: Some(self.bytes_processed as i64),
+                    bytes_returned: Some(self.bytes_returned as i64),
+                    bytes_scanned: Some(bytes_scanned as i64),
+                }),
+            }));
+            events.push(SelectObjectContentEventStreamItem::End(EndEvent {}));
+        }
+
+        Ok(events)
+    }
+    /// Check if the `LIMIT` is reached, the aggregations return a single record at the end.
+    fn limit_reached(&self) -> bool {
+        self.query
+            .limit()
+            .is_some_and(|limit| self.returned_records >= limit)
+    }
+}
+
+/// Position of the first byte to read for the scan range of the request. The delimiter before
+/// the start is read too, it tells if a record starts at the start of the range.
+pub(crate) fn scan_range_position(request: &SelectObjectContentRequest) -> u64 {
+    let start = request
+        .scan_range
+        .as_ref()
+        .and_then(|scan_range| scan_range.start)
+        .unwrap_or(0)
+        .max(0) as u64;
+    start.saturating_sub(record_delimiter(request).len() as u64)
+}
+
+fn record_delimiter(request: &SelectObjectContentRequest) -> Vec<u8> {
+    request
+        .input_serialization
+        .csv
+        .as_ref()
+        .and_then(|csv| csv.record_delimiter.as_deref())
+        .filter(|delimiter| !delimiter.is_empty())
+        .unwrap_or("\n")
+        .as_bytes()
+        .to_vec()
+}
+
+/// Keep the bytes of the records starting in the scan range: the first partial record is
+/// skipped and the record over the end is read until its delimiter.
+struct RangeFilter {
+    delimiter: Vec<u8>,
+    /// Position in the object of the next byte.
+    position: u64,
+    /// Position of the first byte of the delimiter ending the partial record, if any.
+    search_from: u64,
+    /// Last byte of the range, included.
+    end: Option<u64>,
+    state: RangeState,
+    /// Bytes of the delimiter matched until now.
+    matched: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RangeState {
+    /// Skip the record started before the range.
+    Skip,
+    Read,
+    /// The last record is complete.
+    Done,
+}
+
+impl RangeFilter {
+    fn new(
+        request: &SelectObjectContentRequest,
+        scan_range: &ScanRange,
+        position: u64,
+    ) -> Result<Self> {
+        let start = match (scan_range.start, scan_range.end) {
+            (None, Some(_)) => {
+                return Err(Error::InvalidRequest(
+                    "The scan range of the last bytes is not supported by the local evaluation"
+                        .to_string(),
+                ))
+            }
+            (start, _) => start.unwrap_or(0).max(0) as u64,
+        };
+        let header = request
+            .input_serialization
+            .csv
+            .as_ref()
+            .and_then(|csv| csv.file_header_info.as_deref())
+            .is_some_and(|info| info.eq_ignore_ascii_case(FILE_HEADER_USE));
+        if header && start > 0 {
+            return Err(Error::InvalidRequest(
+                "The scan range must start at 0 to use the header in the local evaluation"
+                    .to_string(),
+            ));
+        }
+
+        let delimiter = record_delimiter(request);
+        Ok(RangeFilter {
+            search_from: start.saturating_sub(delimiter.len() as u64),
+            delimiter,
+            position,
+            end: scan_range.end.map(|end| end.max(0) as u64),
+            state: if start == 0 {
+                RangeState::Read
+            } else {
+                RangeState::Skip
+            },
+            matched: 0,
+        })
+    }
+    /// Append the bytes of the chunk to keep, return `false` once the last record is complete.
+    fn filter(&mut self, chunk: &[u8], output: &mut Vec<u8>) -> bool {
+        for byte in chunk {
+            let position = self.position;
+            self.position += 1;
+            match self.state {
+                RangeState::Skip if position >= self.search_from => {
+                    if self.match_delimiter(*byte) {
+                        self.state = RangeState::Read;
+                    }
+                }
+                RangeState::Skip => (),
+                RangeState::Read => {
+                    output.push(*byte);
+                    if self.match_delimiter(*byte) && self.end.is_some_and(|end| position >= end) {
+                        self.state = RangeState::Done;
+                    }
+                }
+                RangeState::Done => break,
+            }
+        }
+        self.state != RangeState::Done
+    }
+    /// Check if the byte ends a record delimiter.
+    fn match_delimiter(&mut self, byte: u8) -> bool {
+        if byte == self.delimiter[self.matched] {
+            self.matched += 1;
+        } else {
+            self.matched = usize::from(byte == self.delimiter[0]);
+        }
+        if self.matched == self.delimiter.len() {
+            self.matched = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_s3::{CSVInput, CSVOutput, InputSerialization, OutputSerialization, ScanRange};
+
+    async fn select(object: &'static [u8], expression: &str) -> (String, Stats) {
+        let request = SelectObjectContentRequest {
+            expression: expression.to_string(),
+            expression_type: "SQL".to_string(),
+            input_serialization: InputSerialization {
+                csv: Some(CSVInput {
+                    file_header_info: Some("USE".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            output_serialization: OutputSerialization {
+                csv: Some(CSVOutput::default()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let events: Vec<_> = select_local(object, &request)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        let mut records = String::new();
+        let mut stats = None;
+        for event in events {
+            match event {
+                SelectObjectContentEventStreamItem::Records(event) => {
+                    records.push_str(std::str::from_utf8(&event.payload.unwrap()).unwrap())
+                }
+                SelectObjectContentEventStreamItem::Stats(event) => stats = event.details,
+                _ => (),
+            }
+        }
+        (records, stats.unwrap())
+    }
+
+    #[async_std::test]
+    async fn select_csv() {
+        let object = b"name,number\na,10\nb,20\nc,30\n";
+
+        let (records, stats) = select(
+            object,
+            "SELECT s.name, s.number * 2 AS double FROM S3Object s WHERE s.number >= 20",
+        )
+        .await;
+
+        assert_eq!("b,40\nc,60\n", records);
+        assert_eq!(Some(object.len() as i64), stats.bytes_scanned);
+        assert_eq!(Some(records.len() as i64), stats.bytes_returned);
+    }
+
+    #[async_std::test]
+    async fn select_limit() {
+        let (records, _) = select(b"name\na\nb\nc\n", "SELECT * FROM S3Object LIMIT 2").await;
+
+        assert_eq!("a\nb\n", records);
+    }
+
+    #[async_std::test]
+    async fn select_aggregates() {
+        let object = b"name,number,date\na,10,2021-01-01T\nb,20,2021-03-01T\nc,,2021-02-01T\n";
+
+        let (records, _) = select(
+            object,
+            "SELECT COUNT(*), SUM(CAST(NULLIF(s.number, '') AS INT)), AVG(CAST(NULLIF(s.number, '') AS INT)), \
+                MAX(EXTRACT(MONTH FROM TO_TIMESTAMP(s.date))) FROM S3Object s",
+        )
+        .await;
+        assert_eq!("3,30,15,3\n", records);
+
+        let (records, _) = select(
+            object,
+            "SELECT COUNT(s.number) FROM S3Object s WHERE s.name <> 'a'",
+        )
+        .await;
+        assert_eq!("2\n", records);
+    }
+
+    #[async_std::test]
+    async fn select_functions() {
+        let object = b"name,number\n  Alice ,10\nbob,\n";
+
+        let (records, _) = select(
+            object,
+            "SELECT UPPER(TRIM(s.name)), COALESCE(s.number, 'none'), \
+                CASE WHEN CHAR_LENGTH(s.name) > 3 THEN 'long' ELSE 'short' END, \
+                SUBSTRING(s.name FROM 1 FOR 2) FROM S3Object s",
+        )
+        .await;
+
+        assert_eq!("ALICE,10,long,  \nBOB,,short,bo\n", records);
+    }
+
+    #[async_std::test]
+    async fn select_gzip_object() {
+        let mut object = Vec::new();
+        async_compression::futures::bufread::GzipEncoder::new(&b"name\na\nb\n"[..])
+            .read_to_end(&mut object)
+            .await
+            .unwrap();
+        let request = SelectObjectContentRequest {
+            expression: "SELECT * FROM S3Object s WHERE s.name = 'b'".to_string(),
+            expression_type: "SQL".to_string(),
+            input_serialization: InputSerialization {
+                csv: Some(CSVInput {
+                    file_header_info: Some("USE".to_string()),
+                    ..Default::default()
+                }),
+                compression_type: Some("GZIP".to_string()),
+                ..Default::default()
+            },
+            output_serialization: OutputSerialization {
+                csv: Some(CSVOutput::default()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let compressed_size = object.len() as i64;
+        let events: Vec<_> = select_local(futures::io::Cursor::new(object), &request)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            &events[0],
+            SelectObjectContentEventStreamItem::Records(records)
+                if records.payload.as_deref() == Some(&b"b\n"[..])
+        ));
+        // The bytes read from the object are scanned, the decompressed ones are processed.
+        match &events[1] {
+            SelectObjectContentEventStreamItem::Stats(stats) => {
+                let stats = stats.details.as_ref().unwrap();
+                assert_eq!(Some(compressed_size), stats.bytes_scanned);
+                assert_eq!(Some(9), stats.bytes_processed);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    fn scan_range_request(start: Option<i64>, end: Option<i64>) -> SelectObjectContentRequest {
+        SelectObjectContentRequest {
+            expression: "SELECT * FROM S3Object s".to_string(),
+            expression_type: "SQL".to_string(),
+            input_serialization: InputSerialization {
+                csv: Some(CSVInput::default()),
+                ..Default::default()
+            },
+            output_serialization: OutputSerialization {
+                csv: Some(CSVOutput::default()),
+                ..Default::default()
+            },
+            scan_range: Some(ScanRange { start, end }),
+            ..Default::default()
+        }
+    }
+
+    async fn select_range(object: &'static [u8], position: u64, start: i64, end: i64) -> String {
+        let request = scan_range_request(Some(start), Some(end));
+        assert_eq!(position, scan_range_position(&request));
+        let events: Vec<_> = select_local_at(&object[position as usize..], &request, position)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                SelectObjectContentEventStreamItem::Records(event) => {
+                    Some(String::from_utf8(event.payload.unwrap().to_vec()).unwrap())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn select_scan_range() {
+        let object = b"aa\nbb\ncc\ndd\n";
+
+        // The records starting in the range, the last one is read until its end.
+        assert_eq!("aa\nbb\n", select_range(object, 0, 0, 3).await);
+        // The partial first record is skipped.
+        assert_eq!("bb\ncc\n", select_range(object, 0, 1, 6).await);
+        // The record starting at the start of the range is kept.
+        assert_eq!("bb\n", select_range(object, 2, 3, 4).await);
+        assert_eq!("dd\n", select_range(object, 8, 9, 100).await);
+    }
+
+    #[test]
+    fn reject_scan_range() {
+        // The last bytes of the object.
+        assert!(matches!(
+            select_local(&b""[..], &scan_range_request(None, Some(1024))),
+            Err(Error::InvalidRequest(_))
+        ));
+
+        let mut request = scan_range_request(Some(10), None);
+        request.input_serialization.csv = Some(CSVInput {
+            file_header_info: Some("USE".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            select_local(&b""[..], &request),
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+//! Read the rows of a Parquet object, behind the `parquet` feature.
+
+use std::convert::TryFrom;
+
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDate, Utc};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::reader::RowIter;
+use parquet::record::{Field, Row};
+use parquet::schema::types::Type;
+
+use crate::error::{Error, Result};
+use crate::query::ast::Identifier;
+
+use super::value::Value;
+
+/// Reader of the rows of a Parquet object. The footer is at the end of the object, so the
+/// object is kept in memory and the row groups are read once it's completely received.
+#[derive(Default)]
+pub(crate) struct ParquetReader {
+    data: Vec<u8>,
+    columns: Option<Vec<Identifier>>,
+    rows: Option<RowIter<'static>>,
+}
+
+impl ParquetReader {
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+    }
+    /// Read only the columns of the query. `None` reads all the columns.
+    pub(crate) fn project(&mut self, columns: Option<Vec<Identifier>>) {
+        self.columns = columns;
+    }
+    pub(crate) fn next_record(&mut self, eof: bool) -> Result<Option<Value>> {
+        if !eof {
+            return Ok(None);
+        }
+        if self.rows.is_none() {
+            let rows = self.open()?;
+            self.rows = Some(rows);
+        }
+        match self.rows.as_mut().and_then(Iterator::next) {
+            Some(Ok(row)) => Ok(Some(row_value(row))),
+            Some(Err(e)) => Err(Error::InvalidRecord(e.to_string())),
+            None => Ok(None),
+        }
+    }
+    fn open(&mut self) -> Result<RowIter<'static>> {
+        let data = Bytes::from(std::mem::take(&mut self.data));
+        let reader =
+            SerializedFileReader::new(data).map_err(|e| Error::InvalidRecord(e.to_string()))?;
+
+        let schema = reader.metadata().file_metadata().schema();
+        let projection = match &self.columns {
+            Some(columns) => {
+                let mut fields: Vec<_> = schema
+                    .get_fields()
+                    .iter()
+                    .filter(|field| columns.iter().any(|column| column.matches(field.name())))
+                    .cloned()
+                    .collect();
+                // At least one column is read to count the rows.
+                if fields.is_empty() {
+                    fields.extend(schema.get_fields().first().cloned());
+                }
+                Some(
+                    Type::group_type_builder(schema.name())
+                        .with_fields(fields)
+                        .build()
+                        .map_err(|e| Error::InvalidRecord(e.to_string()))?,
+                )
+            }
+            None => None,
+        };
+
+        RowIter::from_file_into(Box::new(reader))
+            .project(projection)
+            .map_err(|e| Error::InvalidRecord(e.to_string()))
+    }
+}
+
+fn row_value(row: Row) -> Value {
+    Value::Struct(
+        row.into_columns()
+            .into_iter()
+            .map(|(name, field)| (name, field_value(field)))
+            .collect(),
+    )
+}
+
+fn field_value(field: Field) -> Value {
+    let timestamp = |timestamp: Option<DateTime<Utc>>| {
+        timestamp.map_or(Value::Null, |timestamp| Value::Timestamp(timestamp.into()))
+    };
+    match field {
+        Field::Null => Value::Null,
+        Field::Bool(value) => Value::Bool(value),
+        Field::Byte(value) => Value::Int(value.into()),
+        Field::Short(value) => Value::Int(value.into()),
+        Field::Int(value) => Value::Int(value.into()),
+        Field::Long(value) => Value::Int(value),
+        Field::UByte(value) => Value::Int(value.into()),
+        Field::UShort(value) => Value::Int(value.into()),
+        Field::UInt(value) => Value::Int(value.into()),
+        Field::ULong(value) => i64::try_from(value).map_or(Value::Float(value as f64), Value::Int),
+        Field::Float(value) => Value::Float(value.into()),
+        Field::Double(value) => Value::Float(value),
+        Field::Str(value) => Value::String(value),
+        Field::Date(days) => timestamp(
+            NaiveDate::from_ymd_opt(1970, 1, 1)
+                .and_then(|epoch| epoch.checked_add_signed(chrono::Duration::days(days.into())))
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date| date.and_utc()),
+        ),
+        Field::TimestampMillis(millis) => timestamp(DateTime::from_timestamp_millis(millis)),
+        Field::TimestampMicros(micros) => timestamp(DateTime::from_timestamp_micros(micros)),
+        Field::Group(row) => row_value(row),
+        Field::ListInternal(list) => {
+            Value::List(list.elements().iter().cloned().map(field_value).collect())
+        }
+        Field::MapInternal(map) => Value::Struct(
+            map.entries()
+                .iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Field::Str(key) => key.clone(),
+                        key => key.to_string(),
+                    };
+                    (key, field_value(value.clone()))
+                })
+                .collect(),
+        ),
+        // Decimals, binaries and times are returned as text.
+        field => Value::String(field.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::TryStreamExt;
+    use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use rusoto_s3::{JSONOutput, SelectObjectContentRequest};
+
+    use crate::local::select_local;
+    use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+    use crate::request::SelectRequest;
+
+    fn parquet_object() -> Vec<u8> {
+        let schema = parse_message_type(
+            "message schema { REQUIRED BYTE_ARRAY name (UTF8); REQUIRED INT64 size; }",
+        )
+        .unwrap();
+        let mut object = Vec::new();
+        let mut writer = SerializedFileWriter::new(
+            &mut object,
+            Arc::new(schema),
+            Arc::new(WriterProperties::builder().build()),
+        )
+        .unwrap();
+
+        let mut row_group = writer.next_row_group().unwrap();
+        let mut column = row_group.next_column().unwrap().unwrap();
+        let names: Vec<ByteArray> = vec!["a".into(), "b".into(), "c".into()];
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&names, None, None)
+            .unwrap();
+        column.close().unwrap();
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<Int64Type>()
+            .write_batch(&[10, 20, 30], None, None)
+            .unwrap();
+        column.close().unwrap();
+        row_group.close().unwrap();
+        writer.close().unwrap();
+
+        object
+    }
+
+    async fn select(request: SelectObjectContentRequest) -> String {
+        select_object(parquet_object(), request).await
+    }
+
+    async fn select_object(object: Vec<u8>, request: SelectObjectContentRequest) -> String {
+        let object = futures::io::Cursor::new(object);
+        let events: Vec<_> = select_local(object, &request)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                SelectObjectContentEventStreamItem::Records(records) => {
+                    Some(String::from_utf8(records.payload.unwrap().to_vec()).unwrap())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn select_parquet() {
+        let request = SelectRequest::new(
+            "my-bucket",
+            "data/part-0.parquet",
+            "SELECT s.name FROM S3Object s WHERE s.size > 10",
+        )
+        .parquet_input()
+        .json_output(JSONOutput::default())
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            "{\"name\":\"b\"}\n{\"name\":\"c\"}\n",
+            select(request).await
+        );
+    }
+
+    #[async_std::test]
+    async fn select_parquet_aggregate() {
+        let request = SelectRequest::new(
+            "my-bucket",
+            "data/part-0.parquet",
+            "SELECT COUNT(*), SUM(s.size) FROM S3Object s",
+        )
+        .parquet_input()
+        .build()
+        .unwrap();
+
+        assert_eq!("3,60\n", select(request).await);
+    }
+
+    #[async_std::test]
+    async fn select_parquet_file() {
+        let request = SelectRequest::new(
+            "my-bucket",
+            "data/multi_lines.parquet",
+            "SELECT s.number, s.string, s.\"date\" FROM S3Object s WHERE s.round > 10.15",
+        )
+        .parquet_input()
+        .json_output(JSONOutput::default())
+        .build()
+        .unwrap();
+        let object = include_bytes!("../../data/multi_lines.parquet").to_vec();
+
+        assert_eq!(
+            "{\"number\":10,\"string\":\"value to test\",\"date\":\"2019-12-31T00:00:00Z\"}\n\
+             {\"number\":30,\"string\":\"value to test 3\",\"date\":\"2018-12-31T00:00:00Z\"}\n",
+            select_object(object, request).await
+        );
+    }
+}
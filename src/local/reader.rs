@@ -0,0 +1,683 @@
+//! Split the object into records with the `InputSerialization` of the request.
+
+#[cfg(feature = "arrow")]
+use rusoto_s3::OutputSerialization;
+use rusoto_s3::{CSVInput, InputSerialization, JSONInput};
+
+use crate::error::{Error, Result};
+use crate::query::ast::Identifier;
+use crate::schema::{first_char, FILE_HEADER_USE};
+
+#[cfg(feature = "parquet")]
+use super::parquet_reader::ParquetReader;
+#[cfg(feature = "parquet")]
+use super::value::Value;
+
+/// Record read from the object.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Record {
+    Csv(Vec<String>),
+    Json(serde_json::Value),
+    #[cfg(feature = "parquet")]
+    Parquet(Value),
+}
+
+/// Reader of the records of a CSV or JSON object. The data is pushed chunk by chunk and the
+/// complete records are read as soon as they are received.
+pub(crate) enum RecordReader {
+    Csv(CsvReader),
+    Json(JsonReader),
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetReader),
+}
+
+impl RecordReader {
+    /// Reader of the decompressed content of the object.
+    pub(crate) fn new(input_serialization: &InputSerialization) -> Result<Self> {
+        match (
+            &input_serialization.csv,
+            &input_serialization.json,
+            &input_serialization.parquet,
+        ) {
+            (Some(csv), None, None) => Ok(RecordReader::Csv(CsvReader::new(csv)?)),
+            (None, Some(json), None) => Ok(RecordReader::Json(JsonReader::new(json))),
+            #[cfg(feature = "parquet")]
+            (None, None, Some(_)) => Ok(RecordReader::Parquet(ParquetReader::default())),
+            #[cfg(not(feature = "parquet"))]
+            (None, None, Some(_)) => Err(Error::InvalidRequest(
+                "The local evaluation of Parquet objects needs the `parquet` feature".to_string(),
+            )),
+            _ => Err(Error::InvalidRequest(
+                "The local evaluation needs either a CSV, a JSON or a Parquet input".to_string(),
+            )),
+        }
+    }
+    /// Reader of the records returned by a select with the output serialization. The CSV
+    /// records have no header and no comments, an empty line is a record with an empty field.
+    #[cfg(feature = "arrow")]
+    pub(crate) fn for_output(output_serialization: &OutputSerialization) -> Result<Self> {
+        match (&output_serialization.csv, &output_serialization.json) {
+            (Some(csv), None) => {
+                let mut reader = CsvReader::new(&CSVInput {
+                    field_delimiter: csv.field_delimiter.clone(),
+                    quote_character: csv.quote_character.clone(),
+                    quote_escape_character: csv.quote_escape_character.clone(),
+                    record_delimiter: csv.record_delimiter.clone(),
+                    allow_quoted_record_delimiter: Some(true),
+                    ..Default::default()
+                })?;
+                reader.comments = None;
+                reader.empty_records = true;
+                Ok(RecordReader::Csv(reader))
+            }
+            (None, Some(json)) => Ok(RecordReader::Json(JsonReader {
+                buffer: Buffer::default(),
+                record_delimiter: Some(
+                    json.record_delimiter
+                        .as_deref()
+                        .filter(|delimiter| !delimiter.is_empty())
+                        .unwrap_or("\n")
+                        .as_bytes()
+                        .to_vec(),
+                ),
+                scanner: ValueScanner::default(),
+            })),
+            _ => Err(Error::InvalidRequest(
+                "The records can only be read with either a CSV or a JSON output".to_string(),
+            )),
+        }
+    }
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        let buffer = match self {
+            RecordReader::Csv(reader) => &mut reader.buffer,
+            RecordReader::Json(reader) => &mut reader.buffer,
+            #[cfg(feature = "parquet")]
+            RecordReader::Parquet(reader) => return reader.push(chunk),
+        };
+        buffer.push(chunk);
+    }
+    /// Read only the columns used by the query, when the format allows it.
+    pub(crate) fn project(&mut self, columns: Option<Vec<Identifier>>) {
+        #[cfg(feature = "parquet")]
+        if let RecordReader::Parquet(reader) = self {
+            reader.project(columns);
+        }
+        #[cfg(not(feature = "parquet"))]
+        let _ = columns;
+    }
+    /// Read the next complete record. When `eof` is set, the remaining data is the last record.
+    pub(crate) fn next_record(&mut self, eof: bool) -> Result<Option<Record>> {
+        match self {
+            RecordReader::Csv(reader) => Ok(reader.next_record(eof).map(Record::Csv)),
+            RecordReader::Json(reader) => Ok(reader.next_record(eof)?.map(Record::Json)),
+            #[cfg(feature = "parquet")]
+            RecordReader::Parquet(reader) => Ok(reader.next_record(eof)?.map(Record::Parquet)),
+        }
+    }
+    /// Names of the columns read from the first line of a CSV object.
+    pub(crate) fn header(&self) -> Option<&[String]> {
+        match self {
+            RecordReader::Csv(reader) => reader.header.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Received data not yet read.
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Buffer {
+    fn push(&mut self, chunk: &[u8]) {
+        if self.position > 0 {
+            self.data.drain(..self.position);
+            self.position = 0;
+        }
+        self.data.extend_from_slice(chunk);
+    }
+    fn remaining(&self) -> &[u8] {
+        &self.data[self.position..]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HeaderInfo {
+    Use,
+    Ignore,
+    None,
+}
+
+pub(crate) struct CsvReader {
+    buffer: Buffer,
+    record_delimiter: Vec<u8>,
+    field_delimiter: char,
+    quote: char,
+    quote_escape: char,
+    comments: Option<char>,
+    /// The empty lines are records instead of being skipped.
+    empty_records: bool,
+    quoted_record_delimiter: bool,
+    header_info: HeaderInfo,
+    header: Option<Vec<String>>,
+    first_record: bool,
+    scanner: LineScanner,
+}
+
+/// Scan of the next line, kept between the chunks so the bytes are scanned once.
+#[derive(Default)]
+struct LineScanner {
+    offset: usize,
+    in_quotes: bool,
+    escaped: bool,
+}
+
+impl CsvReader {
+    fn new(input: &CSVInput) -> Result<Self> {
+        let quote = first_char(&input.quote_character, '"');
+        if !quote.is_ascii() {
+            return Err(Error::InvalidRequest(format!(
+                "The quote character '{}' is not supported by the local evaluation",
+                quote
+            )));
+        }
+        let header_info = match input.file_header_info.as_deref() {
+            Some(info) if info.eq_ignore_ascii_case(FILE_HEADER_USE) => HeaderInfo::Use,
+            Some(info) if info.eq_ignore_ascii_case("IGNORE") => HeaderInfo::Ignore,
+            _ => HeaderInfo::None,
+        };
+
+        Ok(CsvReader {
+            buffer: Buffer::default(),
+            record_delimiter: input
+                .record_delimiter
+                .as_deref()
+                .filter(|delimiter| !delimiter.is_empty())
+                .unwrap_or("\n")
+                .as_bytes()
+                .to_vec(),
+            field_delimiter: first_char(&input.field_delimiter, ','),
+            quote,
+            quote_escape: first_char(&input.quote_escape_character, quote),
+            comments: Some(first_char(&input.comments, '#')),
+            empty_records: false,
+            quoted_record_delimiter: input.allow_quoted_record_delimiter.unwrap_or(false),
+            header_info,
+            header: None,
+            first_record: true,
+            scanner: LineScanner::default(),
+        })
+    }
+    fn next_record(&mut self, eof: bool) -> Option<Vec<String>> {
+        loop {
+            let line = self.next_line(eof)?;
+            let line = if self.record_delimiter == b"\n" {
+                line.trim_end_matches('\r')
+            } else {
+                line.as_str()
+            };
+            let comment = self
+                .comments
+                .is_some_and(|comments| line.starts_with(comments));
+            if (line.is_empty() && !self.empty_records) || comment {
+                continue;
+            }
+
+            let fields = split_fields(line, self.field_delimiter, self.quote, self.quote_escape);
+            if self.first_record {
+                self.first_record = false;
+                match self.header_info {
+                    HeaderInfo::Use => {
+                        self.header = Some(fields);
+                        continue;
+                    }
+                    HeaderInfo::Ignore => continue,
+                    HeaderInfo::None => (),
+                }
+            }
+            return Some(fields);
+        }
+    }
+    /// Read until the next record delimiter, skipped inside the quotes if it's allowed.
+    fn next_line(&mut self, eof: bool) -> Option<String> {
+        let data = self.buffer.remaining();
+        if data.is_empty() {
+            return None;
+        }
+
+        let quote = self.quote as u8;
+        // The escaped quotes don't end the quoted fields, a doubled quote flips them twice.
+        let escape = Some(self.quote_escape)
+            .filter(|escape| *escape != self.quote && escape.is_ascii())
+            .map(|escape| escape as u8);
+        let scanner = &mut self.scanner;
+        let mut end = None;
+        while scanner.offset < data.len() {
+            let rest = &data[scanner.offset..];
+            if !self.quoted_record_delimiter || !scanner.in_quotes {
+                if rest.starts_with(&self.record_delimiter) {
+                    end = Some(scanner.offset);
+                    break;
+                }
+                // The delimiter can end in the next chunk.
+                if !eof && self.record_delimiter.starts_with(rest) {
+                    break;
+                }
+            }
+            if self.quoted_record_delimiter {
+                if scanner.escaped {
+                    scanner.escaped = false;
+                } else if scanner.in_quotes && Some(rest[0]) == escape {
+                    scanner.escaped = true;
+                } else if rest[0] == quote {
+                    scanner.in_quotes = !scanner.in_quotes;
+                }
+            }
+            scanner.offset += 1;
+        }
+
+        let (line, consumed) = match end {
+            Some(end) => (&data[..end], end + self.record_delimiter.len()),
+            None if eof => (data, data.len()),
+            None => return None,
+        };
+        let line = String::from_utf8_lossy(line).into_owned();
+        self.buffer.position += consumed;
+        self.scanner = LineScanner::default();
+        Some(line)
+    }
+}
+
+/// Split a CSV record into its fields. Inside a quoted field, the escape character followed by a
+/// quote is a quote, like a doubled quote.
+pub(crate) fn split_fields(
+    record: &str,
+    delimiter: char,
+    quote: char,
+    escape: char,
+) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if (c == escape || c == quote) && chars.peek() == Some(&quote) {
+                field.push(quote);
+                chars.next();
+            } else if c == quote {
+                in_quotes = false;
+            } else {
+                field.push(c);
+            }
+        } else if c == quote {
+            in_quotes = true;
+        } else if c == delimiter {
+            fields.push(std::mem::take(&mut field));
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+
+    fields
+}
+
+/// Reader of the JSON values of a `DOCUMENT`, or of the lines of `LINES`.
+pub(crate) struct JsonReader {
+    buffer: Buffer,
+    /// The record delimiter of `LINES`, `None` for a `DOCUMENT`.
+    record_delimiter: Option<Vec<u8>>,
+    scanner: ValueScanner,
+}
+
+impl JsonReader {
+    fn new(input: &JSONInput) -> Self {
+        let lines = input
+            .type_
+            .as_deref()
+            .is_some_and(|type_| type_.eq_ignore_ascii_case("LINES"));
+        JsonReader {
+            buffer: Buffer::default(),
+            record_delimiter: lines.then(|| b"\n".to_vec()),
+            scanner: ValueScanner::default(),
+        }
+    }
+    fn next_record(&mut self, eof: bool) -> Result<Option<serde_json::Value>> {
+        loop {
+            let data = self.buffer.remaining();
+            let next = match &self.record_delimiter {
+                Some(delimiter) => self.scanner.line(data, delimiter),
+                None => self.scanner.value(data),
+            };
+            let (end, consumed) = match next {
+                Some(next) => next,
+                None if eof => (data.len(), data.len()),
+                None => return Ok(None),
+            };
+            if consumed == 0 {
+                return Ok(None);
+            }
+
+            let record = &data[..end];
+            let value = match record.iter().all(u8::is_ascii_whitespace) {
+                true => None,
+                false => Some(
+                    serde_json::from_slice(record)
+                        .map_err(|e| Error::InvalidRecord(e.to_string()))?,
+                ),
+            };
+            self.buffer.position += consumed;
+            self.scanner = ValueScanner::default();
+            if value.is_some() {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// Find the end of the next JSON value, or of the next line. The state is kept between the
+/// chunks, so the received data is scanned once and each record is parsed once complete.
+#[derive(Default)]
+struct ValueScanner {
+    /// Bytes of the remaining data already scanned.
+    offset: usize,
+    depth: usize,
+    started: bool,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl ValueScanner {
+    /// The end of the next value and the bytes it takes, with the whitespace ending a scalar.
+    fn value(&mut self, data: &[u8]) -> Option<(usize, usize)> {
+        while let Some(byte) = data.get(self.offset).copied() {
+            self.offset += 1;
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                    if self.depth == 0 {
+                        return Some((self.offset, self.offset));
+                    }
+                }
+                continue;
+            }
+            match byte {
+                b'"' => {
+                    self.started = true;
+                    self.in_string = true;
+                }
+                b'{' | b'[' => {
+                    self.started = true;
+                    self.depth += 1;
+                }
+                b'}' | b']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return Some((self.offset, self.offset));
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => {
+                    if self.started && self.depth == 0 {
+                        return Some((self.offset - 1, self.offset));
+                    }
+                }
+                _ => self.started = true,
+            }
+        }
+        None
+    }
+    /// The end of the next line and the bytes it takes with its delimiter.
+    fn line(&mut self, data: &[u8], delimiter: &[u8]) -> Option<(usize, usize)> {
+        match data[self.offset..]
+            .windows(delimiter.len())
+            .position(|window| window == delimiter)
+        {
+            Some(position) => {
+                let end = self.offset + position;
+                Some((end, end + delimiter.len()))
+            }
+            None => {
+                // The end of the data can be the start of a delimiter.
+                self.offset = data
+                    .len()
+                    .saturating_sub(delimiter.len() - 1)
+                    .max(self.offset);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(reader: &mut RecordReader, chunks: &[&[u8]]) -> Vec<Record> {
+        let mut records = Vec::new();
+        for chunk in chunks {
+            reader.push(chunk);
+            while let Some(record) = reader.next_record(false).unwrap() {
+                records.push(record);
+            }
+        }
+        while let Some(record) = reader.next_record(true).unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn csv(fields: &[&str]) -> Record {
+        Record::Csv(fields.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn read_csv_chunks() {
+        let mut reader = RecordReader::new(&InputSerialization {
+            csv: Some(CSVInput {
+                file_header_info: Some("USE".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let records = read_all(
+            &mut reader,
+            &[
+                b"number,string\r\n# comment\n1,\"a,",
+                b"b\"\n\n2,\"it\"\"s\"\n3,c",
+            ],
+        );
+
+        assert_eq!(
+            vec![csv(&["1", "a,b"]), csv(&["2", "it\"s"]), csv(&["3", "c"])],
+            records
+        );
+        assert_eq!(
+            Some(&["number".to_string(), "string".to_string()][..]),
+            reader.header()
+        );
+    }
+
+    #[test]
+    fn read_csv_quoted_record_delimiter() {
+        let mut reader = RecordReader::new(&InputSerialization {
+            csv: Some(CSVInput {
+                allow_quoted_record_delimiter: Some(true),
+                field_delimiter: Some(";".to_string()),
+                quote_escape_character: Some("\\".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let records = read_all(&mut reader, &[b"\"multi\nline \\\"quoted\\\"\";1\n"]);
+
+        assert_eq!(vec![csv(&["multi\nline \"quoted\"", "1"])], records);
+    }
+
+    #[test]
+    fn read_csv_escaped_quote() {
+        let mut reader = RecordReader::new(&InputSerialization {
+            csv: Some(CSVInput {
+                allow_quoted_record_delimiter: Some(true),
+                field_delimiter: Some(";".to_string()),
+                quote_escape_character: Some("\\".to_string()),
+                record_delimiter: Some("\r\n".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let records = read_all(&mut reader, &[b"\"a \\\"b\r", b"\nc\";1\r", b"\nd;2\r\n"]);
+
+        assert_eq!(vec![csv(&["a \"b\r\nc", "1"]), csv(&["d", "2"])], records);
+    }
+
+    #[test]
+    fn read_large_csv_record() {
+        let mut object = b"\"".to_vec();
+        object.extend(b"a,\n".repeat(1024 * 1024));
+        object.extend(b"\",1\n2,3\n");
+
+        let mut reader = RecordReader::new(&InputSerialization {
+            csv: Some(CSVInput {
+                allow_quoted_record_delimiter: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        let chunks: Vec<&[u8]> = object.chunks(1024).collect();
+        let records = read_all(&mut reader, &chunks);
+
+        assert_eq!(2, records.len());
+        assert_eq!(csv(&["2", "3"]), records[1]);
+    }
+
+    #[test]
+    fn read_json_chunks() {
+        let mut reader = RecordReader::new(&InputSerialization {
+            json: Some(JSONInput::default()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let records = read_all(&mut reader, &[b"{\"a\":1}\n{\"a\":", b"[2, 3]}\n"]);
+
+        assert_eq!(
+            vec![
+                Record::Json(serde_json::json!({"a": 1})),
+                Record::Json(serde_json::json!({"a": [2, 3]})),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn read_json_lines() {
+        let mut reader = RecordReader::new(&InputSerialization {
+            json: Some(JSONInput {
+                type_: Some("LINES".to_string()),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let records = read_all(&mut reader, &[b"{\"a\":\"}\\n\"}\r\n\n{\"a\"", b":2}\n3"]);
+
+        assert_eq!(
+            vec![
+                Record::Json(serde_json::json!({"a": "}\n"})),
+                Record::Json(serde_json::json!({"a": 2})),
+                Record::Json(serde_json::json!(3)),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn read_large_json_document() {
+        let mut document = b"{\"Records\":[".to_vec();
+        for i in 0..100_000 {
+            if i > 0 {
+                document.push(b',');
+            }
+            document.extend(format!("{{\"id\":{},\"name\":\"a \\\"]}}\"}}", i).as_bytes());
+        }
+        document.extend(b"]} 1");
+        assert!(document.len() > 2 * 1024 * 1024);
+
+        let mut reader = RecordReader::new(&InputSerialization {
+            json: Some(JSONInput {
+                type_: Some("DOCUMENT".to_string()),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut records = Vec::new();
+        for chunk in document.chunks(64 * 1024) {
+            reader.push(chunk);
+            while let Some(record) = reader.next_record(false).unwrap() {
+                records.push(record);
+            }
+        }
+        assert_eq!(1, records.len());
+        while let Some(record) = reader.next_record(true).unwrap() {
+            records.push(record);
+        }
+
+        match &records[0] {
+            Record::Json(value) => {
+                let values = value["Records"].as_array().unwrap();
+                assert_eq!(100_000, values.len());
+                assert_eq!(
+                    serde_json::json!({"id": 99_999, "name": "a \"]}"}),
+                    values[99_999]
+                );
+            }
+            record => panic!("{:?}", record),
+        }
+        assert_eq!(Record::Json(serde_json::json!(1)), records[1]);
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn read_csv_output() {
+        let mut reader = RecordReader::for_output(&OutputSerialization {
+            csv: Some(rusoto_s3::CSVOutput::default()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![csv(&["#1", "a\nb"]), csv(&[""]), csv(&["2", "c"])],
+            read_all(&mut reader, &[b"#1,\"a\nb\"\n\n2,", b"c\n"])
+        );
+    }
+
+    #[test]
+    fn read_invalid_json() {
+        let mut reader = RecordReader::new(&InputSerialization {
+            json: Some(JSONInput::default()),
+            ..Default::default()
+        })
+        .unwrap();
+        reader.push(b"{\"a\":}");
+
+        assert!(matches!(
+            reader.next_record(false),
+            Err(Error::InvalidRecord(_))
+        ));
+    }
+}
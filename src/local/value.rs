@@ -0,0 +1,152 @@
+//! Values handled by the local evaluation.
+
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use serde_json::Number;
+
+/// Value of a column or of an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// The path doesn't exist in the record.
+    Missing,
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Timestamp(DateTime<FixedOffset>),
+    List(Vec<Value>),
+    /// Fields of a JSON object, in the order of the document.
+    Struct(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn is_null_or_missing(&self) -> bool {
+        matches!(self, Value::Null | Value::Missing)
+    }
+    /// Number read from the value, the CSV fields are strings converted on comparison.
+    pub(crate) fn as_number(&self) -> Option<Value> {
+        match self {
+            Value::Int(_) | Value::Float(_) => Some(self.clone()),
+            Value::String(value) => {
+                let value = value.trim();
+                value
+                    .parse()
+                    .map(Value::Int)
+                    .or_else(|_| value.parse().map(Value::Float))
+                    .ok()
+            }
+            _ => None,
+        }
+    }
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self.as_number()? {
+            Value::Int(value) => Some(value as f64),
+            Value::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+    /// Compare two values of compatible types. Numbers written in strings are compared as
+    /// numbers when the other value is a number.
+    pub(crate) fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Timestamp(a), Value::Timestamp(b)) => Some(a.cmp(b)),
+            (Value::Int(_), _)
+            | (Value::Float(_), _)
+            | (_, Value::Int(_))
+            | (_, Value::Float(_)) => match (self.as_number()?, other.as_number()?) {
+                (Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+                (a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+            },
+            _ => None,
+        }
+    }
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Missing | Value::Null => serde_json::Value::Null,
+            Value::Bool(value) => serde_json::Value::Bool(*value),
+            Value::Int(value) => serde_json::Value::Number((*value).into()),
+            Value::Float(value) => Number::from_f64(*value)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            Value::String(value) => serde_json::Value::String(value.clone()),
+            Value::Timestamp(_) => serde_json::Value::String(self.to_string()),
+            Value::List(values) => {
+                serde_json::Value::Array(values.iter().map(Value::to_json).collect())
+            }
+            Value::Struct(fields) => serde_json::Value::Object(
+                fields
+                    .iter()
+                    .filter(|(_, value)| *value != Value::Missing)
+                    .map(|(name, value)| (name.clone(), value.to_json()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Value {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Bool(value),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(value) => Value::Int(value),
+                None => Value::Float(number.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(value) => Value::String(value),
+            serde_json::Value::Array(values) => {
+                Value::List(values.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(fields) => Value::Struct(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name, Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Text of the value in a CSV field.
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Missing | Value::Null => Ok(()),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Timestamp(value) => {
+                write!(f, "{}", value.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            Value::List(_) | Value::Struct(_) => write!(f, "{}", self.to_json()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_csv_field_with_number() {
+        let field = Value::String("20".to_string());
+
+        assert_eq!(Some(Ordering::Equal), field.compare(&Value::Int(20)));
+        assert_eq!(Some(Ordering::Less), field.compare(&Value::Float(20.5)));
+        assert_eq!(
+            Some(Ordering::Greater),
+            Value::String("abc".to_string()).compare(&Value::String("abb".to_string()))
+        );
+        assert_eq!(
+            None,
+            Value::String("abc".to_string()).compare(&Value::Int(1))
+        );
+    }
+}
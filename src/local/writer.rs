@@ -0,0 +1,170 @@
+//! Write the projected rows with the `OutputSerialization` of the request.
+
+use rusoto_s3::{CSVOutput, OutputSerialization};
+
+use crate::error::{Error, Result};
+use crate::schema::first_char;
+
+use super::value::Value;
+
+/// Writer of the records returned by the local evaluation.
+pub(crate) enum RecordWriter {
+    Csv {
+        record_delimiter: String,
+        field_delimiter: String,
+        quote: char,
+        quote_escape: char,
+        always_quote: bool,
+    },
+    Json {
+        record_delimiter: String,
+    },
+}
+
+impl RecordWriter {
+    pub(crate) fn new(output_serialization: &OutputSerialization) -> Result<Self> {
+        match (&output_serialization.csv, &output_serialization.json) {
+            (Some(csv), None) => Ok(RecordWriter::csv(csv)),
+            (None, Some(json)) => Ok(RecordWriter::Json {
+                record_delimiter: json
+                    .record_delimiter
+                    .clone()
+                    .unwrap_or_else(|| "\n".to_string()),
+            }),
+            _ => Err(Error::InvalidRequest(
+                "The local evaluation needs either a CSV or a JSON output".to_string(),
+            )),
+        }
+    }
+    fn csv(csv: &CSVOutput) -> Self {
+        let quote = first_char(&csv.quote_character, '"');
+        RecordWriter::Csv {
+            record_delimiter: csv
+                .record_delimiter
+                .clone()
+                .unwrap_or_else(|| "\n".to_string()),
+            field_delimiter: csv
+                .field_delimiter
+                .clone()
+                .unwrap_or_else(|| ",".to_string()),
+            quote,
+            quote_escape: first_char(&csv.quote_escape_character, quote),
+            always_quote: csv
+                .quote_fields
+                .as_deref()
+                .is_some_and(|quote_fields| quote_fields.eq_ignore_ascii_case("ALWAYS")),
+        }
+    }
+    /// Append the row to the output. A structure is written field by field, any other value
+    /// is written as the only field `_1`.
+    pub(crate) fn write(&self, row: &Value, output: &mut Vec<u8>) {
+        match self {
+            RecordWriter::Csv {
+                record_delimiter,
+                field_delimiter,
+                quote,
+                quote_escape,
+                always_quote,
+            } => {
+                let values = match row {
+                    Value::Struct(fields) => fields.iter().map(|(_, value)| value).collect(),
+                    value => vec![value],
+                };
+                let fields: Vec<String> = values
+                    .into_iter()
+                    .map(|value| {
+                        let text = value.to_string();
+                        let needs_quotes = text.contains(*quote)
+                            || text.contains(field_delimiter.as_str())
+                            || text.contains(record_delimiter.as_str())
+                            || text.contains('\n');
+                        if *always_quote || needs_quotes {
+                            let escaped =
+                                text.replace(*quote, &format!("{}{}", quote_escape, quote));
+                            format!("{}{}{}", quote, escaped, quote)
+                        } else {
+                            text
+                        }
+                    })
+                    .collect();
+                output.extend_from_slice(fields.join(field_delimiter).as_bytes());
+                output.extend_from_slice(record_delimiter.as_bytes());
+            }
+            RecordWriter::Json { record_delimiter } => {
+                let json = match row {
+                    Value::Struct(_) => row.to_json(),
+                    value => serde_json::json!({ "_1": value.to_json() }),
+                };
+                output.extend_from_slice(json.to_string().as_bytes());
+                output.extend_from_slice(record_delimiter.as_bytes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_s3::JSONOutput;
+
+    fn row() -> Value {
+        Value::Struct(vec![
+            (
+                "name".to_string(),
+                Value::String("say \"hi\", bob".to_string()),
+            ),
+            ("size".to_string(), Value::Int(3)),
+            ("missing".to_string(), Value::Missing),
+        ])
+    }
+
+    #[test]
+    fn write_csv() {
+        let writer = RecordWriter::new(&OutputSerialization {
+            csv: Some(CSVOutput::default()),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut output = Vec::new();
+        writer.write(&row(), &mut output);
+
+        assert_eq!(
+            "\"say \"\"hi\"\", bob\",3,\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let writer = RecordWriter::new(&OutputSerialization {
+            csv: Some(CSVOutput {
+                quote_fields: Some("ALWAYS".to_string()),
+                field_delimiter: Some(";".to_string()),
+                record_delimiter: Some("\r\n".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut output = Vec::new();
+        writer.write(&Value::Int(3), &mut output);
+
+        assert_eq!("\"3\"\r\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn write_json() {
+        let writer = RecordWriter::new(&OutputSerialization {
+            json: Some(JSONOutput {
+                record_delimiter: Some(",".to_string()),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut output = Vec::new();
+        writer.write(&row(), &mut output);
+        writer.write(&Value::Null, &mut output);
+
+        assert_eq!(
+            "{\"name\":\"say \\\"hi\\\", bob\",\"size\":3},{\"_1\":null},",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
@@ -6,8 +6,10 @@ use std::marker::PhantomData;
 use std::pin::Pin;
 
 use crc32fast::Hasher;
-use async_std::task::{Context, Poll};
+use futures::io::{AsyncRead, Cursor};
+use futures::task::{Context, Poll};
 use rusoto_core::event_stream::DeserializeEvent;
+use rusoto_core::request::HttpDispatchError;
 use rusoto_core::RusotoError;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -95,6 +97,13 @@ impl<T> Into<RusotoError<T>> for EventStreamParseError {
     }
 }
 
+/// Check if the error comes from a message with an invalid checksum.
+#[cfg(feature = "tracing")]
+pub(crate) fn is_invalid_crc<T>(err: &RusotoError<T>) -> bool {
+    let invalid_crc = EventStreamParseError::InvalidCrc.to_string();
+    matches!(err, RusotoError::ParseError(message) if *message == invalid_crc)
+}
+
 #[allow(missing_docs)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum EventStreamHeaderValue<'a> {
@@ -228,21 +237,37 @@ impl<'a> EventStreamMessage<'a> {
     }
 }
 
+/// Size of the chunks read from the http body.
+const CHUNK_SIZE: usize = 8 * 1024;
+
 /// Event Stream decoder
 ///
 /// This struct implements `futures::Stream` and decodes events of type `T` from a streaming HTTP body.
-#[derive(Debug)]
 pub struct EventStream<T> {
-    response_body: Option<Vec<u8>>,
+    response_body: Option<Pin<Box<dyn AsyncRead + Send>>>,
     buf: Vec<u8>,
     _phantom: std::marker::PhantomData<T>,
 }
 
+impl<T> std::fmt::Debug for EventStream<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventStream")
+            .field("response_body", &self.response_body.as_ref().map(|_| "AsyncRead"))
+            .field("buf", &self.buf)
+            .finish()
+    }
+}
+
 impl<T: DeserializeEvent + Unpin> EventStream<T> {
     #[doc(hidden)]
     pub fn new(body: Vec<u8>) -> EventStream<T> {
+        Self::from_reader(Cursor::new(body))
+    }
+
+    /// Decode the events while the http body is read.
+    pub fn from_reader<R: AsyncRead + Send + 'static>(reader: R) -> EventStream<T> {
         EventStream {
-            response_body: Some(body),
+            response_body: Some(Box::pin(reader)),
             buf: Vec::with_capacity(512),
             _phantom: PhantomData {},
         }
@@ -294,64 +319,105 @@ impl<T: DeserializeEvent + Unpin> EventStream<T> {
         }
     }
 
-    fn drop_response_body(&mut self) {
+    /// Close the http body. The events already received stay in the buffer.
+    pub(crate) fn drop_response_body(&mut self) {
         self.response_body = None;
     }
 }
 
-impl<T: DeserializeEvent + Unpin> async_std::stream::Stream for EventStream<T> {
+impl<T: DeserializeEvent + Unpin> futures::stream::Stream for EventStream<T> {
     type Item = Result<T, RusotoError<()>>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let projection = self.get_mut();
+        let mut chunk = [0; CHUNK_SIZE];
 
-        // First try to use the buffer
-        match Self::pop_event(&mut projection.buf) {
-            Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
-            Ok(None) => {}
-            Err(err) => {
-                projection.drop_response_body();
-                return Poll::Ready(Some(Err(err)));
-            }
-        };
-
-        match projection.response_body.as_mut() {
-            // We received an http body chunk
-            Some(byte_chunk) => {
-                log::trace!("Got event stream bytes: {:?}", byte_chunk);
-
-                projection.buf.append(byte_chunk);
+        loop {
+            // First try to use the buffer
+            match Self::pop_event(&mut projection.buf) {
+                Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                Ok(None) => {}
+                Err(err) => {
+                    projection.drop_response_body();
+                    projection.buf.clear();
+                    return Poll::Ready(Some(Err(err)));
+                }
+            };
 
-                let parsed_event = match Self::pop_event(&mut projection.buf) {
-                    Ok(None) => {
-                        cx.waker().wake_by_ref();
-                        return Poll::Pending;
+            match projection.response_body.as_mut() {
+                Some(reader) => match reader.as_mut().poll_read(cx, &mut chunk) {
+                    Poll::Pending => return Poll::Pending,
+                    // The underlying stream is closed
+                    Poll::Ready(Ok(0)) => projection.drop_response_body(),
+                    // We received an http body chunk
+                    Poll::Ready(Ok(size)) => {
+                        log::trace!("Got event stream bytes: {:?}", &chunk[..size]);
+                        projection.buf.extend_from_slice(&chunk[..size]);
                     }
-                    Ok(Some(item)) => Ok(item),
-                    Err(err) => {
+                    Poll::Ready(Err(err)) => {
                         projection.drop_response_body();
-                        Err(err)
+                        projection.buf.clear();
+                        return Poll::Ready(Some(Err(RusotoError::HttpDispatch(
+                            HttpDispatchError::new(format!(
+                                "Failed to read the event stream: {}",
+                                err
+                            )),
+                        ))));
                     }
-                };
-                Poll::Ready(Some(parsed_event))
-            }
-
-            // The underlying stream is closed
-            None => {
-                projection.drop_response_body();
-
-                if !projection.buf.is_empty() {
-                    Poll::Ready(Some(Err(RusotoError::ParseError(
-                        "Event stream closed with incomplete data remaining".to_string(),
-                    ))))
-                } else {
-                    Poll::Ready(None)
+                },
+                None => {
+                    if !projection.buf.is_empty() {
+                        projection.buf.clear();
+                        return Poll::Ready(Some(Err(RusotoError::ParseError(
+                            "Event stream closed with incomplete data remaining".to_string(),
+                        ))));
+                    }
+                    return Poll::Ready(None);
                 }
             }
         }
     }
 }
 
+/// Encode an event stream message, as sent by the server.
+#[cfg(test)]
+pub(crate) fn encode_message(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+    let mut headers_buf = Vec::new();
+    for (name, value) in headers {
+        headers_buf.push(name.len() as u8);
+        headers_buf.extend_from_slice(name.as_bytes());
+        headers_buf.push(7);
+        headers_buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        headers_buf.extend_from_slice(value.as_bytes());
+    }
+
+    let total_length = 16 + headers_buf.len() + payload.len();
+    let mut message = Vec::with_capacity(total_length);
+    message.extend_from_slice(&(total_length as u32).to_be_bytes());
+    message.extend_from_slice(&(headers_buf.len() as u32).to_be_bytes());
+    let mut hasher = Hasher::new();
+    hasher.update(&message);
+    message.extend_from_slice(&hasher.finalize().to_be_bytes());
+    message.extend_from_slice(&headers_buf);
+    message.extend_from_slice(payload);
+    let mut hasher = Hasher::new();
+    hasher.update(&message);
+    message.extend_from_slice(&hasher.finalize().to_be_bytes());
+    message
+}
+
+/// Encode an event of the select object content stream.
+#[cfg(test)]
+pub(crate) fn encode_event(event_type: &str, payload: &[u8]) -> Vec<u8> {
+    encode_message(
+        &[
+            (":event-type", event_type),
+            (":content-type", "application/octet-stream"),
+            (":message-type", "event"),
+        ],
+        payload,
+    )
+}
 
 #[cfg(test)]
 mod tests {
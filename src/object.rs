@@ -0,0 +1,254 @@
+//! Metadata of the objects stored in the bucket.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use percent_encoding::percent_decode_str;
+use rusoto_s3::{CommonPrefix, HeadObjectOutput, ListObjectsV2Output, Object};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::error::{Error, Result};
+
+/// Read the XML body of a `ListObjectsV2` response.
+///
+/// The keys and prefixes are decoded when the listing is requested with the `url` encoding type.
+pub fn deserialize_list_objects_v2(body: &[u8]) -> Result<ListObjectsV2Output> {
+    let mut output = ListObjectsV2Output::default();
+    let mut path: Vec<String> = Vec::new();
+    let mut object = Object::default();
+    let mut text = String::new();
+
+    for event in EventReader::new(body) {
+        match event.map_err(|e| Error::InvalidResponse(e.to_string()))? {
+            XmlEvent::StartElement { name, .. } => {
+                path.push(name.local_name);
+                text.clear();
+            }
+            XmlEvent::Characters(characters) | XmlEvent::CData(characters) => {
+                text.push_str(&characters)
+            }
+            XmlEvent::EndElement { .. } => {
+                let value = std::mem::take(&mut text);
+                let names: Vec<&str> = path.iter().map(String::as_str).collect();
+                match names.as_slice() {
+                    [_, "Name"] => output.name = Some(value),
+                    [_, "Prefix"] => output.prefix = Some(value),
+                    [_, "Delimiter"] => output.delimiter = Some(value),
+                    [_, "EncodingType"] => output.encoding_type = Some(value),
+                    [_, "StartAfter"] => output.start_after = Some(value),
+                    [_, "ContinuationToken"] => output.continuation_token = Some(value),
+                    [_, "NextContinuationToken"] => output.next_continuation_token = Some(value),
+                    [_, "KeyCount"] => output.key_count = Some(parse(&value)?),
+                    [_, "MaxKeys"] => output.max_keys = Some(parse(&value)?),
+                    [_, "IsTruncated"] => output.is_truncated = Some(parse(&value)?),
+                    [_, "Contents", "Key"] => object.key = Some(value),
+                    [_, "Contents", "LastModified"] => object.last_modified = Some(value),
+                    [_, "Contents", "ETag"] => object.e_tag = Some(value),
+                    [_, "Contents", "Size"] => object.size = Some(parse(&value)?),
+                    [_, "Contents", "StorageClass"] => object.storage_class = Some(value),
+                    [_, "Contents"] => output
+                        .contents
+                        .get_or_insert_with(Vec::new)
+                        .push(std::mem::take(&mut object)),
+                    [_, "CommonPrefixes", "Prefix"] => output
+                        .common_prefixes
+                        .get_or_insert_with(Vec::new)
+                        .push(CommonPrefix {
+                            prefix: Some(value),
+                        }),
+                    _ => (),
+                }
+                path.pop();
+            }
+            _ => (),
+        }
+    }
+
+    if output.name.is_none() {
+        return Err(Error::InvalidResponse(
+            "The ListBucketResult is missing".to_string(),
+        ));
+    }
+
+    if output.encoding_type.as_deref() == Some("url") {
+        let decode = |value: &mut Option<String>| {
+            if let Some(value) = value {
+                *value = percent_decode_str(value).decode_utf8_lossy().into_owned();
+            }
+        };
+        decode(&mut output.prefix);
+        decode(&mut output.delimiter);
+        decode(&mut output.start_after);
+        for object in output.contents.iter_mut().flatten() {
+            decode(&mut object.key);
+        }
+        for prefix in output.common_prefixes.iter_mut().flatten() {
+            decode(&mut prefix.prefix);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Read the headers of a `HeadObject` response. The user metadata is read from the
+/// `x-amz-meta-*` headers.
+pub fn deserialize_head_object(response: &surf::Response) -> Result<HeadObjectOutput> {
+    let header = |name: &str| header(response, name);
+
+    let metadata: HashMap<String, String> = response
+        .iter()
+        .filter_map(|(name, values)| {
+            name.as_str()
+                .strip_prefix("x-amz-meta-")
+                .map(|name| (name.to_string(), values.last().as_str().to_string()))
+        })
+        .collect();
+
+    Ok(HeadObjectOutput {
+        accept_ranges: header("accept-ranges"),
+        cache_control: header("cache-control"),
+        content_disposition: header("content-disposition"),
+        content_encoding: header("content-encoding"),
+        content_language: header("content-language"),
+        content_length: parse_header(response, "content-length")?,
+        content_type: header("content-type"),
+        delete_marker: parse_header(response, "x-amz-delete-marker")?,
+        e_tag: header("etag"),
+        expiration: header("x-amz-expiration"),
+        expires: header("expires"),
+        last_modified: header("last-modified"),
+        metadata: if metadata.is_empty() {
+            None
+        } else {
+            Some(metadata)
+        },
+        parts_count: parse_header(response, "x-amz-mp-parts-count")?,
+        sse_customer_algorithm: header("x-amz-server-side-encryption-customer-algorithm"),
+        sse_customer_key_md5: header("x-amz-server-side-encryption-customer-key-md5"),
+        ssekms_key_id: header("x-amz-server-side-encryption-aws-kms-key-id"),
+        server_side_encryption: header("x-amz-server-side-encryption"),
+        storage_class: header("x-amz-storage-class"),
+        version_id: header("x-amz-version-id"),
+        ..Default::default()
+    })
+}
+
+fn header(response: &surf::Response, name: &str) -> Option<String> {
+    response
+        .header(name)
+        .map(|values| values.last().as_str().to_string())
+}
+
+fn parse_header<T: FromStr>(response: &surf::Response, name: &str) -> Result<Option<T>> {
+    header(response, name)
+        .map(|value| parse(&value))
+        .transpose()
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| Error::InvalidResponse(format!("Unexpected value '{}'", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST_OBJECTS_V2: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    <Name>my-bucket</Name>
+    <Prefix>data/year%3D2021/</Prefix>
+    <KeyCount>2</KeyCount>
+    <MaxKeys>1000</MaxKeys>
+    <EncodingType>url</EncodingType>
+    <IsTruncated>true</IsTruncated>
+    <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+    <Contents>
+        <Key>data/year%3D2021/part%200.csv</Key>
+        <LastModified>2021-01-01T10:00:00.000Z</LastModified>
+        <ETag>&quot;d41d8cd98f00b204e9800998ecf8427e&quot;</ETag>
+        <Size>1024</Size>
+        <StorageClass>STANDARD</StorageClass>
+    </Contents>
+    <Contents>
+        <Key>data/year%3D2021/part%201.csv</Key>
+        <Size>2048</Size>
+    </Contents>
+    <CommonPrefixes>
+        <Prefix>data/year%3D2021/month%3D01/</Prefix>
+    </CommonPrefixes>
+</ListBucketResult>"#;
+
+    #[test]
+    fn deserialize_list() {
+        let output = deserialize_list_objects_v2(LIST_OBJECTS_V2.as_bytes()).unwrap();
+
+        assert_eq!(Some("my-bucket"), output.name.as_deref());
+        assert_eq!(Some("data/year=2021/"), output.prefix.as_deref());
+        assert_eq!(Some(true), output.is_truncated);
+        assert_eq!(
+            Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM="),
+            output.next_continuation_token.as_deref()
+        );
+
+        let contents = output.contents.unwrap();
+        assert_eq!(2, contents.len());
+        assert_eq!(
+            Some("data/year=2021/part 0.csv"),
+            contents[0].key.as_deref()
+        );
+        assert_eq!(
+            Some("\"d41d8cd98f00b204e9800998ecf8427e\""),
+            contents[0].e_tag.as_deref()
+        );
+        assert_eq!(Some(1024), contents[0].size);
+        assert_eq!(Some(2048), contents[1].size);
+        assert_eq!(None, contents[1].storage_class);
+
+        assert_eq!(
+            Some("data/year=2021/month=01/"),
+            output.common_prefixes.unwrap()[0].prefix.as_deref()
+        );
+    }
+
+    #[test]
+    fn deserialize_head() {
+        let mut response = surf::http::Response::new(surf::StatusCode::Ok);
+        response.insert_header("content-length", "1024");
+        response.insert_header("content-encoding", "gzip");
+        response.insert_header("content-type", "text/csv");
+        response.insert_header("etag", "\"d41d8cd98f00b204e9800998ecf8427e\"");
+        response.insert_header("last-modified", "Fri, 01 Jan 2021 10:00:00 GMT");
+        response.insert_header("x-amz-meta-source", "export");
+        let response = surf::Response::from(response);
+
+        let output = deserialize_head_object(&response).unwrap();
+
+        assert_eq!(Some(1024), output.content_length);
+        assert_eq!(Some("gzip"), output.content_encoding.as_deref());
+        assert_eq!(Some("text/csv"), output.content_type.as_deref());
+        assert_eq!(
+            Some("\"d41d8cd98f00b204e9800998ecf8427e\""),
+            output.e_tag.as_deref()
+        );
+        assert_eq!(
+            Some("Fri, 01 Jan 2021 10:00:00 GMT"),
+            output.last_modified.as_deref()
+        );
+        assert_eq!(
+            Some("export"),
+            output.metadata.unwrap().get("source").map(String::as_str)
+        );
+    }
+
+    #[test]
+    fn deserialize_error() {
+        let result = deserialize_list_objects_v2(b"<Error><Code>NoSuchBucket</Code></Error>");
+        assert!(matches!(result, Err(Error::InvalidResponse(_))));
+
+        let result = deserialize_list_objects_v2(b"<ListBucketResult><Name>");
+        assert!(matches!(result, Err(Error::InvalidResponse(_))));
+    }
+}
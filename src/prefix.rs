@@ -0,0 +1,323 @@
+//! Select the objects found under a prefix as a single stream.
+
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt, TryStreamExt};
+use rusoto_s3::{ListObjectsV2Request, Object, SelectObjectContentRequest};
+
+use crate::client::Client;
+use crate::cost::STORAGE_CLASS_STANDARD;
+use crate::error::Result;
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+
+/// Number of objects selected at the same time by default.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Event of the select of one of the objects.
+#[derive(Debug)]
+pub struct ObjectEvent {
+    /// Key of the object that produced the event.
+    pub key: String,
+    pub event: SelectObjectContentEventStreamItem,
+}
+
+/// Select run on every object of a prefix, built by [`Client::select_prefix`].
+///
+/// The keys are listed page by page while the first objects are selected. The events of the
+/// objects are interleaved, the events of one object keep their order.
+#[derive(Clone)]
+pub struct PrefixSelect {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    request_template: SelectObjectContentRequest,
+    key_filter: Option<String>,
+    concurrency: usize,
+}
+
+impl PrefixSelect {
+    pub(crate) fn new(
+        client: Client,
+        bucket: String,
+        prefix: String,
+        request_template: SelectObjectContentRequest,
+    ) -> Self {
+        PrefixSelect {
+            client,
+            bucket,
+            prefix,
+            request_template,
+            key_filter: None,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+    /// Select only the keys matching the glob pattern, see [`glob_match`].
+    pub fn with_key_filter<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.key_filter = Some(pattern.into());
+        self
+    }
+    /// Maximum number of objects selected at the same time.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+    /// Keys of the objects to select, in the order of the listing.
+    pub fn keys(&self) -> BoxStream<'static, Result<String>> {
+        self.objects()
+            .map_ok(|object| object.key.unwrap_or_default())
+            .boxed()
+    }
+    /// Objects to select, with a key.
+    fn objects(&self) -> BoxStream<'static, Result<Object>> {
+        let request = ListObjectsV2Request {
+            bucket: self.bucket.clone(),
+            prefix: Some(self.prefix.clone()),
+            ..Default::default()
+        };
+        let key_filter = self.key_filter.clone();
+
+        self.client
+            .list_objects_v2_pages(request)
+            .map_ok(|output| {
+                let objects = output.contents.unwrap_or_default();
+                stream::iter(objects.into_iter().map(Ok))
+            })
+            .try_flatten()
+            .try_filter(move |object| {
+                // The folder placeholders have no content.
+                let selected = object.key.as_deref().is_some_and(|key| {
+                    !key.ends_with('/')
+                        && key_filter
+                            .as_deref()
+                            .is_none_or(|pattern| glob_match(pattern, key))
+                });
+                futures::future::ready(selected)
+            })
+            .boxed()
+    }
+    /// Run the select on every object and merge the events.
+    ///
+    /// A failed select yields its error and the other objects are still selected. The storage
+    /// class of the listing gives the price of the selects, the objects without one are
+    /// `STANDARD`.
+    pub fn into_stream(self) -> BoxStream<'static, Result<ObjectEvent>> {
+        let client = self.client.clone();
+        let mut request_template = self.request_template.clone();
+        request_template.bucket = self.bucket.clone();
+
+        self.objects()
+            .map(move |object| select_object(client.clone(), request_template.clone(), object))
+            .map(|select| stream::once(select).try_flatten().boxed())
+            .flatten_unordered(self.concurrency)
+            .boxed()
+    }
+}
+
+async fn select_object(
+    client: Client,
+    mut request: SelectObjectContentRequest,
+    object: Result<Object>,
+) -> Result<impl Stream<Item = Result<ObjectEvent>>> {
+    let object = object?;
+    let key = object.key.unwrap_or_default();
+    request.key = key.clone();
+    let storage_class = object
+        .storage_class
+        .unwrap_or_else(|| STORAGE_CLASS_STANDARD.to_string());
+    let events = client
+        .select_object_in_storage_class(request, Some(storage_class))
+        .await?;
+
+    Ok(events.map_ok(move |event| ObjectEvent {
+        key: key.clone(),
+        event,
+    }))
+}
+
+/// Match the key against a glob pattern. `*` matches any characters except `/`, `**` matches
+/// any characters, `**/` matches zero or more directories and `?` matches one character except
+/// `/`.
+pub fn glob_match(pattern: &str, key: &str) -> bool {
+    let key: Vec<char> = key.chars().collect();
+
+    // `matches[i]` tells if the tokens after the current one match `key[i..]`, the tokens are
+    // matched from the last one so each token checks each position once.
+    let mut next = vec![false; key.len() + 1];
+    next[key.len()] = true;
+    for token in glob_tokens(pattern).iter().rev() {
+        let mut matches = vec![false; key.len() + 1];
+        // A directory of `key[i..]` ends before the rest of the pattern.
+        let mut directory = false;
+        for i in (0..=key.len()).rev() {
+            let c = key.get(i).copied();
+            matches[i] = match token {
+                GlobToken::Char(p) => c == Some(*p) && next[i + 1],
+                GlobToken::One => c.is_some_and(|c| c != '/') && next[i + 1],
+                GlobToken::Segment => next[i] || (c.is_some_and(|c| c != '/') && matches[i + 1]),
+                GlobToken::Any => next[i] || (c.is_some() && matches[i + 1]),
+                GlobToken::Directories => {
+                    directory = directory || (c == Some('/') && next[i + 1]);
+                    next[i] || directory
+                }
+            };
+        }
+        next = matches;
+    }
+    next[0]
+}
+
+/// Part of a glob pattern.
+enum GlobToken {
+    Char(char),
+    /// `?`
+    One,
+    /// `*`
+    Segment,
+    /// `**`
+    Any,
+    /// `**/`
+    Directories,
+}
+
+fn glob_tokens(pattern: &str) -> Vec<GlobToken> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < pattern.len() {
+        let (token, size) = match &pattern[i..] {
+            ['*', '*', '/', ..] => (GlobToken::Directories, 3),
+            ['*', '*', ..] => (GlobToken::Any, 2),
+            ['*', ..] => (GlobToken::Segment, 1),
+            ['?', ..] => (GlobToken::One, 1),
+            [c, ..] => (GlobToken::Char(*c), 1),
+            [] => break,
+        };
+        tokens.push(token);
+        i += size;
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost::PriceTable;
+    use crate::model::event_stream::encode_event;
+    use async_trait::async_trait;
+    use percent_encoding::percent_decode_str;
+    use surf::http::{Method, Request, Response, StatusCode};
+
+    /// Bucket listing its keys two by two and selecting the key itself as the only record. The
+    /// objects have no metadata, the storage class is in the listing.
+    #[derive(Debug)]
+    struct BucketServer {
+        keys: Vec<&'static str>,
+    }
+
+    #[async_trait]
+    impl surf::HttpClient for BucketServer {
+        async fn send(&self, request: Request) -> surf::Result<Response> {
+            let mut response = Response::new(StatusCode::Ok);
+            if request.method() == Method::Get {
+                let start: usize = request
+                    .url()
+                    .query_pairs()
+                    .find(|(name, _)| name == "continuation-token")
+                    .map_or(0, |(_, token)| token.parse().unwrap());
+                let end = self.keys.len().min(start + 2);
+                let contents: String = self.keys[start..end]
+                    .iter()
+                    .map(|key| {
+                        format!(
+                            "<Contents><Key>{}</Key><StorageClass>STANDARD_IA</StorageClass>\
+                                </Contents>",
+                            key
+                        )
+                    })
+                    .collect();
+                response.set_body(format!(
+                    "<ListBucketResult><Name>my-bucket</Name><IsTruncated>{}</IsTruncated>\
+                        <NextContinuationToken>{}</NextContinuationToken>{}</ListBucketResult>",
+                    end < self.keys.len(),
+                    end,
+                    contents
+                ));
+            } else if request.method() == Method::Head {
+                return Ok(Response::new(StatusCode::NotFound));
+            } else {
+                let path = percent_decode_str(request.url().path()).decode_utf8()?;
+                let key = path.trim_start_matches("/my-bucket/");
+                let mut events = encode_event("Records", key.as_bytes());
+                events.extend(encode_event("End", b""));
+                response.set_body(events);
+            }
+            Ok(response)
+        }
+    }
+
+    #[async_std::test]
+    async fn select_prefix() {
+        let http_client = surf::Client::with_http_client(BucketServer {
+            keys: vec![
+                "data/",
+                "data/part-0.csv",
+                "data/part-1.json",
+                "data/part-2.csv",
+                "data/part-3.csv",
+                "data/a b?c#d é.csv",
+            ],
+        });
+        // The storage class of the price is taken from the listing, without `HeadObject`.
+        let client = Client::new(http_client, "http://localhost:9000", "us-east-1")
+            .with_price_table(PriceTable::default());
+
+        let mut events: Vec<(String, Vec<u8>)> = client
+            .select_prefix("my-bucket", "data/", SelectObjectContentRequest::default())
+            .with_key_filter("data/*.csv")
+            .with_concurrency(2)
+            .into_stream()
+            .try_filter_map(|event| async move {
+                Ok(match event.event {
+                    SelectObjectContentEventStreamItem::Records(records) => {
+                        Some((event.key, records.payload.unwrap().to_vec()))
+                    }
+                    _ => None,
+                })
+            })
+            .try_collect()
+            .await
+            .unwrap();
+        events.sort();
+
+        assert_eq!(
+            vec![
+                (
+                    "data/a b?c#d é.csv".to_string(),
+                    "data/a b?c#d é.csv".as_bytes().to_vec()
+                ),
+                ("data/part-0.csv".to_string(), b"data/part-0.csv".to_vec()),
+                ("data/part-2.csv".to_string(), b"data/part-2.csv".to_vec()),
+                ("data/part-3.csv".to_string(), b"data/part-3.csv".to_vec()),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("data/*.csv", "data/part-0.csv"));
+        assert!(!glob_match("data/*.csv", "data/2021/part-0.csv"));
+        assert!(glob_match("data/**.csv", "data/2021/part-0.csv"));
+        assert!(glob_match("data/**/part-?.csv", "data/2021/01/part-0.csv"));
+        assert!(!glob_match("data/part-?.csv", "data/part-10.csv"));
+        assert!(!glob_match("data/*.csv", "data/part-0.csv.gz"));
+        assert!(glob_match("**", "data/part-0.csv"));
+        assert!(glob_match("data/**/part-0.csv", "data/part-0.csv"));
+        assert!(glob_match("**/part-0.csv", "part-0.csv"));
+        assert!(!glob_match("data/**/part-0.csv", "data2/part-0.csv"));
+
+        // The repeated `**` don't backtrack.
+        let key = "a".repeat(200);
+        assert!(!glob_match(&format!("{}b", "**a".repeat(20)), &key));
+        assert!(glob_match(&"**a".repeat(20), &key));
+    }
+}
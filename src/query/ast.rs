@@ -0,0 +1,877 @@
+//! Syntax tree of the S3 Select SQL dialect.
+//!
+//! Every node renders the SQL it represents with `Display`, identifiers and literals are quoted
+//! when needed so the rendered expression can't be broken by the values it contains.
+
+use std::fmt::{Display, Formatter, Result};
+
+/// Name of the object in the `FROM` clause.
+pub const OBJECT: &str = "S3Object";
+
+/// Words that can't be used as unquoted identifiers.
+const KEYWORDS: &[&str] = &[
+    "all", "and", "as", "asc", "avg", "between", "by", "case", "cast", "count", "desc", "distinct",
+    "else", "end", "escape", "extract", "false", "from", "group", "having", "in", "is", "join",
+    "like", "limit", "max", "min", "missing", "not", "null", "on", "or", "order", "select", "sum",
+    "then", "true", "union", "when", "where",
+];
+
+/// `SELECT ... FROM S3Object[*].path alias WHERE ... LIMIT ...`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectStatement {
+    pub projection: Projection,
+    pub from: FromClause,
+    pub filter: Option<Expr>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// `SELECT *`
+    All,
+    Items(Vec<SelectItem>),
+}
+
+/// Projected expression with an optional `AS alias`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectItem {
+    pub expr: Expr,
+    pub alias: Option<Identifier>,
+}
+
+/// `FROM S3Object[*].path alias`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FromClause {
+    /// JSON path inside the object, empty for CSV and Parquet.
+    pub path: Vec<PathStep>,
+    pub alias: Option<Identifier>,
+}
+
+/// Step of a JSON path.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathStep {
+    /// `.name`
+    Key(Identifier),
+    /// `[0]`
+    Index(u64),
+    /// `[*]`
+    Wildcard,
+}
+
+impl PathStep {
+    pub fn key<S: Into<String>>(name: S) -> Self {
+        PathStep::Key(Identifier::new(name))
+    }
+}
+
+/// Name of a column, an attribute or an alias.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Identifier {
+    pub name: String,
+    /// Quoted identifiers are case sensitive and can contain any character.
+    pub quoted: bool,
+}
+
+impl Identifier {
+    /// Create an identifier, quoted only if the name can't be written as is.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        let name = name.into();
+        let quoted = needs_quotes(&name);
+        Identifier { name, quoted }
+    }
+    pub fn quoted<S: Into<String>>(name: S) -> Self {
+        Identifier {
+            name: name.into(),
+            quoted: true,
+        }
+    }
+    /// Check if the identifier designates the `name`. Unquoted identifiers are case insensitive.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.quoted {
+            self.name == name
+        } else {
+            self.name.eq_ignore_ascii_case(name)
+        }
+    }
+}
+
+fn needs_quotes(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid_start = matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || c == '_');
+
+    !valid_start
+        || !chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        || KEYWORDS.contains(&name)
+}
+
+/// Reference to a column: `s.name`, `_1`, `s.attribute[0].name`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnRef {
+    /// Alias of the `FROM` clause.
+    pub qualifier: Option<Identifier>,
+    /// Name of the column followed by the JSON path inside it. The first step is always a key.
+    pub path: Vec<PathStep>,
+}
+
+impl ColumnRef {
+    /// Name of the column, without the qualifier and the nested path.
+    pub fn name(&self) -> Option<&Identifier> {
+        match self.path.first() {
+            Some(PathStep::Key(identifier)) => Some(identifier),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Null,
+    Missing,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    Bool,
+    Int,
+    Integer,
+    String,
+    Float,
+    Decimal,
+    Numeric,
+    Timestamp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DatePart {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    TimezoneHour,
+    TimezoneMinute,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Not,
+    Minus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Concat,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+impl BinaryOperator {
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Eq
+            | BinaryOperator::NotEq
+            | BinaryOperator::Lt
+            | BinaryOperator::LtEq
+            | BinaryOperator::Gt
+            | BinaryOperator::GtEq => 4,
+            BinaryOperator::Concat => 5,
+            BinaryOperator::Plus | BinaryOperator::Minus => 6,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulo => 7,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Column(ColumnRef),
+    /// `*` as argument of `COUNT(*)`.
+    Wildcard,
+    /// `?` parameter of a query template, numbered from 0 in the order of the expression.
+    Parameter(usize),
+    Unary {
+        op: UnaryOperator,
+        expr: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOperator,
+        right: Box<Expr>,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    IsMissing {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        escape: Option<Box<Expr>>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    /// Function call, the name is upper case.
+    Function {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Cast {
+        expr: Box<Expr>,
+        data_type: DataType,
+    },
+    Extract {
+        part: DatePart,
+        expr: Box<Expr>,
+    },
+    /// Date part given as argument of `DATE_ADD` and `DATE_DIFF`.
+    DatePart(DatePart),
+    /// `TRIM(BOTH ' ' FROM expr)`
+    Trim {
+        spec: Option<TrimSpec>,
+        characters: Option<Box<Expr>>,
+        expr: Box<Expr>,
+    },
+    /// `CASE operand WHEN condition THEN result ELSE default END`
+    Case {
+        operand: Option<Box<Expr>>,
+        branches: Vec<(Expr, Expr)>,
+        default: Option<Box<Expr>>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrimSpec {
+    Leading,
+    Trailing,
+    Both,
+}
+
+impl Expr {
+    /// Precedence of the expression, used to add the parentheses needed to render it.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op, .. } => op.precedence(),
+            Expr::Unary {
+                op: UnaryOperator::Not,
+                ..
+            } => 3,
+            Expr::IsNull { .. }
+            | Expr::IsMissing { .. }
+            | Expr::Like { .. }
+            | Expr::Between { .. }
+            | Expr::InList { .. } => 4,
+            Expr::Unary {
+                op: UnaryOperator::Minus,
+                ..
+            } => 8,
+            Expr::Literal(Literal::Int(value)) if *value < 0 => 8,
+            Expr::Literal(Literal::Float(value)) if value.is_sign_negative() => 8,
+            _ => u8::MAX,
+        }
+    }
+    /// Visit the expression and all its children.
+    pub fn walk<'a, F: FnMut(&'a Expr)>(&'a self, visitor: &mut F) {
+        visitor(self);
+        match self {
+            Expr::Literal(_)
+            | Expr::Column(_)
+            | Expr::Wildcard
+            | Expr::Parameter(_)
+            | Expr::DatePart(_) => (),
+            Expr::Unary { expr, .. }
+            | Expr::IsNull { expr, .. }
+            | Expr::IsMissing { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::Extract { expr, .. } => expr.walk(visitor),
+            Expr::Trim {
+                characters, expr, ..
+            } => {
+                if let Some(characters) = characters {
+                    characters.walk(visitor);
+                }
+                expr.walk(visitor);
+            }
+            Expr::Case {
+                operand,
+                branches,
+                default,
+            } => {
+                if let Some(operand) = operand {
+                    operand.walk(visitor);
+                }
+                for (condition, result) in branches {
+                    condition.walk(visitor);
+                    result.walk(visitor);
+                }
+                if let Some(default) = default {
+                    default.walk(visitor);
+                }
+            }
+            Expr::Binary { left, right, .. } => {
+                left.walk(visitor);
+                right.walk(visitor);
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.walk(visitor);
+                pattern.walk(visitor);
+                if let Some(escape) = escape {
+                    escape.walk(visitor);
+                }
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk(visitor);
+                low.walk(visitor);
+                high.walk(visitor);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk(visitor);
+                list.iter().for_each(|item| item.walk(visitor));
+            }
+            Expr::Function { args, .. } => args.iter().for_each(|arg| arg.walk(visitor)),
+        }
+    }
+    /// Visit the expression and all its children, the children can be modified.
+    pub fn walk_mut<F: FnMut(&mut Expr)>(&mut self, visitor: &mut F) {
+        visitor(self);
+        match self {
+            Expr::Literal(_)
+            | Expr::Column(_)
+            | Expr::Wildcard
+            | Expr::Parameter(_)
+            | Expr::DatePart(_) => (),
+            Expr::Unary { expr, .. }
+            | Expr::IsNull { expr, .. }
+            | Expr::IsMissing { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::Extract { expr, .. } => expr.walk_mut(visitor),
+            Expr::Trim {
+                characters, expr, ..
+            } => {
+                if let Some(characters) = characters {
+                    characters.walk_mut(visitor);
+                }
+                expr.walk_mut(visitor);
+            }
+            Expr::Case {
+                operand,
+                branches,
+                default,
+            } => {
+                if let Some(operand) = operand {
+                    operand.walk_mut(visitor);
+                }
+                for (condition, result) in branches {
+                    condition.walk_mut(visitor);
+                    result.walk_mut(visitor);
+                }
+                if let Some(default) = default {
+                    default.walk_mut(visitor);
+                }
+            }
+            Expr::Binary { left, right, .. } => {
+                left.walk_mut(visitor);
+                right.walk_mut(visitor);
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.walk_mut(visitor);
+                pattern.walk_mut(visitor);
+                if let Some(escape) = escape {
+                    escape.walk_mut(visitor);
+                }
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk_mut(visitor);
+                low.walk_mut(visitor);
+                high.walk_mut(visitor);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk_mut(visitor);
+                list.iter_mut().for_each(|item| item.walk_mut(visitor));
+            }
+            Expr::Function { args, .. } => args.iter_mut().for_each(|arg| arg.walk_mut(visitor)),
+        }
+    }
+    /// Check if the expression contains an aggregate function.
+    pub fn is_aggregate(&self) -> bool {
+        let mut aggregate = false;
+        self.walk(&mut |expr| {
+            if let Expr::Function { name, .. } = expr {
+                aggregate |= is_aggregate_function(name);
+            }
+        });
+        aggregate
+    }
+    /// Columns referenced by the expression.
+    pub fn columns(&self) -> Vec<&ColumnRef> {
+        let mut columns = Vec::new();
+        self.walk(&mut |expr| {
+            if let Expr::Column(column) = expr {
+                columns.push(column);
+            }
+        });
+        columns
+    }
+}
+
+/// Aggregate functions supported by S3 Select.
+pub fn is_aggregate_function(name: &str) -> bool {
+    matches!(
+        name.to_uppercase().as_str(),
+        "AVG" | "COUNT" | "MAX" | "MIN" | "SUM"
+    )
+}
+
+impl SelectStatement {
+    /// Visit all the expressions of the statement.
+    pub fn walk<'a, F: FnMut(&'a Expr)>(&'a self, visitor: &mut F) {
+        if let Projection::Items(items) = &self.projection {
+            items.iter().for_each(|item| item.expr.walk(visitor));
+        }
+        if let Some(filter) = &self.filter {
+            filter.walk(visitor);
+        }
+    }
+    /// Visit all the expressions of the statement, the expressions can be modified.
+    pub fn walk_mut<F: FnMut(&mut Expr)>(&mut self, visitor: &mut F) {
+        if let Projection::Items(items) = &mut self.projection {
+            items
+                .iter_mut()
+                .for_each(|item| item.expr.walk_mut(visitor));
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.walk_mut(visitor);
+        }
+    }
+}
+
+impl Display for SelectStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "SELECT {} FROM {}", self.projection, self.from)?;
+        if let Some(filter) = &self.filter {
+            write!(f, " WHERE {}", filter)?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Projection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Projection::All => write!(f, "*"),
+            Projection::Items(items) => write_list(f, items),
+        }
+    }
+}
+
+impl Display for SelectItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.expr)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", alias)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for FromClause {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", OBJECT)?;
+        for step in &self.path {
+            write!(f, "{}", step)?;
+        }
+        if let Some(alias) = &self.alias {
+            write!(f, " {}", alias)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PathStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            PathStep::Key(identifier) => write!(f, ".{}", identifier),
+            PathStep::Index(index) => write!(f, "[{}]", index),
+            PathStep::Wildcard => write!(f, "[*]"),
+        }
+    }
+}
+
+impl Display for Identifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if self.quoted {
+            write!(f, "\"{}\"", self.name.replace('"', "\"\""))
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+impl Display for ColumnRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let mut steps = self.path.iter();
+        match &self.qualifier {
+            Some(qualifier) => write!(f, "{}", qualifier)?,
+            None => match steps.next() {
+                Some(PathStep::Key(identifier)) => write!(f, "{}", identifier)?,
+                Some(step) => write!(f, "{}", step)?,
+                None => (),
+            },
+        }
+        for step in steps {
+            write!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Literal::Null => write!(f, "NULL"),
+            Literal::Missing => write!(f, "MISSING"),
+            Literal::Bool(true) => write!(f, "TRUE"),
+            Literal::Bool(false) => write!(f, "FALSE"),
+            Literal::Int(value) => write!(f, "{}", value),
+            Literal::Float(value) if value.is_finite() => write!(f, "{:?}", value),
+            Literal::Float(value) => write!(f, "CAST('{}' AS FLOAT)", value),
+            Literal::String(value) => write!(f, "'{}'", value.replace('\'', "''")),
+        }
+    }
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let name = match self {
+            DataType::Bool => "BOOL",
+            DataType::Int => "INT",
+            DataType::Integer => "INTEGER",
+            DataType::String => "STRING",
+            DataType::Float => "FLOAT",
+            DataType::Decimal => "DECIMAL",
+            DataType::Numeric => "NUMERIC",
+            DataType::Timestamp => "TIMESTAMP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for DatePart {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let name = match self {
+            DatePart::Year => "YEAR",
+            DatePart::Month => "MONTH",
+            DatePart::Day => "DAY",
+            DatePart::Hour => "HOUR",
+            DatePart::Minute => "MINUTE",
+            DatePart::Second => "SECOND",
+            DatePart::TimezoneHour => "TIMEZONE_HOUR",
+            DatePart::TimezoneMinute => "TIMEZONE_MINUTE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for TrimSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let name = match self {
+            TrimSpec::Leading => "LEADING",
+            TrimSpec::Trailing => "TRAILING",
+            TrimSpec::Both => "BOTH",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for BinaryOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let symbol = match self {
+            BinaryOperator::Or => "OR",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Eq => "=",
+            BinaryOperator::NotEq => "<>",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::LtEq => "<=",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::GtEq => ">=",
+            BinaryOperator::Concat => "||",
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Write the child expression, between parentheses if it binds less than its parent.
+fn write_operand(f: &mut Formatter<'_>, expr: &Expr, min_precedence: u8) -> Result {
+    if expr.precedence() < min_precedence {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+fn write_list<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+fn not(negated: bool) -> &'static str {
+    if negated {
+        "NOT "
+    } else {
+        ""
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        // Operands of the predicates are at least concatenations, comparisons are wrapped.
+        let predicate_operand = 5;
+        match self {
+            Expr::Literal(literal) => write!(f, "{}", literal),
+            Expr::Column(column) => write!(f, "{}", column),
+            Expr::Wildcard => write!(f, "*"),
+            Expr::Parameter(_) => write!(f, "?"),
+            Expr::Unary {
+                op: UnaryOperator::Not,
+                expr,
+            } => {
+                write!(f, "NOT ")?;
+                write_operand(f, expr, self.precedence())
+            }
+            Expr::Unary {
+                op: UnaryOperator::Minus,
+                expr,
+            } => {
+                // `--` starts a comment, a negative operand is wrapped.
+                write!(f, "-")?;
+                write_operand(f, expr, self.precedence() + 1)
+            }
+            Expr::Binary { left, op, right } => {
+                let precedence = op.precedence();
+                // Comparisons are not associative, `a = b = c` is not valid.
+                let left_precedence = if precedence == 4 {
+                    precedence + 1
+                } else {
+                    precedence
+                };
+                write_operand(f, left, left_precedence)?;
+                write!(f, " {} ", op)?;
+                write_operand(f, right, precedence + 1)
+            }
+            Expr::IsNull { expr, negated } => {
+                write_operand(f, expr, predicate_operand)?;
+                write!(f, " IS {}NULL", not(*negated))
+            }
+            Expr::IsMissing { expr, negated } => {
+                write_operand(f, expr, predicate_operand)?;
+                write!(f, " IS {}MISSING", not(*negated))
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                negated,
+            } => {
+                write_operand(f, expr, predicate_operand)?;
+                write!(f, " {}LIKE ", not(*negated))?;
+                write_operand(f, pattern, predicate_operand)?;
+                if let Some(escape) = escape {
+                    write!(f, " ESCAPE ")?;
+                    write_operand(f, escape, predicate_operand)?;
+                }
+                Ok(())
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                write_operand(f, expr, predicate_operand)?;
+                write!(f, " {}BETWEEN ", not(*negated))?;
+                write_operand(f, low, predicate_operand)?;
+                write!(f, " AND ")?;
+                write_operand(f, high, predicate_operand)
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                write_operand(f, expr, predicate_operand)?;
+                write!(f, " {}IN (", not(*negated))?;
+                write_list(f, list)?;
+                write!(f, ")")
+            }
+            Expr::Function { name, args } => {
+                write!(f, "{}(", name)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Expr::Cast { expr, data_type } => write!(f, "CAST({} AS {})", expr, data_type),
+            Expr::Extract { part, expr } => write!(f, "EXTRACT({} FROM {})", part, expr),
+            Expr::DatePart(part) => write!(f, "{}", part),
+            Expr::Trim {
+                spec,
+                characters,
+                expr,
+            } => {
+                write!(f, "TRIM(")?;
+                if let Some(spec) = spec {
+                    write!(f, "{} ", spec)?;
+                }
+                if let Some(characters) = characters {
+                    write!(f, "{} ", characters)?;
+                }
+                if spec.is_some() || characters.is_some() {
+                    write!(f, "FROM ")?;
+                }
+                write!(f, "{})", expr)
+            }
+            Expr::Case {
+                operand,
+                branches,
+                default,
+            } => {
+                write!(f, "CASE")?;
+                if let Some(operand) = operand {
+                    write!(f, " {}", operand)?;
+                }
+                for (condition, result) in branches {
+                    write!(f, " WHEN {} THEN {}", condition, result)?;
+                }
+                if let Some(default) = default {
+                    write!(f, " ELSE {}", default)?;
+                }
+                write!(f, " END")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifier_quoting() {
+        assert_eq!("number", Identifier::new("number").to_string());
+        assert_eq!("_1", Identifier::new("_1").to_string());
+        assert_eq!(
+            "\"long-string\"",
+            Identifier::new("long-string").to_string()
+        );
+        assert_eq!("\"Number\"", Identifier::new("Number").to_string());
+        assert_eq!("\"select\"", Identifier::new("select").to_string());
+        assert_eq!("\"a\"\"b\"", Identifier::new("a\"b").to_string());
+    }
+
+    #[test]
+    fn literal_quoting() {
+        assert_eq!("'it''s'", Literal::String("it's".to_string()).to_string());
+        assert_eq!("1.0", Literal::Float(1.0).to_string());
+        assert_eq!("-20", Literal::Int(-20).to_string());
+        assert_eq!("CAST('NaN' AS FLOAT)", Literal::Float(f64::NAN).to_string());
+    }
+
+    #[test]
+    fn operator_precedence() {
+        let a = Expr::Literal(Literal::Int(1));
+        let or = Expr::Binary {
+            left: Box::new(a.clone()),
+            op: BinaryOperator::Or,
+            right: Box::new(a.clone()),
+        };
+        let and = Expr::Binary {
+            left: Box::new(or.clone()),
+            op: BinaryOperator::And,
+            right: Box::new(a.clone()),
+        };
+        assert_eq!("(1 OR 1) AND 1", and.to_string());
+
+        let minus = Expr::Binary {
+            left: Box::new(a.clone()),
+            op: BinaryOperator::Minus,
+            right: Box::new(Expr::Binary {
+                left: Box::new(a.clone()),
+                op: BinaryOperator::Minus,
+                right: Box::new(a),
+            }),
+        };
+        assert_eq!("1 - (1 - 1)", minus.to_string());
+    }
+
+    #[test]
+    fn negation_is_not_a_comment() {
+        let negative = Expr::Literal(Literal::Int(-5));
+        let negation = Expr::Unary {
+            op: UnaryOperator::Minus,
+            expr: Box::new(negative.clone()),
+        };
+        assert_eq!("-(-5)", negation.to_string());
+
+        let minus = Expr::Binary {
+            left: Box::new(Expr::Literal(Literal::Int(1))),
+            op: BinaryOperator::Minus,
+            right: Box::new(negative),
+        };
+        assert_eq!("1 - -5", minus.to_string());
+    }
+}
@@ -0,0 +1,534 @@
+//! Build S3 Select expressions without concatenating strings.
+//!
+//! ```
+//! use surf_bucket_select::query::{col, date, Select};
+//!
+//! let query = Select::from_object()
+//!     .columns(["number", "string"])
+//!     .filter(col("number").eq(20).and(col("date").gt(date("2020-01-01"))))
+//!     .limit(10);
+//!
+//! assert_eq!(
+//!     "SELECT s.number, s.string FROM S3Object s WHERE s.number = 20 AND s.date > CAST('2020-01-01' AS TIMESTAMP) LIMIT 10",
+//!     query.to_string()
+//! );
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+use super::ast::*;
+use super::QueryError;
+use crate::schema::Schema;
+
+/// Alias of the object used when none is given.
+pub const DEFAULT_ALIAS: &str = "s";
+
+/// Builder of a `SELECT` statement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Select {
+    statement: SelectStatement,
+}
+
+impl Select {
+    /// `SELECT * FROM S3Object s`
+    pub fn from_object() -> Self {
+        Select {
+            statement: SelectStatement {
+                projection: Projection::All,
+                from: FromClause {
+                    path: Vec::new(),
+                    alias: Some(Identifier::new(DEFAULT_ALIAS)),
+                },
+                filter: None,
+                limit: None,
+            },
+        }
+    }
+    /// Select the records found at the JSON path, `[*].results[*]` gives
+    /// `FROM S3Object[*].results[*] s`.
+    pub fn path<I: IntoIterator<Item = PathStep>>(mut self, path: I) -> Self {
+        self.statement.from.path = path.into_iter().collect();
+        self
+    }
+    /// Alias of the object, used to qualify the columns.
+    pub fn alias<S: Into<String>>(mut self, alias: S) -> Self {
+        self.statement.from.alias = Some(Identifier::new(alias));
+        self
+    }
+    /// Project the columns by name.
+    pub fn columns<I, S>(self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        columns
+            .into_iter()
+            .fold(self, |select, column| select.column(col(column)))
+    }
+    /// Remove the projected expressions, `SELECT *`.
+    pub fn clear_columns(mut self) -> Self {
+        self.statement.projection = Projection::All;
+        self
+    }
+    /// Project an expression.
+    pub fn column<E: Into<Expr>>(mut self, expr: E) -> Self {
+        self.push_item(SelectItem {
+            expr: expr.into(),
+            alias: None,
+        });
+        self
+    }
+    /// Project an expression with `AS alias`.
+    pub fn column_as<E: Into<Expr>, S: Into<String>>(mut self, expr: E, alias: S) -> Self {
+        self.push_item(SelectItem {
+            expr: expr.into(),
+            alias: Some(Identifier::new(alias)),
+        });
+        self
+    }
+    /// Add a condition to the `WHERE` clause. Several filters are combined with `AND`.
+    pub fn filter<E: Into<Expr>>(mut self, expr: E) -> Self {
+        self.statement.filter = Some(match self.statement.filter.take() {
+            Some(filter) => filter.and(expr),
+            None => expr.into(),
+        });
+        self
+    }
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.statement.limit = Some(limit);
+        self
+    }
+    /// Build the statement, the columns are qualified with the alias of the object.
+    pub fn build(self) -> SelectStatement {
+        let mut statement = self.statement;
+        let alias = statement.from.alias.clone();
+        statement.walk_mut(&mut |expr| {
+            if let Expr::Column(column) = expr {
+                if column.qualifier.is_none() {
+                    column.qualifier = alias.clone();
+                }
+            }
+        });
+        statement
+    }
+    /// Build the statement and check its columns against the schema of the object.
+    pub fn validate(self, schema: &Schema) -> Result<SelectStatement, QueryError> {
+        super::validate(&self.build().to_string(), Some(schema))
+    }
+    fn push_item(&mut self, item: SelectItem) {
+        match &mut self.statement.projection {
+            Projection::Items(items) => items.push(item),
+            Projection::All => self.statement.projection = Projection::Items(vec![item]),
+        }
+    }
+}
+
+impl Display for Select {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.clone().build())
+    }
+}
+
+/// Continue to build a parsed statement.
+impl From<SelectStatement> for Select {
+    fn from(statement: SelectStatement) -> Select {
+        Select { statement }
+    }
+}
+
+impl From<Select> for SelectStatement {
+    fn from(select: Select) -> SelectStatement {
+        select.build()
+    }
+}
+
+/// Reference a column by name.
+pub fn col<S: Into<String>>(name: S) -> Expr {
+    col_path(vec![PathStep::key(name)])
+}
+
+/// Reference an attribute nested in a JSON record, `[key("a"), Index(0)]` gives `s.a[0]`.
+pub fn col_path<I: IntoIterator<Item = PathStep>>(path: I) -> Expr {
+    Expr::Column(ColumnRef {
+        qualifier: None,
+        path: path.into_iter().collect(),
+    })
+}
+
+/// Literal value.
+pub fn lit<L: Into<Literal>>(value: L) -> Expr {
+    Expr::Literal(value.into())
+}
+
+/// Timestamp literal from an ISO 8601 date, `CAST('2020-01-01' AS TIMESTAMP)`.
+pub fn date<S: Into<String>>(value: S) -> Expr {
+    lit(value.into()).cast(DataType::Timestamp)
+}
+
+/// Timestamp literal from an ISO 8601 date time, `CAST('2020-01-01T10:00:00Z' AS TIMESTAMP)`.
+pub fn timestamp<S: Into<String>>(value: S) -> Expr {
+    date(value)
+}
+
+/// Call a function.
+///
+/// # Panics
+///
+/// If the name isn't an identifier: letters, digits and `_`, not starting with a digit. It's
+/// written as is in the expression.
+pub fn function<S: Into<String>, I: IntoIterator<Item = Expr>>(name: S, args: I) -> Expr {
+    let name = name.into();
+    let mut chars = name.chars();
+    let identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    assert!(identifier, "Invalid function name {:?}", name);
+
+    Expr::Function {
+        name: name.to_uppercase(),
+        args: args.into_iter().collect(),
+    }
+}
+
+/// `COUNT(*)`
+pub fn count_all() -> Expr {
+    function("COUNT", vec![Expr::Wildcard])
+}
+
+pub fn count<E: Into<Expr>>(expr: E) -> Expr {
+    function("COUNT", vec![expr.into()])
+}
+
+pub fn sum<E: Into<Expr>>(expr: E) -> Expr {
+    function("SUM", vec![expr.into()])
+}
+
+pub fn avg<E: Into<Expr>>(expr: E) -> Expr {
+    function("AVG", vec![expr.into()])
+}
+
+pub fn min<E: Into<Expr>>(expr: E) -> Expr {
+    function("MIN", vec![expr.into()])
+}
+
+pub fn max<E: Into<Expr>>(expr: E) -> Expr {
+    function("MAX", vec![expr.into()])
+}
+
+impl Expr {
+    fn binary<E: Into<Expr>>(self, op: BinaryOperator, right: E) -> Expr {
+        Expr::Binary {
+            left: Box::new(self),
+            op,
+            right: Box::new(right.into()),
+        }
+    }
+    pub fn eq<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::Eq, right)
+    }
+    pub fn not_eq<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::NotEq, right)
+    }
+    pub fn lt<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::Lt, right)
+    }
+    pub fn lt_eq<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::LtEq, right)
+    }
+    pub fn gt<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::Gt, right)
+    }
+    pub fn gt_eq<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::GtEq, right)
+    }
+    pub fn and<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::And, right)
+    }
+    pub fn or<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::Or, right)
+    }
+    /// `self || right`
+    pub fn concat<E: Into<Expr>>(self, right: E) -> Expr {
+        self.binary(BinaryOperator::Concat, right)
+    }
+    pub fn like<S: Into<String>>(self, pattern: S) -> Expr {
+        Expr::Like {
+            expr: Box::new(self),
+            pattern: Box::new(lit(pattern.into())),
+            escape: None,
+            negated: false,
+        }
+    }
+    pub fn not_like<S: Into<String>>(self, pattern: S) -> Expr {
+        Expr::Like {
+            expr: Box::new(self),
+            pattern: Box::new(lit(pattern.into())),
+            escape: None,
+            negated: true,
+        }
+    }
+    pub fn is_null(self) -> Expr {
+        Expr::IsNull {
+            expr: Box::new(self),
+            negated: false,
+        }
+    }
+    pub fn is_not_null(self) -> Expr {
+        Expr::IsNull {
+            expr: Box::new(self),
+            negated: true,
+        }
+    }
+    pub fn between<L: Into<Expr>, H: Into<Expr>>(self, low: L, high: H) -> Expr {
+        Expr::Between {
+            expr: Box::new(self),
+            low: Box::new(low.into()),
+            high: Box::new(high.into()),
+            negated: false,
+        }
+    }
+    pub fn in_list<I: IntoIterator<Item = E>, E: Into<Expr>>(self, list: I) -> Expr {
+        Expr::InList {
+            expr: Box::new(self),
+            list: list.into_iter().map(Into::into).collect(),
+            negated: false,
+        }
+    }
+    pub fn cast(self, data_type: DataType) -> Expr {
+        Expr::Cast {
+            expr: Box::new(self),
+            data_type,
+        }
+    }
+}
+
+impl std::ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::Unary {
+            op: UnaryOperator::Not,
+            expr: Box::new(self),
+        }
+    }
+}
+
+impl std::ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        Expr::Unary {
+            op: UnaryOperator::Minus,
+            expr: Box::new(self),
+        }
+    }
+}
+
+macro_rules! impl_binary_operator {
+    ($trait:ident, $method:ident, $op:expr) => {
+        impl<E: Into<Expr>> std::ops::$trait<E> for Expr {
+            type Output = Expr;
+
+            fn $method(self, right: E) -> Expr {
+                self.binary($op, right)
+            }
+        }
+    };
+}
+
+impl_binary_operator!(Add, add, BinaryOperator::Plus);
+impl_binary_operator!(Sub, sub, BinaryOperator::Minus);
+impl_binary_operator!(Mul, mul, BinaryOperator::Multiply);
+impl_binary_operator!(Div, div, BinaryOperator::Divide);
+impl_binary_operator!(Rem, rem, BinaryOperator::Modulo);
+
+macro_rules! impl_from_literal {
+    ($($type:ty => $variant:ident as $target:ty),*) => {
+        $(
+            impl From<$type> for Literal {
+                fn from(value: $type) -> Literal {
+                    Literal::$variant(<$target>::from(value))
+                }
+            }
+
+            impl From<$type> for Expr {
+                fn from(value: $type) -> Expr {
+                    Expr::Literal(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_literal!(
+    bool => Bool as bool,
+    i8 => Int as i64,
+    i16 => Int as i64,
+    i32 => Int as i64,
+    i64 => Int as i64,
+    u8 => Int as i64,
+    u16 => Int as i64,
+    u32 => Int as i64,
+    f32 => Float as f64,
+    f64 => Float as f64,
+    String => String as String,
+    &str => String as String
+);
+
+/// The integers out of the range of `i64` are written as floats.
+macro_rules! impl_from_wide_integer {
+    ($($type:ty),*) => {
+        $(
+            impl From<$type> for Literal {
+                fn from(value: $type) -> Literal {
+                    i64::try_from(value).map_or(Literal::Float(value as f64), Literal::Int)
+                }
+            }
+
+            impl From<$type> for Expr {
+                fn from(value: $type) -> Expr {
+                    Expr::Literal(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_wide_integer!(u64, usize, i128);
+
+impl From<Literal> for Expr {
+    fn from(literal: Literal) -> Expr {
+        Expr::Literal(literal)
+    }
+}
+
+impl From<ColumnRef> for Expr {
+    fn from(column: ColumnRef) -> Expr {
+        Expr::Column(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_all() {
+        assert_eq!(
+            "SELECT * FROM S3Object s",
+            Select::from_object().to_string()
+        );
+    }
+
+    #[test]
+    fn select_json_path() {
+        let query = Select::from_object()
+            .path(vec![
+                PathStep::Wildcard,
+                PathStep::key("results"),
+                PathStep::Wildcard,
+            ])
+            .alias("r")
+            .filter(col("number").eq(20));
+
+        assert_eq!(
+            "SELECT * FROM S3Object[*].results[*] r WHERE r.number = 20",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn select_quote_identifiers_and_literals() {
+        let query = Select::from_object()
+            .columns(["long-string", "special_char"])
+            .filter(
+                col("string")
+                    .eq("' OR 1=1 --")
+                    .or(col("Number").in_list([1, 2])),
+            );
+
+        assert_eq!(
+            "SELECT s.\"long-string\", s.special_char FROM S3Object s \
+                WHERE s.string = ''' OR 1=1 --' OR s.\"Number\" IN (1, 2)",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn select_aggregates() {
+        let query = Select::from_object()
+            .column(count_all())
+            .column_as(sum(col("filesize")), "total")
+            .filter((!col("name").like("%.tmp")).and(col("date").is_not_null()));
+
+        assert_eq!(
+            "SELECT COUNT(*), SUM(s.filesize) AS total FROM S3Object s \
+                WHERE NOT s.name LIKE '%.tmp' AND s.date IS NOT NULL",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn select_arithmetic() {
+        let query = Select::from_object()
+            .column_as((col("a") + 1) * col("b"), "c")
+            .filter(col("a").between(1, 10));
+
+        assert_eq!(
+            "SELECT (s.a + 1) * s.b AS c FROM S3Object s WHERE s.a BETWEEN 1 AND 10",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn validate_with_schema() {
+        let schema = Schema::new(vec!["number", "long-string"]);
+
+        assert!(Select::from_object()
+            .columns(["_1", "long-string"])
+            .validate(&schema)
+            .is_ok());
+
+        let error = Select::from_object()
+            .columns(["number", "string"])
+            .validate(&schema)
+            .unwrap_err();
+        assert_eq!(
+            "Unknown column string, expected one of: number, long-string",
+            error.message
+        );
+    }
+
+    #[test]
+    fn select_wide_integers() {
+        let query = Select::from_object().filter(
+            col("size")
+                .gt(10_u64)
+                .and(col("size").lt(u64::MAX))
+                .and(col("offset").gt(i128::MIN))
+                .and(col("records").eq(3_usize)),
+        );
+
+        assert_eq!(
+            "SELECT * FROM S3Object s WHERE s.size > 10 AND s.size < 1.8446744073709552e19 \
+                AND s.offset > -1.7014118346046923e38 AND s.records = 3",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn function_name() {
+        assert_eq!(
+            "CHAR_LENGTH(name)",
+            function("char_length", [col("name")]).to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid function name")]
+    fn function_reject_invalid_name() {
+        function("UPPER(s.secret), LOWER", [col("name")]);
+    }
+}
@@ -0,0 +1,333 @@
+//! Split an S3 Select expression into tokens.
+
+use std::fmt::{Display, Formatter};
+
+use super::QueryError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    /// Keyword or unquoted identifier, as written.
+    Word(String),
+    /// `"identifier"`
+    QuotedIdentifier(String),
+    /// `'string'`
+    String(String),
+    Int(i64),
+    Float(f64),
+    /// `?` placeholder of the query templates.
+    Placeholder,
+    Comma,
+    Dot,
+    Semicolon,
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl Token {
+    /// Check if the token is the keyword, case insensitive.
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::QuotedIdentifier(name) => write!(f, "\"{}\"", name),
+            Token::String(value) => write!(f, "'{}'", value),
+            Token::Int(value) => write!(f, "{}", value),
+            Token::Float(value) => write!(f, "{}", value),
+            Token::Placeholder => write!(f, "?"),
+            Token::Comma => write!(f, ","),
+            Token::Dot => write!(f, "."),
+            Token::Semicolon => write!(f, ";"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::LeftBracket => write!(f, "["),
+            Token::RightBracket => write!(f, "]"),
+            Token::Star => write!(f, "*"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Concat => write!(f, "||"),
+            Token::Eq => write!(f, "="),
+            Token::NotEq => write!(f, "<>"),
+            Token::Lt => write!(f, "<"),
+            Token::LtEq => write!(f, "<="),
+            Token::Gt => write!(f, ">"),
+            Token::GtEq => write!(f, ">="),
+        }
+    }
+}
+
+/// Position of a token in the expression. Lines and columns start at 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    /// Byte offset in the expression.
+    pub offset: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub start: Position,
+    /// Byte offset after the token.
+    pub end: usize,
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn position(&mut self) -> Position {
+        let offset = self
+            .chars
+            .peek()
+            .map(|(offset, _)| *offset)
+            .unwrap_or_else(|| self.input.len());
+        Position {
+            line: self.line,
+            column: self.column,
+            offset,
+        }
+    }
+    fn offset(&mut self) -> usize {
+        self.position().offset
+    }
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+    fn next(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+    fn next_if(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+    /// Read until the closing quote, a doubled quote is an escaped quote.
+    fn quoted(&mut self, quote: char, start: Position) -> Result<String, QueryError> {
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some(c) if c == quote => {
+                    if self.next_if(quote) {
+                        value.push(quote);
+                    } else {
+                        return Ok(value);
+                    }
+                }
+                Some(c) => value.push(c),
+                None => {
+                    return Err(QueryError::new(
+                        format!("Unterminated quote {}", quote),
+                        start,
+                    ))
+                }
+            }
+        }
+    }
+    fn number(&mut self, start: Position) -> Result<Token, QueryError> {
+        let mut is_float = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' => (),
+                '.' if !is_float => is_float = true,
+                'e' | 'E' => {
+                    is_float = true;
+                    self.next();
+                    if matches!(self.peek(), Some('+') | Some('-')) {
+                        self.next();
+                    }
+                    continue;
+                }
+                _ => break,
+            }
+            self.next();
+        }
+        let text = &self.input[start.offset..self.offset()];
+        let invalid = || QueryError::new(format!("Invalid number '{}'", text), start);
+        if is_float {
+            text.parse().map(Token::Float).map_err(|_| invalid())
+        } else {
+            text.parse().map(Token::Int).map_err(|_| invalid())
+        }
+    }
+    fn token(&mut self, c: char, start: Position) -> Result<Token, QueryError> {
+        let token = match c {
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            '*' => Token::Star,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '?' => Token::Placeholder,
+            '=' => Token::Eq,
+            '|' if self.next_if('|') => Token::Concat,
+            '!' if self.next_if('=') => Token::NotEq,
+            '<' if self.next_if('=') => Token::LtEq,
+            '<' if self.next_if('>') => Token::NotEq,
+            '<' => Token::Lt,
+            '>' if self.next_if('=') => Token::GtEq,
+            '>' => Token::Gt,
+            '\'' => Token::String(self.quoted('\'', start)?),
+            '"' => Token::QuotedIdentifier(self.quoted('"', start)?),
+            '.' if !matches!(self.peek(), Some('0'..='9')) => Token::Dot,
+            '.' | '0'..='9' => self.number(start)?,
+            c if c.is_alphabetic() || c == '_' => {
+                while matches!(self.peek(), Some(c) if c.is_alphanumeric() || c == '_') {
+                    self.next();
+                }
+                Token::Word(self.input[start.offset..self.offset()].to_string())
+            }
+            c => {
+                return Err(QueryError::new(
+                    format!("Unexpected character '{}'", c),
+                    start,
+                ))
+            }
+        };
+        Ok(token)
+    }
+}
+
+/// Split the expression into tokens. The comments are skipped.
+pub fn tokenize(input: &str) -> Result<Vec<SpannedToken>, QueryError> {
+    let mut lexer = Lexer {
+        input,
+        chars: input.char_indices().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        let start = lexer.position();
+        let c = match lexer.next() {
+            Some(c) => c,
+            None => break,
+        };
+        if c.is_whitespace() {
+            continue;
+        }
+        if c == '-' && lexer.next_if('-') {
+            while !matches!(lexer.next(), Some('\n') | None) {}
+            continue;
+        }
+        if c == '/' && lexer.next_if('*') {
+            loop {
+                match lexer.next() {
+                    Some('*') if lexer.next_if('/') => break,
+                    Some(_) => (),
+                    None => return Err(QueryError::new("Unterminated comment", start)),
+                }
+            }
+            continue;
+        }
+
+        let token = lexer.token(c, start)?;
+        tokens.push(SpannedToken {
+            token,
+            start,
+            end: lexer.offset(),
+        });
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.token)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_statement() {
+        assert_eq!(
+            vec![
+                Token::Word("select".to_string()),
+                Token::Star,
+                Token::Word("from".to_string()),
+                Token::Word("s3object".to_string()),
+                Token::Word("s".to_string()),
+                Token::Word("where".to_string()),
+                Token::Word("s".to_string()),
+                Token::Dot,
+                Token::QuotedIdentifier("long-string".to_string()),
+                Token::NotEq,
+                Token::String("it's".to_string()),
+                Token::Word("and".to_string()),
+                Token::Word("s".to_string()),
+                Token::Dot,
+                Token::Word("number".to_string()),
+                Token::GtEq,
+                Token::Float(1.5),
+            ],
+            tokens("select * from s3object s -- comment\nwhere s.\"long-string\" != 'it''s' and s.number >= 1.5")
+        );
+    }
+
+    #[test]
+    fn tokenize_positions() {
+        let tokens = tokenize("select\n  s.a").unwrap();
+        assert_eq!(
+            Position {
+                line: 2,
+                column: 3,
+                offset: 9
+            },
+            tokens[1].start
+        );
+    }
+
+    #[test]
+    fn tokenize_unterminated_string() {
+        let error = tokenize("select * from s3object where a = 'abc").unwrap_err();
+        assert_eq!(1, error.line);
+        assert_eq!(34, error.column);
+    }
+}
@@ -0,0 +1,45 @@
+//! S3 Select SQL expressions.
+
+use std::fmt::{Display, Formatter};
+
+pub mod ast;
+pub mod builder;
+pub mod lexer;
+pub mod parser;
+pub mod template;
+
+pub use builder::{
+    avg, col, col_path, count, count_all, date, function, lit, max, min, sum, timestamp, Select,
+};
+pub use parser::{parse, validate};
+pub use template::{Param, Template};
+
+/// Error found in an expression, with its position. Lines and columns start at 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl QueryError {
+    pub fn new<S: Into<String>>(message: S, position: lexer::Position) -> Self {
+        QueryError {
+            message: message.into(),
+            line: position.line,
+            column: position.column,
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
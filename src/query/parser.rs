@@ -0,0 +1,1005 @@
+//! Parse and validate S3 Select expressions before sending them.
+
+use super::ast::*;
+use super::lexer::{tokenize, Position, SpannedToken, Token};
+use super::QueryError;
+use crate::schema::{positional_index, Schema};
+
+/// Functions supported by S3 Select with their minimum and maximum number of arguments.
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("AVG", 1, 1),
+    ("COUNT", 1, 1),
+    ("MAX", 1, 1),
+    ("MIN", 1, 1),
+    ("SUM", 1, 1),
+    ("COALESCE", 1, usize::MAX),
+    ("NULLIF", 2, 2),
+    ("DATE_ADD", 3, 3),
+    ("DATE_DIFF", 3, 3),
+    ("TO_STRING", 2, 2),
+    ("TO_TIMESTAMP", 1, 1),
+    ("UTCNOW", 0, 0),
+    ("CHAR_LENGTH", 1, 1),
+    ("CHARACTER_LENGTH", 1, 1),
+    ("LOWER", 1, 1),
+    ("UPPER", 1, 1),
+    ("SUBSTRING", 2, 3),
+];
+
+/// Keywords that can't be used as unquoted column names or aliases.
+const RESERVED: &[&str] = &[
+    "AND", "AS", "BETWEEN", "BY", "CASE", "CAST", "CROSS", "DISTINCT", "ELSE", "END", "ESCAPE",
+    "EXTRACT", "FALSE", "FROM", "FULL", "GROUP", "HAVING", "IN", "INNER", "IS", "JOIN", "LEFT",
+    "LIKE", "LIMIT", "MISSING", "NOT", "NULL", "ON", "OR", "ORDER", "RIGHT", "SELECT", "THEN",
+    "TRUE", "UNION", "WHEN", "WHERE",
+];
+
+/// Parse the expression.
+pub fn parse(sql: &str) -> Result<SelectStatement, QueryError> {
+    Ok(Parser::new(sql)?.statement()?.0)
+}
+
+/// Parse the expression and check the column references against the schema if given.
+pub fn validate(sql: &str, schema: Option<&Schema>) -> Result<SelectStatement, QueryError> {
+    let (statement, columns) = Parser::new(sql)?.statement()?;
+
+    if let Some(schema) = schema {
+        for (position, column) in &columns {
+            let name = match column.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            if schema.position(name).is_none() {
+                let message = match positional_index(&name.name) {
+                    Some(index) => format!(
+                        "The column {} doesn't exist, the object has {} columns",
+                        index + 1,
+                        schema.len()
+                    ),
+                    None => format!(
+                        "Unknown column {}, expected one of: {}",
+                        name,
+                        schema.columns().join(", ")
+                    ),
+                };
+                return Err(QueryError::new(message, *position));
+            }
+        }
+    }
+
+    Ok(statement)
+}
+
+/// Parse a query template, the `?` parameters are numbered in the order of the expression.
+pub(crate) fn parse_template(sql: &str) -> Result<(SelectStatement, usize), QueryError> {
+    let mut parser = Parser::new(sql)?;
+    parser.parameters = Some(0);
+    let (statement, _) = parser.statement()?;
+
+    let mut parameters = 0;
+    statement.walk(&mut |expr| {
+        if let Expr::Parameter(_) = expr {
+            parameters += 1;
+        }
+    });
+    Ok((statement, parameters))
+}
+
+struct Parser {
+    tokens: Vec<SpannedToken>,
+    index: usize,
+    end: Position,
+    /// Column references with their position, to report errors found after parsing.
+    columns: Vec<(Position, ColumnRef)>,
+    /// Number of `?` parameters read, `None` if the expression is not a template.
+    parameters: Option<usize>,
+}
+
+impl Parser {
+    fn new(sql: &str) -> Result<Self, QueryError> {
+        let line = sql.matches('\n').count() + 1;
+        let column = sql.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        Ok(Parser {
+            tokens: tokenize(sql)?,
+            index: 0,
+            end: Position {
+                line,
+                column,
+                offset: sql.len(),
+            },
+            columns: Vec::new(),
+            parameters: None,
+        })
+    }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|token| &token.token)
+    }
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.index + n).map(|token| &token.token)
+    }
+    fn position(&self) -> Position {
+        self.tokens
+            .get(self.index)
+            .map(|token| token.start)
+            .unwrap_or(self.end)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).map(|token| token.token.clone());
+        self.index += 1;
+        token
+    }
+    fn error<T, S: Into<String>>(&self, message: S) -> Result<T, QueryError> {
+        Err(QueryError::new(message, self.position()))
+    }
+    fn unexpected<T>(&self, expected: &str) -> Result<T, QueryError> {
+        match self.peek() {
+            Some(token) => self.error(format!("Expected {}, found '{}'", expected, token)),
+            None => self.error(format!(
+                "Expected {}, found the end of the expression",
+                expected
+            )),
+        }
+    }
+    fn unsupported<T>(&self, feature: &str) -> Result<T, QueryError> {
+        self.error(format!("{} is not supported by S3 Select", feature))
+    }
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(token) if token.is_keyword(keyword))
+    }
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QueryError> {
+        if self.consume_keyword(keyword) {
+            Ok(())
+        } else {
+            self.unexpected(keyword)
+        }
+    }
+    fn consume(&mut self, expected: &Token) -> bool {
+        if self.peek() == Some(expected) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, expected: &Token) -> Result<(), QueryError> {
+        if self.consume(expected) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("'{}'", expected))
+        }
+    }
+    fn check_unsupported_clause(&self) -> Result<(), QueryError> {
+        let clause = match self.peek() {
+            Some(token) if token.is_keyword("GROUP") => "GROUP BY",
+            Some(token) if token.is_keyword("ORDER") => "ORDER BY",
+            Some(token) if token.is_keyword("HAVING") => "HAVING",
+            Some(token) if token.is_keyword("UNION") => "UNION",
+            Some(Token::Comma) => "JOIN",
+            Some(token)
+                if ["JOIN", "INNER", "LEFT", "RIGHT", "FULL", "CROSS"]
+                    .iter()
+                    .any(|keyword| token.is_keyword(keyword)) =>
+            {
+                "JOIN"
+            }
+            _ => return Ok(()),
+        };
+        self.unsupported(clause)
+    }
+
+    fn statement(mut self) -> Result<(SelectStatement, Vec<(Position, ColumnRef)>), QueryError> {
+        self.expect_keyword("SELECT")?;
+        if self.is_keyword("DISTINCT") {
+            return self.unsupported("DISTINCT");
+        }
+        self.consume_keyword("ALL");
+
+        let select_position = self.position();
+        let projection = self.projection()?;
+        self.expect_keyword("FROM")?;
+        let from = self.object()?;
+        self.check_unsupported_clause()?;
+
+        let mut filter = None;
+        let mut filter_position = self.position();
+        if self.consume_keyword("WHERE") {
+            filter_position = self.position();
+            filter = Some(self.expr()?);
+        }
+        self.check_unsupported_clause()?;
+
+        let mut limit = None;
+        if self.consume_keyword("LIMIT") {
+            match self.next() {
+                Some(Token::Int(value)) if value >= 0 => limit = Some(value as u64),
+                _ => {
+                    self.index -= 1;
+                    return self.unexpected("a positive number");
+                }
+            }
+        }
+        self.consume(&Token::Semicolon);
+        self.check_unsupported_clause()?;
+        if self.peek().is_some() {
+            return self.unexpected("the end of the expression");
+        }
+
+        let mut statement = SelectStatement {
+            projection,
+            from,
+            filter,
+            limit,
+        };
+        resolve_alias(&mut statement, &mut self.columns);
+
+        if let Some(filter) = &statement.filter {
+            if filter.is_aggregate() {
+                return Err(QueryError::new(
+                    "Aggregate functions are not allowed in the WHERE clause",
+                    filter_position,
+                ));
+            }
+        }
+        if let Projection::Items(items) = &statement.projection {
+            let aggregates = items.iter().filter(|item| item.expr.is_aggregate()).count();
+            if aggregates > 0 && aggregates < items.len() {
+                return Err(QueryError::new(
+                    "Aggregate functions can't be mixed with other columns without GROUP BY, \
+                        which is not supported by S3 Select",
+                    select_position,
+                ));
+            }
+        }
+
+        Ok((statement, self.columns))
+    }
+    fn projection(&mut self) -> Result<Projection, QueryError> {
+        if self.consume(&Token::Star) {
+            return Ok(Projection::All);
+        }
+
+        let mut items = Vec::new();
+        loop {
+            let expr = self.expr()?;
+            let alias = if self.consume_keyword("AS") || self.is_identifier() {
+                Some(self.identifier()?)
+            } else {
+                None
+            };
+            items.push(SelectItem { expr, alias });
+            if !self.consume(&Token::Comma) {
+                break;
+            }
+        }
+        Ok(Projection::Items(items))
+    }
+    fn object(&mut self) -> Result<FromClause, QueryError> {
+        if self.peek() == Some(&Token::LeftParen) {
+            return self.error("Subqueries are not supported by S3 Select");
+        }
+        if !self.is_keyword(OBJECT) {
+            return self.unexpected(OBJECT);
+        }
+        self.index += 1;
+
+        let mut path = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Dot) => {
+                    self.index += 1;
+                    path.push(PathStep::Key(self.identifier()?));
+                }
+                Some(Token::LeftBracket) => path.push(self.index_step()?),
+                _ => break,
+            }
+        }
+
+        let alias = if self.consume_keyword("AS") || self.is_identifier() {
+            Some(self.identifier()?)
+        } else {
+            None
+        };
+
+        Ok(FromClause { path, alias })
+    }
+    fn is_identifier(&self) -> bool {
+        match self.peek() {
+            Some(Token::QuotedIdentifier(_)) => true,
+            Some(Token::Word(word)) => !is_reserved(word),
+            _ => false,
+        }
+    }
+    fn identifier(&mut self) -> Result<Identifier, QueryError> {
+        match self.peek() {
+            Some(Token::QuotedIdentifier(name)) => {
+                let identifier = Identifier::quoted(name.clone());
+                self.index += 1;
+                Ok(identifier)
+            }
+            Some(Token::Word(word)) if !is_reserved(word) => {
+                let identifier = Identifier {
+                    name: word.clone(),
+                    quoted: false,
+                };
+                self.index += 1;
+                Ok(identifier)
+            }
+            _ => self.unexpected("an identifier"),
+        }
+    }
+    /// `[*]` or `[0]`
+    fn index_step(&mut self) -> Result<PathStep, QueryError> {
+        self.expect(&Token::LeftBracket)?;
+        let step = match self.peek() {
+            Some(Token::Star) => PathStep::Wildcard,
+            Some(Token::Int(index)) if *index >= 0 => PathStep::Index(*index as u64),
+            _ => return self.unexpected("'*' or an index"),
+        };
+        self.index += 1;
+        self.expect(&Token::RightBracket)?;
+        Ok(step)
+    }
+
+    fn expr(&mut self) -> Result<Expr, QueryError> {
+        self.or()
+    }
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.and()?;
+        while self.consume_keyword("OR") {
+            expr = binary(expr, BinaryOperator::Or, self.and()?);
+        }
+        Ok(expr)
+    }
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.not()?;
+        while self.consume_keyword("AND") {
+            expr = binary(expr, BinaryOperator::And, self.not()?);
+        }
+        Ok(expr)
+    }
+    fn not(&mut self) -> Result<Expr, QueryError> {
+        if self.consume_keyword("NOT") {
+            return Ok(Expr::Unary {
+                op: UnaryOperator::Not,
+                expr: Box::new(self.not()?),
+            });
+        }
+        self.predicate()
+    }
+    fn predicate(&mut self) -> Result<Expr, QueryError> {
+        let expr = self.concat()?;
+
+        let op = match self.peek() {
+            Some(Token::Eq) => Some(BinaryOperator::Eq),
+            Some(Token::NotEq) => Some(BinaryOperator::NotEq),
+            Some(Token::Lt) => Some(BinaryOperator::Lt),
+            Some(Token::LtEq) => Some(BinaryOperator::LtEq),
+            Some(Token::Gt) => Some(BinaryOperator::Gt),
+            Some(Token::GtEq) => Some(BinaryOperator::GtEq),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.index += 1;
+            return Ok(binary(expr, op, self.concat()?));
+        }
+
+        if self.consume_keyword("IS") {
+            let negated = self.consume_keyword("NOT");
+            if self.consume_keyword("NULL") {
+                return Ok(Expr::IsNull {
+                    expr: Box::new(expr),
+                    negated,
+                });
+            }
+            if self.consume_keyword("MISSING") {
+                return Ok(Expr::IsMissing {
+                    expr: Box::new(expr),
+                    negated,
+                });
+            }
+            return self.unexpected("NULL or MISSING");
+        }
+
+        let negated = match self.peek_nth(1) {
+            Some(next) if self.is_keyword("NOT") => {
+                ["LIKE", "BETWEEN", "IN"]
+                    .iter()
+                    .any(|keyword| next.is_keyword(keyword))
+                    && self.consume_keyword("NOT")
+            }
+            _ => false,
+        };
+
+        if self.consume_keyword("LIKE") {
+            let pattern = self.concat()?;
+            let escape = if self.consume_keyword("ESCAPE") {
+                Some(Box::new(self.concat()?))
+            } else {
+                None
+            };
+            return Ok(Expr::Like {
+                expr: Box::new(expr),
+                pattern: Box::new(pattern),
+                escape,
+                negated,
+            });
+        }
+        if self.consume_keyword("BETWEEN") {
+            let low = self.concat()?;
+            self.expect_keyword("AND")?;
+            let high = self.concat()?;
+            return Ok(Expr::Between {
+                expr: Box::new(expr),
+                low: Box::new(low),
+                high: Box::new(high),
+                negated,
+            });
+        }
+        if self.consume_keyword("IN") {
+            self.expect(&Token::LeftParen)?;
+            if self.is_keyword("SELECT") {
+                return self.error("Subqueries are not supported by S3 Select");
+            }
+            let list = self.expr_list(&Token::RightParen)?;
+            return Ok(Expr::InList {
+                expr: Box::new(expr),
+                list,
+                negated,
+            });
+        }
+
+        Ok(expr)
+    }
+    fn concat(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.additive()?;
+        while self.consume(&Token::Concat) {
+            expr = binary(expr, BinaryOperator::Concat, self.additive()?);
+        }
+        Ok(expr)
+    }
+    fn additive(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinaryOperator::Plus,
+                Some(Token::Minus) => BinaryOperator::Minus,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = binary(expr, op, self.multiplicative()?);
+        }
+    }
+    fn multiplicative(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOperator::Multiply,
+                Some(Token::Slash) => BinaryOperator::Divide,
+                Some(Token::Percent) => BinaryOperator::Modulo,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = binary(expr, op, self.unary()?);
+        }
+    }
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        if self.consume(&Token::Minus) {
+            return Ok(match self.unary()? {
+                Expr::Literal(Literal::Int(value)) => Expr::Literal(Literal::Int(-value)),
+                Expr::Literal(Literal::Float(value)) => Expr::Literal(Literal::Float(-value)),
+                expr => Expr::Unary {
+                    op: UnaryOperator::Minus,
+                    expr: Box::new(expr),
+                },
+            });
+        }
+        self.consume(&Token::Plus);
+        self.primary()
+    }
+    fn primary(&mut self) -> Result<Expr, QueryError> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return self.unexpected("an expression"),
+        };
+
+        match token {
+            Token::Int(value) => {
+                self.index += 1;
+                Ok(Expr::Literal(Literal::Int(value)))
+            }
+            Token::Float(value) => {
+                self.index += 1;
+                Ok(Expr::Literal(Literal::Float(value)))
+            }
+            Token::String(value) => {
+                self.index += 1;
+                Ok(Expr::Literal(Literal::String(value)))
+            }
+            Token::LeftParen => {
+                self.index += 1;
+                if self.is_keyword("SELECT") {
+                    return self.error("Subqueries are not supported by S3 Select");
+                }
+                let expr = self.expr()?;
+                self.expect(&Token::RightParen)?;
+                Ok(expr)
+            }
+            Token::Placeholder => match self.parameters {
+                Some(index) => {
+                    self.index += 1;
+                    self.parameters = Some(index + 1);
+                    Ok(Expr::Parameter(index))
+                }
+                None => self.error("Unbound parameter '?'"),
+            },
+            Token::QuotedIdentifier(_) => self.column(),
+            Token::Word(word) => {
+                let keyword = word.to_uppercase();
+                match keyword.as_str() {
+                    "TRUE" | "FALSE" | "NULL" | "MISSING" => {
+                        self.index += 1;
+                        Ok(Expr::Literal(match keyword.as_str() {
+                            "TRUE" => Literal::Bool(true),
+                            "FALSE" => Literal::Bool(false),
+                            "NULL" => Literal::Null,
+                            _ => Literal::Missing,
+                        }))
+                    }
+                    "CAST" => self.cast(),
+                    "EXTRACT" => self.extract(),
+                    "CASE" => self.case(),
+                    "TRIM" if self.peek_nth(1) == Some(&Token::LeftParen) => self.trim(),
+                    "SUBSTRING" if self.peek_nth(1) == Some(&Token::LeftParen) => self.substring(),
+                    "SELECT" => self.error("Subqueries are not supported by S3 Select"),
+                    _ if self.peek_nth(1) == Some(&Token::LeftParen) => self.function(keyword),
+                    _ if is_reserved(&word) => self.unexpected("an expression"),
+                    _ => self.column(),
+                }
+            }
+            _ => self.unexpected("an expression"),
+        }
+    }
+    fn column(&mut self) -> Result<Expr, QueryError> {
+        let position = self.position();
+        let mut path = vec![PathStep::Key(self.identifier()?)];
+        loop {
+            match self.peek() {
+                Some(Token::Dot) => {
+                    self.index += 1;
+                    path.push(PathStep::Key(self.identifier()?));
+                }
+                Some(Token::LeftBracket) => path.push(self.index_step()?),
+                _ => break,
+            }
+        }
+        let column = ColumnRef {
+            qualifier: None,
+            path,
+        };
+        self.columns.push((position, column.clone()));
+        Ok(Expr::Column(column))
+    }
+    fn expr_list(&mut self, end: &Token) -> Result<Vec<Expr>, QueryError> {
+        let mut list = Vec::new();
+        if self.consume(end) {
+            return Ok(list);
+        }
+        loop {
+            list.push(self.expr()?);
+            if self.consume(end) {
+                return Ok(list);
+            }
+            self.expect(&Token::Comma)?;
+        }
+    }
+    fn function(&mut self, name: String) -> Result<Expr, QueryError> {
+        let position = self.position();
+        let (min, max) = match FUNCTIONS.iter().find(|(function, _, _)| *function == name) {
+            Some((_, min, max)) => (*min, *max),
+            None => return self.error(format!("Unknown function {}", name)),
+        };
+        self.index += 1;
+        self.expect(&Token::LeftParen)?;
+
+        let args = if name == "COUNT" && self.peek() == Some(&Token::Star) {
+            self.index += 1;
+            self.expect(&Token::RightParen)?;
+            vec![Expr::Wildcard]
+        } else if name == "DATE_ADD" || name == "DATE_DIFF" {
+            let part = self.date_part()?;
+            self.expect(&Token::Comma)?;
+            let mut args = vec![Expr::DatePart(part)];
+            args.append(&mut self.expr_list(&Token::RightParen)?);
+            args
+        } else {
+            self.expr_list(&Token::RightParen)?
+        };
+
+        if args.len() < min || args.len() > max {
+            return Err(QueryError::new(
+                format!("Wrong number of arguments for {}: {}", name, args.len()),
+                position,
+            ));
+        }
+        if is_aggregate_function(&name) && args.iter().any(Expr::is_aggregate) {
+            return Err(QueryError::new(
+                "Aggregate functions can't be nested",
+                position,
+            ));
+        }
+
+        Ok(Expr::Function { name, args })
+    }
+    fn date_part(&mut self) -> Result<DatePart, QueryError> {
+        let part = match self.peek() {
+            Some(Token::Word(word)) => match word.to_uppercase().as_str() {
+                "YEAR" => DatePart::Year,
+                "MONTH" => DatePart::Month,
+                "DAY" => DatePart::Day,
+                "HOUR" => DatePart::Hour,
+                "MINUTE" => DatePart::Minute,
+                "SECOND" => DatePart::Second,
+                "TIMEZONE_HOUR" => DatePart::TimezoneHour,
+                "TIMEZONE_MINUTE" => DatePart::TimezoneMinute,
+                _ => return self.unexpected("a date part"),
+            },
+            _ => return self.unexpected("a date part"),
+        };
+        self.index += 1;
+        Ok(part)
+    }
+    fn cast(&mut self) -> Result<Expr, QueryError> {
+        self.index += 1;
+        self.expect(&Token::LeftParen)?;
+        let expr = self.expr()?;
+        self.expect_keyword("AS")?;
+        let data_type = match self.peek() {
+            Some(Token::Word(word)) => match word.to_uppercase().as_str() {
+                "BOOL" | "BOOLEAN" => DataType::Bool,
+                "INT" => DataType::Int,
+                "INTEGER" => DataType::Integer,
+                "STRING" => DataType::String,
+                "FLOAT" => DataType::Float,
+                "DECIMAL" => DataType::Decimal,
+                "NUMERIC" => DataType::Numeric,
+                "TIMESTAMP" => DataType::Timestamp,
+                _ => return self.unexpected("a data type"),
+            },
+            _ => return self.unexpected("a data type"),
+        };
+        self.index += 1;
+        self.expect(&Token::RightParen)?;
+        Ok(Expr::Cast {
+            expr: Box::new(expr),
+            data_type,
+        })
+    }
+    fn extract(&mut self) -> Result<Expr, QueryError> {
+        self.index += 1;
+        self.expect(&Token::LeftParen)?;
+        let part = self.date_part()?;
+        self.expect_keyword("FROM")?;
+        let expr = self.expr()?;
+        self.expect(&Token::RightParen)?;
+        Ok(Expr::Extract {
+            part,
+            expr: Box::new(expr),
+        })
+    }
+    fn case(&mut self) -> Result<Expr, QueryError> {
+        self.index += 1;
+        let operand = if self.is_keyword("WHEN") {
+            None
+        } else {
+            Some(Box::new(self.expr()?))
+        };
+        let mut branches = Vec::new();
+        while self.consume_keyword("WHEN") {
+            let condition = self.expr()?;
+            self.expect_keyword("THEN")?;
+            branches.push((condition, self.expr()?));
+        }
+        if branches.is_empty() {
+            return self.unexpected("WHEN");
+        }
+        let default = if self.consume_keyword("ELSE") {
+            Some(Box::new(self.expr()?))
+        } else {
+            None
+        };
+        self.expect_keyword("END")?;
+        Ok(Expr::Case {
+            operand,
+            branches,
+            default,
+        })
+    }
+    /// `TRIM([[LEADING | TRAILING | BOTH] [characters] FROM] expr)`
+    fn trim(&mut self) -> Result<Expr, QueryError> {
+        self.index += 1;
+        self.expect(&Token::LeftParen)?;
+        let spec = if self.consume_keyword("LEADING") {
+            Some(TrimSpec::Leading)
+        } else if self.consume_keyword("TRAILING") {
+            Some(TrimSpec::Trailing)
+        } else if self.consume_keyword("BOTH") {
+            Some(TrimSpec::Both)
+        } else {
+            None
+        };
+
+        let mut characters = None;
+        let expr = if spec.is_some() && self.consume_keyword("FROM") {
+            self.expr()?
+        } else {
+            let first = self.expr()?;
+            if self.consume_keyword("FROM") {
+                characters = Some(Box::new(first));
+                self.expr()?
+            } else if spec.is_some() {
+                return self.unexpected("FROM");
+            } else {
+                first
+            }
+        };
+        self.expect(&Token::RightParen)?;
+        Ok(Expr::Trim {
+            spec,
+            characters,
+            expr: Box::new(expr),
+        })
+    }
+    /// `SUBSTRING(expr FROM start FOR length)` or `SUBSTRING(expr, start, length)`
+    fn substring(&mut self) -> Result<Expr, QueryError> {
+        let position = self.position();
+        self.index += 1;
+        self.expect(&Token::LeftParen)?;
+        let mut args = vec![self.expr()?];
+        if self.consume_keyword("FROM") {
+            args.push(self.expr()?);
+            if self.consume_keyword("FOR") {
+                args.push(self.expr()?);
+            }
+            self.expect(&Token::RightParen)?;
+        } else {
+            self.expect(&Token::Comma)?;
+            args.append(&mut self.expr_list(&Token::RightParen)?);
+        }
+        if args.len() > 3 {
+            return Err(QueryError::new(
+                format!("Wrong number of arguments for SUBSTRING: {}", args.len()),
+                position,
+            ));
+        }
+        Ok(Expr::Function {
+            name: "SUBSTRING".to_string(),
+            args,
+        })
+    }
+}
+
+fn binary(left: Expr, op: BinaryOperator, right: Expr) -> Expr {
+    Expr::Binary {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
+}
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(word))
+}
+
+/// Move the alias of the object from the column path to the qualifier.
+fn resolve_alias(statement: &mut SelectStatement, columns: &mut [(Position, ColumnRef)]) {
+    let alias = match statement.from.alias.clone() {
+        Some(alias) => alias,
+        None => return,
+    };
+    let resolve = |column: &mut ColumnRef| {
+        if column.qualifier.is_some() {
+            return;
+        }
+        if let Some(PathStep::Key(first)) = column.path.first() {
+            if first.name == alias.name || (!first.quoted && alias.matches(&first.name)) {
+                column.qualifier = Some(alias.clone());
+                column.path.remove(0);
+            }
+        }
+    };
+
+    statement.walk_mut(&mut |expr| {
+        if let Expr::Column(column) = expr {
+            resolve(column);
+        }
+    });
+    columns.iter_mut().for_each(|(_, column)| resolve(column));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{col, date, Select};
+
+    #[test]
+    fn parse_and_render() {
+        let queries = [
+            "SELECT * FROM S3Object",
+            "SELECT s._1, s._2 FROM S3Object s WHERE s._1 = 'a' LIMIT 10",
+            "SELECT * FROM S3Object[*].results[*] r WHERE r.number = 20",
+            "SELECT s.\"long-string\" AS l FROM S3Object s WHERE s.a IS NOT NULL",
+            "SELECT COUNT(*), SUM(CAST(s.size AS INT)) FROM S3Object s",
+            "SELECT s.name FROM S3Object s WHERE s.name NOT LIKE '%.tmp' ESCAPE '!'",
+            "SELECT s.a FROM S3Object s WHERE s.a BETWEEN 1 AND 10 OR s.a IN (20, 30)",
+            "SELECT EXTRACT(YEAR FROM TO_TIMESTAMP(s.date)) FROM S3Object s",
+            "SELECT DATE_ADD(DAY, 1, UTCNOW()) FROM S3Object s",
+            "SELECT TRIM(LEADING '0' FROM s.a), SUBSTRING(s.b, 1, 2) FROM S3Object s",
+            "SELECT CASE WHEN s.a > 1 THEN 'big' ELSE 'small' END FROM S3Object s",
+        ];
+
+        for query in queries.iter() {
+            assert_eq!(*query, parse(query).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn parse_normalize() {
+        assert_eq!(
+            "SELECT SUBSTRING(s.a, 2, 3) FROM S3Object s WHERE s.a <> 'x' AND s.b = -1",
+            parse(
+                "select substring(s.a from 2 for 3) from s3object s where s.a != 'x' and s.b = -1;"
+            )
+            .unwrap()
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn parse_builder_output() {
+        let query = Select::from_object()
+            .columns(["number", "long-string"])
+            .filter(col("number").eq(20).and(col("date").gt(date("2020-01-01"))))
+            .limit(10);
+
+        assert_eq!(query.clone().build(), parse(&query.to_string()).unwrap());
+    }
+
+    #[test]
+    fn parse_resolve_alias() {
+        let statement = parse("SELECT s.a.b, c FROM S3Object s").unwrap();
+        let expected = Projection::Items(vec![
+            SelectItem {
+                expr: Expr::Column(ColumnRef {
+                    qualifier: Some(Identifier::new("s")),
+                    path: vec![PathStep::key("a"), PathStep::key("b")],
+                }),
+                alias: None,
+            },
+            SelectItem {
+                expr: Expr::Column(ColumnRef {
+                    qualifier: None,
+                    path: vec![PathStep::key("c")],
+                }),
+                alias: None,
+            },
+        ]);
+        assert_eq!(expected, statement.projection);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let errors = [
+            (
+                "SELECT * FROM S3Object s WHERE",
+                1,
+                31,
+                "Expected an expression",
+            ),
+            ("SELECT * FORM S3Object", 1, 10, "Expected FROM"),
+            (
+                "SELECT *\nFROM S3Object s\nWHERE s.a = = 1",
+                3,
+                13,
+                "Expected an expression",
+            ),
+            (
+                "SELECT * FROM S3Object s, S3Object t",
+                1,
+                25,
+                "JOIN is not supported",
+            ),
+            (
+                "SELECT * FROM S3Object s JOIN S3Object t",
+                1,
+                26,
+                "JOIN is not supported",
+            ),
+            (
+                "SELECT * FROM S3Object s WHERE s.a IN (SELECT 1 FROM S3Object)",
+                1,
+                40,
+                "Subqueries are not supported",
+            ),
+            (
+                "SELECT * FROM (SELECT * FROM S3Object)",
+                1,
+                15,
+                "Subqueries are not supported",
+            ),
+            (
+                "SELECT s.a FROM S3Object s GROUP BY s.a",
+                1,
+                28,
+                "GROUP BY is not supported",
+            ),
+            (
+                "SELECT DISTINCT s.a FROM S3Object s",
+                1,
+                8,
+                "DISTINCT is not supported",
+            ),
+            (
+                "SELECT FOO(s.a) FROM S3Object s",
+                1,
+                8,
+                "Unknown function FOO",
+            ),
+            (
+                "SELECT COUNT(*), s.a FROM S3Object s",
+                1,
+                8,
+                "Aggregate functions can't be mixed",
+            ),
+            (
+                "SELECT * FROM S3Object s WHERE COUNT(*) > 1",
+                1,
+                32,
+                "Aggregate functions are not allowed",
+            ),
+        ];
+
+        for (query, line, column, message) in errors.iter() {
+            let error = parse(query).unwrap_err();
+            assert!(
+                error.message.starts_with(message),
+                "{}: {}",
+                query,
+                error.message
+            );
+            assert_eq!((*line, *column), (error.line, error.column), "{}", query);
+        }
+    }
+
+    #[test]
+    fn validate_with_schema() {
+        let schema = Schema::new(vec!["number", "long-string"]);
+
+        assert!(validate(
+            "SELECT s.number, s.\"long-string\" FROM S3Object s",
+            Some(&schema)
+        )
+        .is_ok());
+        assert!(validate("SELECT s._2 FROM S3Object s", Some(&schema)).is_ok());
+
+        let error = validate(
+            "SELECT s.number\nFROM S3Object s WHERE s.numbr = 1",
+            Some(&schema),
+        )
+        .unwrap_err();
+        assert_eq!((2, 23), (error.line, error.column));
+        assert!(error.message.starts_with("Unknown column numbr"));
+
+        let error = validate("SELECT s._3 FROM S3Object s", Some(&schema)).unwrap_err();
+        assert_eq!((1, 8), (error.line, error.column));
+    }
+}
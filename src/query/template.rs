@@ -0,0 +1,234 @@
+//! Query templates with `?` parameters bound to typed values.
+//!
+//! ```
+//! use surf_bucket_select::query::Template;
+//!
+//! let template = Template::parse("select * from s3object s where s.tenant = ? and s.number > ?")?;
+//! let query = template.bind(&["it's me".into(), 10.into()])?;
+//!
+//! assert_eq!(
+//!     "SELECT * FROM S3Object s WHERE s.tenant = 'it''s me' AND s.number > 10",
+//!     query.to_string()
+//! );
+//! # Ok::<(), surf_bucket_select::query::QueryError>(())
+//! ```
+
+use std::convert::TryFrom;
+
+use chrono::{DateTime, FixedOffset, SecondsFormat, TimeZone};
+
+use super::ast::{Expr, Literal, SelectStatement};
+use super::lexer::{tokenize, Position, Token};
+use super::parser::parse_template;
+use super::{lit, timestamp, QueryError};
+
+/// Value bound to a parameter. The value is written as a S3 Select literal of its type, so it's
+/// never read as a part of the expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Param {
+    Null,
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// Written as `CAST('2020-01-01T10:00:00Z' AS TIMESTAMP)`.
+    Timestamp(DateTime<FixedOffset>),
+}
+
+impl From<Param> for Expr {
+    fn from(param: Param) -> Expr {
+        match param {
+            Param::Null => lit(Literal::Null),
+            Param::String(value) => lit(value),
+            Param::Int(value) => lit(value),
+            Param::Float(value) => lit(value),
+            Param::Bool(value) => lit(value),
+            Param::Timestamp(value) => {
+                timestamp(value.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+        }
+    }
+}
+
+macro_rules! param_from {
+    ($variant:ident, $($type:ty),*) => {
+        $(
+            impl From<$type> for Param {
+                fn from(value: $type) -> Param {
+                    Param::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+param_from!(String, String, &str);
+param_from!(Int, i8, i16, i32, i64, u8, u16, u32);
+param_from!(Float, f32, f64);
+param_from!(Bool, bool);
+
+/// The integers out of the range of `i64` are bound as floats.
+macro_rules! param_from_wide_integer {
+    ($($type:ty),*) => {
+        $(
+            impl From<$type> for Param {
+                fn from(value: $type) -> Param {
+                    i64::try_from(value).map_or(Param::Float(value as f64), Param::Int)
+                }
+            }
+        )*
+    };
+}
+
+param_from_wide_integer!(u64, usize, i128);
+
+impl<Tz: TimeZone> From<DateTime<Tz>> for Param {
+    fn from(value: DateTime<Tz>) -> Param {
+        Param::Timestamp(value.fixed_offset())
+    }
+}
+
+impl<T: Into<Param>> From<Option<T>> for Param {
+    fn from(value: Option<T>) -> Param {
+        value.map(Into::into).unwrap_or(Param::Null)
+    }
+}
+
+/// Statement parsed once and bound to new values for each select.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    statement: SelectStatement,
+    /// Position of the parameters in the template.
+    positions: Vec<Position>,
+    end: Position,
+}
+
+impl Template {
+    /// Parse the template, `?` marks a parameter.
+    pub fn parse(sql: &str) -> Result<Self, QueryError> {
+        let (statement, _) = parse_template(sql)?;
+        let tokens = tokenize(sql)?;
+        let end = tokens.last().map(|token| token.start).unwrap_or_default();
+        let positions = tokens
+            .into_iter()
+            .filter(|token| token.token == Token::Placeholder)
+            .map(|token| token.start)
+            .collect();
+
+        Ok(Template {
+            statement,
+            positions,
+            end,
+        })
+    }
+    /// Number of parameters to bind.
+    pub fn parameters(&self) -> usize {
+        self.positions.len()
+    }
+    /// Replace the parameters by the values, in order. A value is expected for each parameter.
+    pub fn bind(&self, values: &[Param]) -> Result<SelectStatement, QueryError> {
+        if values.len() < self.positions.len() {
+            return Err(QueryError::new(
+                format!("Missing value for the parameter {}", values.len() + 1),
+                self.positions[values.len()],
+            ));
+        }
+        if values.len() > self.positions.len() {
+            return Err(QueryError::new(
+                format!(
+                    "Expected {} values, got {}",
+                    self.positions.len(),
+                    values.len()
+                ),
+                self.end,
+            ));
+        }
+
+        let mut statement = self.statement.clone();
+        statement.walk_mut(&mut |expr| {
+            if let Expr::Parameter(index) = expr {
+                *expr = values[*index].clone().into();
+            }
+        });
+
+        Ok(statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::parse;
+
+    #[test]
+    fn bind_literals() {
+        let template = Template::parse(
+            "select s.name from s3object s where s.tenant = ? and s.number > ? and s.ratio < ? \
+                and s.active = ? and s.date >= ? and s.label = ?",
+        )
+        .unwrap();
+        assert_eq!(6, template.parameters());
+
+        let date = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 10, 0, 0)
+            .unwrap();
+        let query = template
+            .bind(&[
+                "acme".into(),
+                (-5).into(),
+                0.5.into(),
+                true.into(),
+                date.into(),
+                None::<i64>.into(),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            "SELECT s.name FROM S3Object s WHERE s.tenant = 'acme' AND s.number > -5 \
+                AND s.ratio < 0.5 AND s.active = TRUE \
+                AND s.date >= CAST('2020-01-01T10:00:00+02:00' AS TIMESTAMP) \
+                AND s.label = NULL",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn bind_can_not_inject() {
+        let template = Template::parse("select * from s3object s where s.tenant = ?").unwrap();
+        let query = template.bind(&["' or '1'='1".into()]).unwrap();
+        assert_eq!(query, parse(&query.to_string()).unwrap());
+
+        let template = Template::parse("select * from s3object s where s.tenant = -?").unwrap();
+        let query = template.bind(&[(-1).into()]).unwrap();
+        assert_eq!(
+            "SELECT * FROM S3Object s WHERE s.tenant = -(-1)",
+            query.to_string()
+        );
+    }
+
+    #[test]
+    fn bind_wrong_number_of_values() {
+        let template =
+            Template::parse("select * from s3object s\nwhere s.a = ? or s.b = ?").unwrap();
+
+        let error = template.bind(&[1.into()]).unwrap_err();
+        assert_eq!("Missing value for the parameter 2", error.message);
+        assert_eq!((2, 24), (error.line, error.column));
+
+        let error = template.bind(&[1.into(), 2.into(), 3.into()]).unwrap_err();
+        assert_eq!("Expected 2 values, got 3", error.message);
+    }
+
+    #[test]
+    fn bind_wide_integers() {
+        let template =
+            Template::parse("select * from s3object s where s.a = ? or s.b = ?").unwrap();
+        let query = template.bind(&[10_u64.into(), u64::MAX.into()]).unwrap();
+
+        assert_eq!(
+            "SELECT * FROM S3Object s WHERE s.a = 10 OR s.b = 1.8446744073709552e19",
+            query.to_string()
+        );
+    }
+}
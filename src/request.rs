@@ -0,0 +1,374 @@
+//! Build the select requests and check the options that S3 rejects for the input format.
+//!
+//! ```
+//! use surf_bucket_select::request::SelectRequest;
+//!
+//! let request = SelectRequest::new("my-bucket", "data/part-0.parquet", "SELECT s.name FROM S3Object s")
+//!     .parquet_input()
+//!     .build()
+//!     .unwrap();
+//!
+//! assert!(request.input_serialization.parquet.is_some());
+//! assert!(request.output_serialization.csv.is_some());
+//! ```
+
+use rusoto_s3::{
+    CSVInput, CSVOutput, HeadObjectOutput, JSONInput, JSONOutput, OutputSerialization,
+    ParquetInput, RequestProgress, ScanRange, SelectObjectContentRequest,
+};
+
+use crate::error::{Error, Result};
+use crate::sse::ServerSideEncryption;
+
+/// Compression of the objects that are not compressed.
+pub const COMPRESSION_NONE: &str = "NONE";
+pub const COMPRESSION_GZIP: &str = "GZIP";
+pub const COMPRESSION_BZIP2: &str = "BZIP2";
+/// Type of the JSON input with one document per line.
+pub const JSON_LINES: &str = "LINES";
+
+/// Builder of a [`SelectObjectContentRequest`]. The records are returned as CSV by default.
+#[derive(Clone, Debug)]
+pub struct SelectRequest {
+    request: SelectObjectContentRequest,
+}
+
+impl SelectRequest {
+    pub fn new<B: Into<String>, K: Into<String>, E: Into<String>>(
+        bucket: B,
+        key: K,
+        expression: E,
+    ) -> Self {
+        SelectRequest {
+            request: SelectObjectContentRequest {
+                bucket: bucket.into(),
+                key: key.into(),
+                expression: expression.into(),
+                expression_type: "SQL".to_string(),
+                output_serialization: OutputSerialization {
+                    csv: Some(CSVOutput::default()),
+                    json: None,
+                },
+                ..Default::default()
+            },
+        }
+    }
+    /// Read the object as CSV.
+    pub fn csv_input(mut self, csv: CSVInput) -> Self {
+        let input = &mut self.request.input_serialization;
+        input.csv = Some(csv);
+        input.json = None;
+        input.parquet = None;
+        self
+    }
+    /// Read the object as JSON.
+    pub fn json_input(mut self, json: JSONInput) -> Self {
+        let input = &mut self.request.input_serialization;
+        input.csv = None;
+        input.json = Some(json);
+        input.parquet = None;
+        self
+    }
+    /// Read the object as Parquet. The object is always read as a whole, only the columns of
+    /// the query are scanned.
+    pub fn parquet_input(mut self) -> Self {
+        let input = &mut self.request.input_serialization;
+        input.csv = None;
+        input.json = None;
+        input.parquet = Some(ParquetInput {});
+        self
+    }
+    /// Compression of the whole object: `NONE`, `GZIP` or `BZIP2`.
+    pub fn compression_type<S: Into<String>>(mut self, compression_type: S) -> Self {
+        self.request.input_serialization.compression_type = Some(compression_type.into());
+        self
+    }
+    /// Set the compression type from the suffix of the key, `.gz` or `.bz2`, if it's not set.
+    pub fn detect_compression(mut self) -> Self {
+        let input = &mut self.request.input_serialization;
+        if input.compression_type.is_none() && input.parquet.is_none() {
+            input.compression_type = compression_from_key(&self.request.key).map(str::to_string);
+        }
+        self
+    }
+    /// Select only the records starting in the byte range, the end is included.
+    pub fn scan_range(mut self, start: Option<i64>, end: Option<i64>) -> Self {
+        self.request.scan_range = Some(ScanRange { start, end });
+        self
+    }
+    /// Return the records as CSV.
+    pub fn csv_output(mut self, csv: CSVOutput) -> Self {
+        self.request.output_serialization = OutputSerialization {
+            csv: Some(csv),
+            json: None,
+        };
+        self
+    }
+    /// Return the records as JSON.
+    pub fn json_output(mut self, json: JSONOutput) -> Self {
+        self.request.output_serialization = OutputSerialization {
+            csv: None,
+            json: Some(json),
+        };
+        self
+    }
+    /// Receive `Progress` events while the object is scanned.
+    pub fn request_progress(mut self, enabled: bool) -> Self {
+        self.request.request_progress = Some(RequestProgress {
+            enabled: Some(enabled),
+        });
+        self
+    }
+    pub fn encryption(mut self, encryption: &ServerSideEncryption) -> Self {
+        encryption.apply(&mut self.request);
+        self
+    }
+    pub fn expected_bucket_owner<S: Into<String>>(mut self, owner: S) -> Self {
+        self.request.expected_bucket_owner = Some(owner.into());
+        self
+    }
+    /// Check the options and return the request.
+    pub fn build(self) -> Result<SelectObjectContentRequest> {
+        let input = &self.request.input_serialization;
+        if input.csv.is_none() && input.json.is_none() && input.parquet.is_none() {
+            return Err(Error::InvalidRequest(
+                "The input format is missing, CSV, JSON or Parquet".to_string(),
+            ));
+        }
+        validate(&self.request)?;
+        Ok(self.request)
+    }
+}
+
+impl From<SelectObjectContentRequest> for SelectRequest {
+    fn from(request: SelectObjectContentRequest) -> Self {
+        SelectRequest { request }
+    }
+}
+
+/// Compression type of the object according to the suffix of its key.
+pub fn compression_from_key(key: &str) -> Option<&'static str> {
+    let key = key.to_ascii_lowercase();
+    if key.ends_with(".gz") || key.ends_with(".gzip") {
+        Some(COMPRESSION_GZIP)
+    } else if key.ends_with(".bz2") {
+        Some(COMPRESSION_BZIP2)
+    } else {
+        None
+    }
+}
+
+/// The key ends with the extension of an uncompressed CSV, JSON or Parquet object.
+pub(crate) fn is_uncompressed_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    [
+        ".csv", ".tsv", ".txt", ".json", ".jsonl", ".ndjson", ".parquet",
+    ]
+    .iter()
+    .any(|extension| key.ends_with(extension))
+}
+
+/// Compression type of the object according to its `Content-Encoding` or its `Content-Type`.
+pub fn compression_from_head(head: &HeadObjectOutput) -> Option<&'static str> {
+    let encodings = head
+        .content_encoding
+        .iter()
+        .flat_map(|encoding| encoding.split(','));
+    let content_type = head
+        .content_type
+        .as_deref()
+        .and_then(|content_type| content_type.split(';').next());
+
+    encodings.chain(content_type).find_map(|value| {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" | "application/gzip" | "application/x-gzip" => Some(COMPRESSION_GZIP),
+            "bzip2" | "x-bzip2" | "application/x-bzip2" | "application/x-bzip" => {
+                Some(COMPRESSION_BZIP2)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Check the serialization options that S3 refuses together:
+/// - a single input and a single output format,
+/// - no whole object compression and no scan range for Parquet,
+/// - no scan range for the compressed objects and for the JSON documents.
+pub fn validate(request: &SelectObjectContentRequest) -> Result<()> {
+    let input = &request.input_serialization;
+    let formats = [
+        input.csv.is_some(),
+        input.json.is_some(),
+        input.parquet.is_some(),
+    ];
+    if formats.iter().filter(|format| **format).count() > 1 {
+        return Err(Error::InvalidRequest(
+            "Only one input format can be set, CSV, JSON or Parquet".to_string(),
+        ));
+    }
+    let output = &request.output_serialization;
+    if output.csv.is_some() && output.json.is_some() {
+        return Err(Error::InvalidRequest(
+            "Only one output format can be set, CSV or JSON".to_string(),
+        ));
+    }
+
+    let compressed = input
+        .compression_type
+        .as_deref()
+        .is_some_and(|compression| !compression.eq_ignore_ascii_case(COMPRESSION_NONE));
+    if input.parquet.is_some() {
+        if compressed {
+            return Err(Error::InvalidRequest(
+                "The Parquet objects are compressed by column, the compression type must be NONE"
+                    .to_string(),
+            ));
+        }
+        if request.scan_range.is_some() {
+            return Err(Error::InvalidRequest(
+                "The scan range is not supported for the Parquet objects".to_string(),
+            ));
+        }
+    }
+    if request.scan_range.is_some() {
+        if compressed {
+            return Err(Error::InvalidRequest(format!(
+                "The scan range is not supported for the compressed objects, '{}' is compressed \
+                    with {}",
+                request.key,
+                input.compression_type.as_deref().unwrap_or_default()
+            )));
+        }
+        if let Some(json) = &input.json {
+            if !json
+                .type_
+                .as_deref()
+                .is_some_and(|type_| type_.eq_ignore_ascii_case(JSON_LINES))
+            {
+                return Err(Error::InvalidRequest(
+                    "The scan range is only supported for the JSON LINES objects".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parquet() -> SelectRequest {
+        SelectRequest::new(
+            "my-bucket",
+            "data/part-0.parquet",
+            "SELECT * FROM S3Object s",
+        )
+        .parquet_input()
+    }
+
+    #[test]
+    fn build_parquet_request() {
+        let request = parquet()
+            .compression_type("NONE")
+            .json_output(JSONOutput::default())
+            .build()
+            .unwrap();
+
+        assert_eq!(Some(ParquetInput {}), request.input_serialization.parquet);
+        assert_eq!(None, request.input_serialization.csv);
+        assert_eq!(None, request.output_serialization.csv);
+        assert_eq!("SQL", request.expression_type);
+    }
+
+    #[test]
+    fn reject_parquet_options() {
+        assert!(matches!(
+            parquet().scan_range(Some(0), Some(1024)).build(),
+            Err(Error::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            parquet().compression_type("GZIP").build(),
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn reject_scan_range() {
+        let request = SelectRequest::new("my-bucket", "data/part-0.json", "SELECT * FROM S3Object");
+
+        assert!(matches!(
+            request
+                .clone()
+                .json_input(JSONInput::default())
+                .scan_range(Some(0), None)
+                .build(),
+            Err(Error::InvalidRequest(_))
+        ));
+        assert!(request
+            .clone()
+            .json_input(JSONInput {
+                type_: Some("LINES".to_string()),
+            })
+            .scan_range(Some(0), None)
+            .build()
+            .is_ok());
+        assert!(matches!(
+            request
+                .csv_input(CSVInput::default())
+                .compression_type("GZIP")
+                .scan_range(Some(0), None)
+                .build(),
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn detect_compression() {
+        assert_eq!(Some("GZIP"), compression_from_key("data/part-0.csv.gz"));
+        assert_eq!(Some("BZIP2"), compression_from_key("data/part-0.JSON.BZ2"));
+        assert_eq!(None, compression_from_key("data/part-0.csv"));
+
+        let head = |content_encoding: Option<&str>, content_type: Option<&str>| HeadObjectOutput {
+            content_encoding: content_encoding.map(str::to_string),
+            content_type: content_type.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some("GZIP"),
+            compression_from_head(&head(Some("aws-chunked, gzip"), Some("text/csv")))
+        );
+        assert_eq!(
+            Some("BZIP2"),
+            compression_from_head(&head(None, Some("application/x-bzip2; charset=binary")))
+        );
+        assert_eq!(None, compression_from_head(&head(None, Some("text/csv"))));
+
+        let request =
+            SelectRequest::new("my-bucket", "data/part-0.csv.gz", "SELECT * FROM S3Object")
+                .csv_input(CSVInput::default())
+                .detect_compression();
+        assert_eq!(
+            Some("GZIP"),
+            request
+                .clone()
+                .build()
+                .unwrap()
+                .input_serialization
+                .compression_type
+                .as_deref()
+        );
+        assert!(matches!(
+            request.scan_range(Some(0), Some(1024)).build(),
+            Err(Error::InvalidRequest(message)) if message.contains("GZIP")
+        ));
+    }
+
+    #[test]
+    fn reject_missing_input() {
+        let request = SelectRequest::new("my-bucket", "data/part-0.csv", "SELECT * FROM S3Object");
+
+        assert!(matches!(request.build(), Err(Error::InvalidRequest(_))));
+    }
+}
@@ -0,0 +1,248 @@
+//! Columns of the selected records.
+
+use rusoto_s3::CSVInput;
+
+use crate::query::ast::Identifier;
+
+/// `FileHeaderInfo` telling that the first line contains the column names.
+pub const FILE_HEADER_USE: &str = "USE";
+
+/// Names of the columns of a CSV object, in the order of the file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schema {
+    columns: Vec<String>,
+}
+
+impl Schema {
+    pub fn new<I, S>(columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Schema {
+            columns: columns.into_iter().map(Into::into).collect(),
+        }
+    }
+    /// Read the column names from the header line of a CSV file.
+    pub fn from_csv_header(header: &str, delimiter: char, quote: char) -> Self {
+        Schema::new(split_csv_record(
+            header.trim_end_matches(['\r', '\n'].as_ref()),
+            delimiter,
+            quote,
+        ))
+    }
+    /// Read the schema from the first bytes of a CSV object with the settings of the select
+    /// request. The columns are named `_1.._N` when the header is not used.
+    ///
+    /// Return `None` if the first record is not complete, unless `data` is the whole object.
+    pub fn from_csv_input(data: &[u8], input: &CSVInput, complete: bool) -> Option<Self> {
+        let data = String::from_utf8_lossy(data);
+        let record_delimiter = input.record_delimiter.as_deref().unwrap_or("\n");
+        let delimiter = first_char(&input.field_delimiter, ',');
+        let quote = first_char(&input.quote_character, '"');
+        let comments = first_char(&input.comments, '#');
+        let quoted_record_delimiter = input.allow_quoted_record_delimiter.unwrap_or(false);
+
+        let mut rest = data.as_ref();
+        let header = loop {
+            let end = if quoted_record_delimiter {
+                find_unquoted(rest, record_delimiter, quote)
+            } else {
+                rest.find(record_delimiter)
+            };
+            let record = match end {
+                Some(end) => &rest[..end],
+                None if complete => rest,
+                None => return None,
+            };
+            if !record.starts_with(comments) || end.is_none() {
+                break record;
+            }
+            rest = &rest[end? + record_delimiter.len()..];
+        };
+        if header.is_empty() {
+            return Some(Schema::default());
+        }
+
+        let schema = Schema::from_csv_header(header, delimiter, quote);
+        match input.file_header_info.as_deref() {
+            Some(info) if info.eq_ignore_ascii_case(FILE_HEADER_USE) => Some(schema),
+            _ => Some(Schema::new((1..=schema.len()).map(|n| format!("_{}", n)))),
+        }
+    }
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+    /// Index of the column designated by the identifier, by name or by position with `_1.._N`.
+    pub fn position(&self, identifier: &Identifier) -> Option<usize> {
+        if let Some(position) = self
+            .columns
+            .iter()
+            .position(|column| identifier.matches(column))
+        {
+            return Some(position);
+        }
+
+        positional_index(&identifier.name).filter(|index| *index < self.columns.len())
+    }
+    /// Name of the column designated by the identifier, `_2` gives the name of the second column.
+    pub fn column_name(&self, identifier: &Identifier) -> Option<&str> {
+        self.position(identifier)
+            .map(|position| self.columns[position].as_str())
+    }
+}
+
+pub(crate) fn first_char(value: &Option<String>, default: char) -> char {
+    value
+        .as_deref()
+        .and_then(|value| value.chars().next())
+        .unwrap_or(default)
+}
+
+/// Offset of the first delimiter found outside a quoted field.
+fn find_unquoted(data: &str, delimiter: &str, quote: char) -> Option<usize> {
+    let mut in_quotes = false;
+    for (offset, c) in data.char_indices() {
+        if c == quote {
+            in_quotes = !in_quotes;
+        } else if !in_quotes && data[offset..].starts_with(delimiter) {
+            return Some(offset);
+        }
+    }
+    None
+}
+
+/// Index of a positional column name, `_1` is the first column.
+pub fn positional_index(name: &str) -> Option<usize> {
+    let number: usize = name.strip_prefix('_')?.parse().ok()?;
+    number.checked_sub(1)
+}
+
+/// Split one CSV record. A doubled quote inside a quoted field is an escaped quote.
+pub(crate) fn split_csv_record(record: &str, delimiter: char, quote: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == quote {
+                if chars.peek() == Some(&quote) {
+                    field.push(quote);
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == quote {
+            in_quotes = true;
+        } else if c == delimiter {
+            fields.push(std::mem::take(&mut field));
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_csv_header() {
+        let schema = Schema::from_csv_header(
+            "number;\"long-string\";\"with \"\"quotes\"\"\";date\r\n",
+            ';',
+            '"',
+        );
+
+        assert_eq!(
+            &["number", "long-string", "with \"quotes\"", "date"],
+            schema.columns()
+        );
+    }
+
+    fn csv_input(file_header_info: &str) -> CSVInput {
+        CSVInput {
+            file_header_info: Some(file_header_info.to_string()),
+            field_delimiter: Some(";".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn from_csv_input() {
+        let data = b"# exported\nnumber;\"long-string\";special_char\n1;\"abc\";\"@\"\n2;";
+
+        let schema = Schema::from_csv_input(data, &csv_input("USE"), false).unwrap();
+        assert_eq!(&["number", "long-string", "special_char"], schema.columns());
+
+        let schema = Schema::from_csv_input(data, &csv_input("IGNORE"), false).unwrap();
+        assert_eq!(&["_1", "_2", "_3"], schema.columns());
+    }
+
+    #[test]
+    fn from_csv_input_incomplete_header() {
+        let data = b"number;long-str";
+
+        assert_eq!(None, Schema::from_csv_input(data, &csv_input("USE"), false));
+        assert_eq!(
+            &["number", "long-str"],
+            Schema::from_csv_input(data, &csv_input("USE"), true)
+                .unwrap()
+                .columns()
+        );
+    }
+
+    #[test]
+    fn from_csv_input_quoted_record_delimiter() {
+        let data = b"\"multi\nline\";number\r\n1;2\r\n";
+        let input = CSVInput {
+            allow_quoted_record_delimiter: Some(true),
+            record_delimiter: Some("\r\n".to_string()),
+            ..csv_input("USE")
+        };
+
+        let schema = Schema::from_csv_input(data, &input, false).unwrap();
+        assert_eq!(&["multi\nline", "number"], schema.columns());
+    }
+
+    #[test]
+    fn column_name() {
+        let schema = Schema::new(vec!["number", "long-string"]);
+
+        assert_eq!(
+            Some("long-string"),
+            schema.column_name(&Identifier::new("_2"))
+        );
+        assert_eq!(
+            Some("number"),
+            schema.column_name(&Identifier::new("number"))
+        );
+        assert_eq!(None, schema.column_name(&Identifier::new("_3")));
+    }
+
+    #[test]
+    fn position() {
+        let schema = Schema::new(vec!["number", "Name"]);
+
+        assert_eq!(Some(0), schema.position(&Identifier::new("number")));
+        assert_eq!(Some(1), schema.position(&Identifier::new("_2")));
+        assert_eq!(None, schema.position(&Identifier::new("_3")));
+        assert_eq!(Some(1), schema.position(&Identifier::quoted("Name")));
+        assert_eq!(None, schema.position(&Identifier::quoted("name")));
+        assert_eq!(None, schema.position(&Identifier::new("unknown")));
+    }
+}
@@ -0,0 +1,996 @@
+//! Stream of the select events with the request timeouts.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use futures::task::{Context, Poll, Waker};
+use futures_timer::Delay;
+use rusoto_s3::{OutputSerialization, Stats};
+
+use crate::cost::Price;
+use crate::error::{Error, Result};
+use crate::model::event_stream::EventStream;
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+#[cfg(feature = "tracing")]
+use crate::telemetry::Telemetry;
+
+/// Timeouts applied to a select. `None` means no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Maximum duration to get the response headers.
+    pub connect: Option<Duration>,
+    /// Maximum duration between the response headers and the first event.
+    pub first_event: Option<Duration>,
+    /// Maximum duration between two events. S3 sends `Cont` events to keep the connection
+    /// alive during long scans, they reset this timer like any other event.
+    pub idle: Option<Duration>,
+    /// Maximum duration of the whole select, from the request to the last event.
+    pub total: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn with_connect(mut self, timeout: Duration) -> Self {
+        self.connect = Some(timeout);
+        self
+    }
+    pub fn with_first_event(mut self, timeout: Duration) -> Self {
+        self.first_event = Some(timeout);
+        self
+    }
+    pub fn with_idle(mut self, timeout: Duration) -> Self {
+        self.idle = Some(timeout);
+        self
+    }
+    pub fn with_total(mut self, timeout: Duration) -> Self {
+        self.total = Some(timeout);
+        self
+    }
+    /// Remaining time to connect, the total timeout included.
+    pub(crate) fn connect_deadline(&self, started_at: Instant) -> Option<(Duration, Error)> {
+        let remaining_total = self
+            .total
+            .map(|total| total.saturating_sub(started_at.elapsed()));
+
+        match (self.connect, remaining_total) {
+            (Some(connect), Some(remaining)) if remaining < connect => {
+                Some((remaining, Error::TotalTimeout(self.total.unwrap())))
+            }
+            (Some(connect), _) => Some((connect, Error::ConnectTimeout(connect))),
+            (None, Some(remaining)) => Some((remaining, Error::TotalTimeout(self.total.unwrap()))),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Limits of a select, checked with the `Progress` and `Stats` events and the records received.
+/// `None` means no limit. The select is stopped as soon as one of them is exceeded.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Budget {
+    pub bytes_scanned: Option<u64>,
+    pub bytes_returned: Option<u64>,
+    /// Records received, counted by their delimiter.
+    pub records: Option<u64>,
+    /// Time since the request was sent.
+    pub duration: Option<Duration>,
+    /// Estimated cost in dollars, the stream must have a price.
+    pub cost: Option<f64>,
+}
+
+impl Budget {
+    pub fn with_bytes_scanned(mut self, bytes: u64) -> Self {
+        self.bytes_scanned = Some(bytes);
+        self
+    }
+    pub fn with_bytes_returned(mut self, bytes: u64) -> Self {
+        self.bytes_returned = Some(bytes);
+        self
+    }
+    pub fn with_records(mut self, records: u64) -> Self {
+        self.records = Some(records);
+        self
+    }
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+    pub fn with_cost(mut self, cost: f64) -> Self {
+        self.cost = Some(cost);
+        self
+    }
+    /// The first limit exceeded by the select.
+    fn exceeded(&self, summary: &SelectSummary) -> Option<Limit> {
+        let stats = summary.stats.clone().unwrap_or_default();
+        let over = |limit: Option<u64>, value: Option<i64>| {
+            limit.filter(|limit| value.is_some_and(|value| value.max(0) as u64 > *limit))
+        };
+
+        if let Some(bytes) = over(self.bytes_scanned, stats.bytes_scanned) {
+            Some(Limit::BytesScanned(bytes))
+        } else if let Some(bytes) = over(self.bytes_returned, stats.bytes_returned) {
+            Some(Limit::BytesReturned(bytes))
+        } else if let Some(records) = self.records.filter(|records| summary.records > *records) {
+            Some(Limit::Records(records))
+        } else {
+            self.cost
+                .filter(|cost| summary.estimated_cost.is_some_and(|value| value > *cost))
+                .map(Limit::Cost)
+        }
+    }
+}
+
+/// Limit of a [`Budget`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    BytesScanned(u64),
+    BytesReturned(u64),
+    Records(u64),
+    Duration(Duration),
+    Cost(f64),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::BytesScanned(bytes) => write!(f, "{} bytes scanned", bytes),
+            Limit::BytesReturned(bytes) => write!(f, "{} bytes returned", bytes),
+            Limit::Records(records) => write!(f, "{} records", records),
+            Limit::Duration(duration) => write!(f, "{:?}", duration),
+            Limit::Cost(cost) => write!(f, "${}", cost),
+        }
+    }
+}
+
+/// Run the future until the deadline.
+pub(crate) async fn with_deadline<F, T>(future: F, deadline: Option<(Duration, Error)>) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match deadline {
+        Some((duration, error)) => {
+            futures::pin_mut!(future);
+            match futures::future::select(future, Delay::new(duration)).await {
+                futures::future::Either::Left((result, _)) => result,
+                futures::future::Either::Right(_) => Err(error),
+            }
+        }
+        None => future.await,
+    }
+}
+
+/// Stop selects from another task. The clones of a handle abort the same selects.
+///
+/// The http connection of an aborted select is closed and its stream ends with
+/// `Error::Cancelled`, after the events already received.
+///
+/// A handle is aborted for good: the selects started later with it are cancelled at once. Use a
+/// new handle for the next selects.
+#[derive(Clone, Debug, Default)]
+pub struct AbortHandle {
+    inner: Arc<AbortState>,
+}
+
+#[derive(Debug, Default)]
+struct AbortState {
+    aborted: AtomicBool,
+    next_id: AtomicU64,
+    /// Tasks waiting for the events of the selects, by registration.
+    wakers: Mutex<HashMap<u64, Waker>>,
+}
+
+impl AbortState {
+    /// Lock the wakers even after a panic of another task, a map of wakers is never left half
+    /// updated.
+    fn wakers(&self) -> MutexGuard<'_, HashMap<u64, Waker>> {
+        self.wakers.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl AbortHandle {
+    pub fn new() -> Self {
+        AbortHandle::default()
+    }
+    /// Abort the selects and wake up the tasks reading them.
+    pub fn abort(&self) {
+        self.inner.aborted.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.inner.wakers());
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+    pub fn is_aborted(&self) -> bool {
+        self.inner.aborted.load(Ordering::SeqCst)
+    }
+    /// Registration of one select, its waker is removed when it's dropped.
+    fn register(&self) -> AbortRegistration {
+        AbortRegistration {
+            handle: self.clone(),
+            id: self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+    /// Resolved when the handle is aborted.
+    pub(crate) async fn aborted(&self) {
+        let registration = self.register();
+        futures::future::poll_fn(|cx| match registration.poll_aborted(cx) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        })
+        .await
+    }
+}
+
+/// Waker of a select waiting for the abort of the handle.
+#[derive(Debug)]
+struct AbortRegistration {
+    handle: AbortHandle,
+    id: u64,
+}
+
+impl AbortRegistration {
+    /// Check if the handle is aborted, or wake up the task when it will be.
+    fn poll_aborted(&self, cx: &mut Context<'_>) -> bool {
+        if self.handle.is_aborted() {
+            return true;
+        }
+        {
+            let mut wakers = self.handle.inner.wakers();
+            match wakers.get(&self.id) {
+                Some(waker) if waker.will_wake(cx.waker()) => (),
+                _ => {
+                    wakers.insert(self.id, cx.waker().clone());
+                }
+            }
+        }
+        // The handle can be aborted while the waker is registered.
+        self.handle.is_aborted()
+    }
+}
+
+impl Drop for AbortRegistration {
+    fn drop(&mut self) {
+        self.handle.inner.wakers().remove(&self.id);
+    }
+}
+
+/// What a select returned until now.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SelectSummary {
+    /// Records received, counted by the record delimiter of the output serialization.
+    pub records: u64,
+    /// `Records` events received.
+    pub records_events: u64,
+    /// Bytes of the payloads of the `Records` events.
+    pub payload_bytes: u64,
+    /// Statistics of the `Stats` event, or of the last `Progress` event until it's received.
+    /// The `Progress` events are sent if the request asks for them.
+    pub stats: Option<Stats>,
+    /// The `Stats` event is received, the statistics are the final ones.
+    pub complete: bool,
+    /// Time since the request was sent.
+    pub elapsed: Duration,
+    /// Cost of the bytes scanned and returned, if the stream has a price.
+    pub estimated_cost: Option<f64>,
+}
+
+/// Stream of the events returned by a select.
+///
+/// The stream ends with an error if one of the timeouts is reached or the budget is exceeded.
+/// The http connection is closed when the stream is dropped.
+pub struct SelectStream {
+    events: BoxStream<'static, Result<SelectObjectContentEventStreamItem>>,
+    timeouts: Timeouts,
+    received_event: bool,
+    last_heartbeat: Option<Instant>,
+    filter_continuations: bool,
+    idle_timer: Option<Delay>,
+    total_timer: Option<Delay>,
+    finished: bool,
+    started_at: Instant,
+    summary: SelectSummary,
+    record_counter: RecordCounter,
+    price: Option<Price>,
+    budget: Budget,
+    budget_timer: Option<Delay>,
+    /// Error returned after the event which exceeded the budget.
+    exceeded: Option<Error>,
+    abort_registration: Option<AbortRegistration>,
+    #[cfg(feature = "tracing")]
+    telemetry: Option<Telemetry>,
+}
+
+impl SelectStream {
+    /// Wrap the event stream. `started_at` is the time the request was sent, used by the total
+    /// timeout.
+    pub fn new(
+        events: EventStream<SelectObjectContentEventStreamItem>,
+        timeouts: Timeouts,
+        started_at: Instant,
+    ) -> Self {
+        SelectStream::from_events(events.map_err(Error::from).boxed(), timeouts, started_at)
+    }
+    /// Wrap events that are not read from a response, like the events of a local evaluation.
+    pub(crate) fn from_events(
+        events: BoxStream<'static, Result<SelectObjectContentEventStreamItem>>,
+        timeouts: Timeouts,
+        started_at: Instant,
+    ) -> Self {
+        SelectStream {
+            events,
+            timeouts,
+            received_event: false,
+            last_heartbeat: None,
+            filter_continuations: false,
+            idle_timer: timeouts.first_event.map(Delay::new),
+            total_timer: timeouts
+                .total
+                .map(|total| Delay::new(total.saturating_sub(started_at.elapsed()))),
+            finished: false,
+            started_at,
+            summary: SelectSummary::default(),
+            record_counter: RecordCounter::new(&OutputSerialization::default()),
+            price: None,
+            budget: Budget::default(),
+            budget_timer: None,
+            exceeded: None,
+            abort_registration: None,
+            #[cfg(feature = "tracing")]
+            telemetry: None,
+        }
+    }
+    /// Estimate the cost of the select with this price.
+    pub fn with_price(mut self, price: Price) -> Self {
+        self.price = Some(price);
+        self
+    }
+    /// Stop the select when it exceeds one of the limits, with a `BudgetExceeded` error. The
+    /// duration is counted from the request.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget_timer = budget
+            .duration
+            .map(|duration| Delay::new(duration.saturating_sub(self.started_at.elapsed())));
+        self.budget = budget;
+        self
+    }
+    /// Count the records with the delimiter of the output serialization of the request, `\n`
+    /// by default.
+    pub fn with_output_serialization(mut self, output_serialization: &OutputSerialization) -> Self {
+        self.record_counter = RecordCounter::new(output_serialization);
+        self
+    }
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+    /// End the stream with a `Cancelled` error when the handle is aborted.
+    pub fn with_abort_handle(mut self, abort_handle: AbortHandle) -> Self {
+        self.abort_registration = Some(abort_handle.register());
+        self
+    }
+    /// Record the events in the span and the metrics of the select.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+    /// Don't return the `Cont` events. They still reset the idle timer.
+    pub fn filter_continuations(mut self, filter_continuations: bool) -> Self {
+        self.filter_continuations = filter_continuations;
+        self
+    }
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+    /// Time of the last `Cont` event sent by the server to keep the connection alive.
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.last_heartbeat
+    }
+    /// The events received until now, the final statistics once the stream is finished.
+    pub fn summary(&self) -> SelectSummary {
+        SelectSummary {
+            elapsed: self.started_at.elapsed(),
+            estimated_cost: self.estimated_cost(),
+            ..self.summary.clone()
+        }
+    }
+    /// Cost of the bytes scanned and returned until now, updated by the `Progress` and `Stats`
+    /// events. `None` without price.
+    pub fn estimated_cost(&self) -> Option<f64> {
+        let price = self.price?;
+        Some(
+            self.summary
+                .stats
+                .as_ref()
+                .map_or(0.0, |stats| price.cost(stats)),
+        )
+    }
+    fn record(&mut self, event: &SelectObjectContentEventStreamItem) {
+        let summary = &mut self.summary;
+        match event {
+            SelectObjectContentEventStreamItem::Records(records) => {
+                summary.records += self
+                    .record_counter
+                    .count(records.payload.as_deref().unwrap_or_default());
+                summary.records_events += 1;
+                summary.payload_bytes +=
+                    records.payload.as_ref().map_or(0, |payload| payload.len()) as u64;
+            }
+            SelectObjectContentEventStreamItem::Progress(progress) if !summary.complete => {
+                summary.stats = progress.details.as_ref().map(|progress| Stats {
+                    bytes_scanned: progress.bytes_scanned,
+                    bytes_processed: progress.bytes_processed,
+                    bytes_returned: progress.bytes_returned,
+                });
+            }
+            SelectObjectContentEventStreamItem::Stats(stats) => {
+                summary.stats = stats.details.clone();
+                summary.complete = true;
+            }
+            _ => (),
+        }
+    }
+    fn finish(&mut self, error: Error) -> Poll<Option<Result<SelectObjectContentEventStreamItem>>> {
+        self.finished = true;
+        #[cfg(feature = "tracing")]
+        if let Some(telemetry) = self.telemetry.as_mut() {
+            telemetry.error(&error);
+        }
+        // Close the http connection.
+        self.events = stream::empty().boxed();
+        Poll::Ready(Some(Err(error)))
+    }
+}
+
+impl Stream for SelectStream {
+    type Item = Result<SelectObjectContentEventStreamItem>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(error) = this.exceeded.take() {
+            return this.finish(error);
+        }
+        if this.finished {
+            return Poll::Ready(None);
+        }
+        #[cfg(feature = "tracing")]
+        let span = this
+            .telemetry
+            .as_ref()
+            .map(|telemetry| telemetry.span().clone());
+        #[cfg(feature = "tracing")]
+        let _entered = span.as_ref().map(tracing::Span::enter);
+
+        // The events already received are returned before the error of an aborted select.
+        let aborted = this
+            .abort_registration
+            .as_ref()
+            .is_some_and(|abort_registration| abort_registration.poll_aborted(cx));
+
+        loop {
+            match this.events.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(event))) => {
+                    #[cfg(feature = "tracing")]
+                    if let Some(telemetry) = this.telemetry.as_mut() {
+                        telemetry.event(&event);
+                    }
+                    this.record(&event);
+                    if let Some(limit) = this.budget.exceeded(&this.summary()) {
+                        // Close the connection now, the event is returned and the error next.
+                        this.events = stream::empty().boxed();
+                        this.exceeded =
+                            Some(Error::BudgetExceeded(limit, Box::new(this.summary())));
+                    }
+                    this.received_event = true;
+                    this.idle_timer = this.timeouts.idle.map(Delay::new);
+                    match event {
+                        SelectObjectContentEventStreamItem::Cont(_) => {
+                            this.last_heartbeat = Some(Instant::now());
+                            if this.filter_continuations {
+                                continue;
+                            }
+                        }
+                        SelectObjectContentEventStreamItem::End(_) => this.finished = true,
+                        _ => (),
+                    }
+                    return Poll::Ready(Some(Ok(event)));
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    this.finished = true;
+                    #[cfg(feature = "tracing")]
+                    if let Some(telemetry) = this.telemetry.as_mut() {
+                        telemetry.error(&err);
+                    }
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Ready(None) => {
+                    this.finished = true;
+                    #[cfg(feature = "tracing")]
+                    if let Some(telemetry) = this.telemetry.as_mut() {
+                        telemetry.finish();
+                    }
+                    return Poll::Ready(None);
+                }
+                Poll::Pending if aborted => {
+                    let summary = this.summary();
+                    return this.finish(Error::Cancelled(Box::new(summary)));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        if let Some(timer) = this.total_timer.as_mut() {
+            if Pin::new(timer).poll(cx).is_ready() {
+                let timeout = this.timeouts.total.unwrap_or_default();
+                return this.finish(Error::TotalTimeout(timeout));
+            }
+        }
+
+        if let Some(timer) = this.budget_timer.as_mut() {
+            if Pin::new(timer).poll(cx).is_ready() {
+                let limit = Limit::Duration(this.budget.duration.unwrap_or_default());
+                return this.finish(Error::BudgetExceeded(limit, Box::new(this.summary())));
+            }
+        }
+
+        if let Some(timer) = this.idle_timer.as_mut() {
+            if Pin::new(timer).poll(cx).is_ready() {
+                let error = if this.received_event {
+                    Error::IdleTimeout(this.timeouts.idle.unwrap_or_default())
+                } else {
+                    Error::FirstEventTimeout(this.timeouts.first_event.unwrap_or_default())
+                };
+                return this.finish(error);
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Counter of the records in the payloads, by their delimiter outside of the CSV quotes.
+#[derive(Clone, Copy, Debug)]
+struct RecordCounter {
+    delimiter: u8,
+    quote: Option<u8>,
+    in_quotes: bool,
+}
+
+impl RecordCounter {
+    fn new(output_serialization: &OutputSerialization) -> Self {
+        // The last byte of a `\r\n` delimiter, the JSON strings can't contain it.
+        let last_byte = |delimiter: &Option<String>| {
+            delimiter
+                .as_deref()
+                .and_then(|delimiter| delimiter.bytes().last())
+                .unwrap_or(b'\n')
+        };
+        match (&output_serialization.csv, &output_serialization.json) {
+            (Some(csv), _) => RecordCounter {
+                delimiter: last_byte(&csv.record_delimiter),
+                quote: Some(
+                    csv.quote_character
+                        .as_deref()
+                        .and_then(|quote| quote.bytes().next())
+                        .unwrap_or(b'"'),
+                ),
+                in_quotes: false,
+            },
+            (None, json) => RecordCounter {
+                delimiter: last_byte(&json.as_ref().and_then(|json| json.record_delimiter.clone())),
+                quote: None,
+                in_quotes: false,
+            },
+        }
+    }
+    fn count(&mut self, payload: &[u8]) -> u64 {
+        let mut records = 0;
+        for byte in payload {
+            if Some(*byte) == self.quote {
+                self.in_quotes = !self.in_quotes;
+            } else if *byte == self.delimiter && !self.in_quotes {
+                records += 1;
+            }
+        }
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::event_stream::encode_event;
+    use futures::io::AsyncRead;
+    use futures::TryStreamExt;
+
+    /// Body sending each chunk after a delay.
+    struct SlowReader {
+        chunks: Vec<(Duration, Vec<u8>)>,
+        delay: Option<Delay>,
+    }
+
+    impl SlowReader {
+        fn new(mut chunks: Vec<(Duration, Vec<u8>)>) -> Self {
+            chunks.reverse();
+            SlowReader {
+                chunks,
+                delay: None,
+            }
+        }
+    }
+
+    impl AsyncRead for SlowReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+            let (duration, _) = match this.chunks.last() {
+                Some(chunk) => chunk,
+                None => return Poll::Ready(Ok(0)),
+            };
+            let delay = this.delay.get_or_insert_with(|| Delay::new(*duration));
+            if Pin::new(delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.delay = None;
+            let (_, chunk) = this.chunks.pop().unwrap();
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Poll::Ready(Ok(chunk.len()))
+        }
+    }
+
+    fn stream(chunks: Vec<(Duration, Vec<u8>)>, timeouts: Timeouts) -> SelectStream {
+        SelectStream::new(
+            EventStream::from_reader(SlowReader::new(chunks)),
+            timeouts,
+            Instant::now(),
+        )
+    }
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[async_std::test]
+    async fn select_stream_read_events() {
+        let mut stream = stream(
+            vec![
+                (millis(0), encode_event("Records", b"1,2\n")),
+                (millis(10), encode_event("End", b"")),
+            ],
+            Timeouts::default().with_idle(millis(500)),
+        );
+
+        assert!(matches!(
+            stream.try_next().await.unwrap(),
+            Some(SelectObjectContentEventStreamItem::Records(_))
+        ));
+        assert!(matches!(
+            stream.try_next().await.unwrap(),
+            Some(SelectObjectContentEventStreamItem::End(_))
+        ));
+        assert!(stream.try_next().await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn select_stream_first_event_timeout() {
+        let mut stream = stream(
+            vec![(millis(500), encode_event("End", b""))],
+            Timeouts::default().with_first_event(millis(20)),
+        );
+
+        assert!(matches!(
+            stream.try_next().await,
+            Err(Error::FirstEventTimeout(_))
+        ));
+        assert!(stream.try_next().await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn select_stream_idle_timeout() {
+        let mut stream = stream(
+            vec![
+                (millis(0), encode_event("Records", b"1,2\n")),
+                (millis(500), encode_event("End", b"")),
+            ],
+            Timeouts::default().with_idle(millis(20)),
+        );
+
+        assert!(stream.try_next().await.is_ok());
+        assert!(matches!(
+            stream.try_next().await,
+            Err(Error::IdleTimeout(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn select_stream_continuation_reset_idle_timer() {
+        let mut stream = stream(
+            vec![
+                (millis(0), encode_event("Records", b"1,2\n")),
+                (millis(30), encode_event("Cont", b"")),
+                (millis(30), encode_event("Cont", b"")),
+                (millis(30), encode_event("End", b"")),
+            ],
+            Timeouts::default().with_idle(millis(60)),
+        );
+
+        let mut events = 0;
+        while stream.try_next().await.unwrap().is_some() {
+            events += 1;
+        }
+        assert_eq!(4, events);
+    }
+
+    #[async_std::test]
+    async fn select_stream_filter_continuations() {
+        let mut stream = stream(
+            vec![
+                (millis(0), encode_event("Cont", b"")),
+                (millis(0), encode_event("Records", b"1,2\n")),
+                (millis(0), encode_event("Cont", b"")),
+                (millis(0), encode_event("End", b"")),
+            ],
+            Timeouts::default(),
+        )
+        .filter_continuations(true);
+
+        assert!(stream.last_heartbeat().is_none());
+        assert!(matches!(
+            stream.try_next().await.unwrap(),
+            Some(SelectObjectContentEventStreamItem::Records(_))
+        ));
+        let first_heartbeat = stream.last_heartbeat();
+        assert!(first_heartbeat.is_some());
+        assert!(matches!(
+            stream.try_next().await.unwrap(),
+            Some(SelectObjectContentEventStreamItem::End(_))
+        ));
+        assert!(stream.last_heartbeat() > first_heartbeat);
+    }
+
+    #[async_std::test]
+    async fn select_stream_total_timeout() {
+        let mut stream = stream(
+            vec![
+                (millis(0), encode_event("Records", b"1,2\n")),
+                (millis(30), encode_event("Cont", b"")),
+                (millis(30), encode_event("Cont", b"")),
+                (millis(30), encode_event("End", b"")),
+            ],
+            Timeouts::default()
+                .with_idle(millis(60))
+                .with_total(millis(50)),
+        );
+
+        let mut result = Ok(None);
+        for _ in 0..4 {
+            result = stream.try_next().await;
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(Error::TotalTimeout(_))));
+    }
+
+    #[async_std::test]
+    async fn with_deadline_timeout() {
+        let result = with_deadline(
+            async {
+                Delay::new(millis(500)).await;
+                Ok(())
+            },
+            Timeouts::default()
+                .with_connect(millis(10))
+                .connect_deadline(Instant::now()),
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::ConnectTimeout(_))));
+    }
+
+    #[async_std::test]
+    async fn select_stream_summary() {
+        let progress = |bytes_scanned| {
+            SelectObjectContentEventStreamItem::Progress(rusoto_s3::ProgressEvent {
+                details: Some(rusoto_s3::Progress {
+                    bytes_scanned: Some(bytes_scanned),
+                    bytes_processed: Some(bytes_scanned),
+                    bytes_returned: Some(0),
+                }),
+            })
+        };
+        let events = stream::iter(vec![
+            progress(1 << 30),
+            SelectObjectContentEventStreamItem::Records(rusoto_s3::RecordsEvent {
+                payload: Some(b"1,2\n".to_vec().into()),
+            }),
+            SelectObjectContentEventStreamItem::Stats(rusoto_s3::StatsEvent {
+                details: Some(Stats {
+                    bytes_scanned: Some(2 << 30),
+                    bytes_processed: Some(2 << 30),
+                    bytes_returned: Some(1 << 30),
+                }),
+            }),
+            progress(3 << 30),
+        ])
+        .map(Ok)
+        .boxed();
+        let mut stream = SelectStream::from_events(events, Timeouts::default(), Instant::now())
+            .with_price(Price::new(0.002, 0.0007));
+
+        stream.try_next().await.unwrap();
+        assert_eq!(Some(0.002), stream.estimated_cost());
+        stream.try_next().await.unwrap();
+        stream.try_next().await.unwrap();
+        stream.try_next().await.unwrap();
+
+        let summary = stream.summary();
+        assert_eq!(1, summary.records_events);
+        assert_eq!(4, summary.payload_bytes);
+        assert!(summary.complete);
+        assert_eq!(Some(2 << 30), summary.stats.unwrap().bytes_scanned);
+        assert_eq!(Some(0.0047), summary.estimated_cost);
+    }
+
+    fn records(payload: &'static [u8]) -> SelectObjectContentEventStreamItem {
+        SelectObjectContentEventStreamItem::Records(rusoto_s3::RecordsEvent {
+            payload: Some(payload.to_vec().into()),
+        })
+    }
+
+    #[async_std::test]
+    async fn select_stream_budget_records() {
+        let events = stream::iter(vec![
+            records(b"a,\"multi\nline\"\nb,2\n"),
+            records(b"c,3\n"),
+            records(b"d,4\n"),
+        ])
+        .map(Ok)
+        .boxed();
+        let mut stream = SelectStream::from_events(events, Timeouts::default(), Instant::now())
+            .with_output_serialization(&OutputSerialization {
+                csv: Some(rusoto_s3::CSVOutput::default()),
+                json: None,
+            })
+            .with_budget(Budget::default().with_records(2));
+
+        assert!(stream.try_next().await.unwrap().is_some());
+        // The event exceeding the budget is returned before the error.
+        assert!(stream.try_next().await.unwrap().is_some());
+        match stream.try_next().await {
+            Err(Error::BudgetExceeded(Limit::Records(2), summary)) => {
+                assert_eq!(3, summary.records);
+                assert_eq!(2, summary.records_events);
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(stream.try_next().await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn select_stream_budget_bytes_scanned() {
+        let events = stream::iter(vec![
+            SelectObjectContentEventStreamItem::Progress(rusoto_s3::ProgressEvent {
+                details: Some(rusoto_s3::Progress {
+                    bytes_scanned: Some(2048),
+                    bytes_processed: Some(2048),
+                    bytes_returned: Some(0),
+                }),
+            }),
+            records(b"1,2\n"),
+        ])
+        .map(Ok)
+        .boxed();
+        let mut stream = SelectStream::from_events(events, Timeouts::default(), Instant::now())
+            .with_budget(Budget::default().with_bytes_scanned(1024));
+
+        assert!(stream.try_next().await.unwrap().is_some());
+        assert!(matches!(
+            stream.try_next().await,
+            Err(Error::BudgetExceeded(Limit::BytesScanned(1024), _))
+        ));
+        assert!(stream.try_next().await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn select_stream_budget_duration() {
+        let mut stream = stream(
+            vec![
+                (millis(0), encode_event("Records", b"1,2\n")),
+                (millis(500), encode_event("End", b"")),
+            ],
+            Timeouts::default(),
+        )
+        .with_budget(Budget::default().with_duration(millis(30)));
+
+        assert!(stream.try_next().await.is_ok());
+        match stream.try_next().await {
+            Err(Error::BudgetExceeded(Limit::Duration(_), summary)) => {
+                assert_eq!(1, summary.records);
+                assert!(summary.elapsed >= millis(30));
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[async_std::test]
+    async fn select_stream_abort() {
+        let abort_handle = AbortHandle::new();
+        let mut stream = stream(
+            vec![
+                (millis(0), encode_event("Records", b"1,2\n")),
+                (millis(500), encode_event("End", b"")),
+            ],
+            Timeouts::default(),
+        )
+        .with_abort_handle(abort_handle.clone());
+        assert!(stream.try_next().await.unwrap().is_some());
+
+        let started_at = Instant::now();
+        async_std::task::spawn(async move {
+            Delay::new(millis(20)).await;
+            abort_handle.abort();
+        });
+        match stream.try_next().await {
+            Err(Error::Cancelled(summary)) => assert_eq!(1, summary.records),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(started_at.elapsed() < millis(400));
+        assert!(stream.try_next().await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn select_stream_abort_return_received_events() {
+        let abort_handle = AbortHandle::new();
+        let mut records = encode_event("Records", b"1,2\n");
+        records.extend(encode_event("Records", b"3,4\n"));
+        let mut stream = stream(
+            vec![
+                (millis(0), records),
+                (millis(500), encode_event("End", b"")),
+            ],
+            Timeouts::default(),
+        )
+        .with_abort_handle(abort_handle.clone());
+        assert!(stream.try_next().await.unwrap().is_some());
+
+        abort_handle.abort();
+        assert!(matches!(
+            stream.try_next().await.unwrap(),
+            Some(SelectObjectContentEventStreamItem::Records(records))
+                if records.payload.as_deref() == Some(&b"3,4\n"[..])
+        ));
+        match stream.try_next().await {
+            Err(Error::Cancelled(summary)) => assert_eq!(2, summary.records),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[async_std::test]
+    async fn abort_registration_removed_on_drop() {
+        let abort_handle = AbortHandle::new();
+        for _ in 0..10 {
+            let mut stream = stream(
+                vec![(millis(0), encode_event("End", b""))],
+                Timeouts::default(),
+            )
+            .with_abort_handle(abort_handle.clone());
+            while stream.try_next().await.unwrap().is_some() {}
+        }
+        assert!(abort_handle.inner.wakers().is_empty());
+
+        abort_handle.abort();
+        let mut stream = stream(
+            vec![(millis(500), encode_event("End", b""))],
+            Timeouts::default(),
+        )
+        .with_abort_handle(abort_handle);
+        assert!(matches!(stream.try_next().await, Err(Error::Cancelled(_))));
+    }
+}
@@ -0,0 +1,491 @@
+//! Write the records of a select to a file or to any `AsyncWrite`.
+//!
+//! The CSV and NDJSON records are copied as the select returns them. The Arrow IPC and Parquet
+//! files, with the `arrow` feature, are written batch by batch from the JSON records.
+//!
+//! ```
+//! # use futures::stream::{self, StreamExt};
+//! # use rusoto_s3::{RecordsEvent, Stats, StatsEvent};
+//! # use surf_bucket_select::model::select_object_content::SelectObjectContentEventStreamItem;
+//! use surf_bucket_select::sink::{Sink, SinkFormat};
+//!
+//! # async_std::task::block_on(async {
+//! // The request of the select must use `SinkFormat::Ndjson.output_serialization()`.
+//! let events = stream::iter(vec![
+//!     SelectObjectContentEventStreamItem::Records(RecordsEvent {
+//!         payload: Some(b"{\"name\":\"a\"}\n{\"name\":\"b\"}\n".to_vec().into()),
+//!     }),
+//!     SelectObjectContentEventStreamItem::Stats(StatsEvent {
+//!         details: Some(Stats { bytes_scanned: Some(42), ..Default::default() }),
+//!     }),
+//! ])
+//! .map(Ok);
+//!
+//! let mut output = Vec::new();
+//! let summary = Sink::new(SinkFormat::Ndjson, &mut output).write_all(events).await.unwrap();
+//!
+//! assert_eq!(2, summary.records);
+//! assert_eq!(Some(42), summary.stats.and_then(|stats| stats.bytes_scanned));
+//! # });
+//! ```
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use futures::io::{AllowStdIo, AsyncWrite, AsyncWriteExt};
+use futures::{Stream, TryStreamExt};
+use rusoto_s3::{CSVOutput, JSONOutput, OutputSerialization, Stats};
+
+use crate::error::{Error, Result};
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+
+#[cfg(feature = "arrow")]
+use arrow_array::RecordBatch;
+#[cfg(feature = "arrow")]
+use arrow_schema::SchemaRef;
+
+#[cfg(feature = "arrow")]
+use crate::arrow::BatchDecoder;
+
+/// Records of an Arrow record batch, the Parquet row groups have the same size by default.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// Format of the records written by a [`Sink`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkFormat {
+    /// The CSV records, the select must return CSV.
+    Csv,
+    /// One JSON record per line, the select must return JSON.
+    Ndjson,
+    /// A Parquet file, the select must return JSON.
+    #[cfg(all(feature = "arrow", feature = "parquet"))]
+    Parquet,
+    /// An Arrow IPC file, the select must return JSON.
+    #[cfg(feature = "arrow")]
+    ArrowIpc,
+}
+
+impl SinkFormat {
+    /// Output serialization of the select read by the sink.
+    pub fn output_serialization(self) -> OutputSerialization {
+        match self {
+            SinkFormat::Csv => OutputSerialization {
+                csv: Some(CSVOutput::default()),
+                json: None,
+            },
+            _ => OutputSerialization {
+                csv: None,
+                json: Some(JSONOutput {
+                    record_delimiter: Some("\n".to_string()),
+                }),
+            },
+        }
+    }
+}
+
+/// What a [`Sink`] wrote.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SinkSummary {
+    /// Records written. The CSV records are counted by their new lines outside of quotes.
+    pub records: u64,
+    /// Arrow record batches written, none for the CSV and NDJSON records.
+    pub batches: u64,
+    pub bytes_written: u64,
+    /// Statistics of the select, if the server sent them.
+    pub stats: Option<Stats>,
+}
+
+/// Writer of the events of a select.
+pub struct Sink<W> {
+    format: SinkFormat,
+    output: W,
+    batch_size: usize,
+    #[cfg(all(feature = "arrow", feature = "parquet"))]
+    row_group_size: Option<usize>,
+    #[cfg(feature = "arrow")]
+    schema: Option<SchemaRef>,
+}
+
+impl Sink<AllowStdIo<BufWriter<File>>> {
+    /// Create, or truncate, the local file. The file is written with blocking writes.
+    pub fn create<P: AsRef<Path>>(format: SinkFormat, path: P) -> Result<Self> {
+        let file = File::create(path).map_err(Error::Output)?;
+        Ok(Sink::new(format, AllowStdIo::new(BufWriter::new(file))))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Sink<W> {
+    pub fn new(format: SinkFormat, output: W) -> Self {
+        Sink {
+            format,
+            output,
+            batch_size: DEFAULT_BATCH_SIZE,
+            #[cfg(all(feature = "arrow", feature = "parquet"))]
+            row_group_size: None,
+            #[cfg(feature = "arrow")]
+            schema: None,
+        }
+    }
+    /// Records of each Arrow record batch, the schema is inferred from the first one.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    /// Maximum records of a Parquet row group, the batch size by default.
+    #[cfg(all(feature = "arrow", feature = "parquet"))]
+    pub fn with_row_group_size(mut self, row_group_size: usize) -> Self {
+        self.row_group_size = Some(row_group_size.max(1));
+        self
+    }
+    /// Schema of the Arrow and Parquet files instead of the inferred one. The fields are
+    /// converted to the type of their column, the strings selected from a CSV object can be
+    /// read as `Int64`, `Float64` or `Boolean`.
+    #[cfg(feature = "arrow")]
+    pub fn with_schema(mut self, schema: SchemaRef) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+    /// Write the records of the events, then close the output. If the select is cancelled, the
+    /// complete records received are written and the output is closed before returning the
+    /// error.
+    pub async fn write_all<S>(mut self, events: S) -> Result<SinkSummary>
+    where
+        S: Stream<Item = Result<SelectObjectContentEventStreamItem>>,
+    {
+        futures::pin_mut!(events);
+        let mut encoder = Encoder::new(&self)?;
+        let mut summary = SinkSummary::default();
+
+        let cancelled = loop {
+            match events.try_next().await {
+                Ok(Some(SelectObjectContentEventStreamItem::Records(records))) => {
+                    let payload = records.payload.unwrap_or_default();
+                    let data = encoder.encode(&payload, &mut summary)?;
+                    self.write(&data, &mut summary).await?;
+                }
+                Ok(Some(SelectObjectContentEventStreamItem::Stats(event))) => {
+                    summary.stats = event.details
+                }
+                Ok(Some(_)) => (),
+                Ok(None) => break None,
+                Err(error @ Error::Cancelled(_)) => break Some(error),
+                Err(error) => return Err(error),
+            }
+        };
+        let data = encoder.finish(cancelled.is_none(), &mut summary)?;
+        self.write(&data, &mut summary).await?;
+        self.output.close().await.map_err(Error::Output)?;
+
+        cancelled.map_or(Ok(summary), Err)
+    }
+    async fn write(&mut self, data: &[u8], summary: &mut SinkSummary) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.output.write_all(data).await.map_err(Error::Output)?;
+        summary.bytes_written += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Convert the payloads of the `Records` events to the bytes of the output.
+enum Encoder {
+    Csv {
+        in_quotes: bool,
+    },
+    Ndjson,
+    #[cfg(feature = "arrow")]
+    Batches(Box<Batches>),
+}
+
+impl Encoder {
+    fn new<W>(sink: &Sink<W>) -> Result<Self> {
+        match sink.format {
+            SinkFormat::Csv => Ok(Encoder::Csv { in_quotes: false }),
+            SinkFormat::Ndjson => Ok(Encoder::Ndjson),
+            #[cfg(feature = "arrow")]
+            format => Ok(Encoder::Batches(Box::new(Batches {
+                format,
+                #[cfg(feature = "parquet")]
+                row_group_size: sink.row_group_size.unwrap_or(sink.batch_size),
+                decoder: BatchDecoder::new(
+                    &format.output_serialization(),
+                    sink.schema.clone(),
+                    sink.batch_size,
+                )?,
+                writer: None,
+            }))),
+        }
+    }
+    fn encode(&mut self, payload: &[u8], summary: &mut SinkSummary) -> Result<Vec<u8>> {
+        match self {
+            Encoder::Csv { in_quotes } => {
+                for byte in payload {
+                    match byte {
+                        b'"' => *in_quotes = !*in_quotes,
+                        b'\n' if !*in_quotes => summary.records += 1,
+                        _ => (),
+                    }
+                }
+                Ok(payload.to_vec())
+            }
+            Encoder::Ndjson => {
+                summary.records += payload.iter().filter(|byte| **byte == b'\n').count() as u64;
+                Ok(payload.to_vec())
+            }
+            #[cfg(feature = "arrow")]
+            Encoder::Batches(batches) => {
+                let mut data = Vec::new();
+                for batch in batches.decoder.push(payload)? {
+                    data.extend(batches.write(Some(&batch), summary)?);
+                }
+                Ok(data)
+            }
+        }
+    }
+    /// Bytes ending the output. Without `eof`, the end of the last payload is not a record.
+    #[cfg_attr(not(feature = "arrow"), allow(unused_variables))]
+    fn finish(&mut self, eof: bool, summary: &mut SinkSummary) -> Result<Vec<u8>> {
+        match self {
+            Encoder::Csv { .. } | Encoder::Ndjson => Ok(Vec::new()),
+            #[cfg(feature = "arrow")]
+            Encoder::Batches(batches) => {
+                let last = match eof {
+                    true => batches.decoder.finish()?,
+                    false => batches.decoder.flush()?,
+                };
+                let mut data = Vec::new();
+                if last.is_some() || batches.writer.is_none() {
+                    data.extend(batches.write(last.as_ref(), summary)?);
+                }
+                data.extend(batches.close()?);
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Decoder of the record batches and their writer, created with the schema.
+#[cfg(feature = "arrow")]
+struct Batches {
+    format: SinkFormat,
+    #[cfg(feature = "parquet")]
+    row_group_size: usize,
+    decoder: BatchDecoder,
+    writer: Option<BatchWriter>,
+}
+
+#[cfg(feature = "arrow")]
+enum BatchWriter {
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::arrow::ArrowWriter<Vec<u8>>),
+    ArrowIpc(arrow_ipc::writer::FileWriter<Vec<u8>>),
+}
+
+#[cfg(feature = "arrow")]
+fn output_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> Error {
+    Error::Output(std::io::Error::other(error))
+}
+
+#[cfg(feature = "arrow")]
+impl Batches {
+    /// Write the batch, without batch only create the writer. Return the bytes written so far.
+    fn write(&mut self, batch: Option<&RecordBatch>, summary: &mut SinkSummary) -> Result<Vec<u8>> {
+        let schema = match batch {
+            Some(batch) => batch.schema(),
+            None => self.decoder.schema(&[]),
+        };
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self.writer.insert(match self.format {
+                #[cfg(feature = "parquet")]
+                SinkFormat::Parquet => {
+                    let properties = parquet::file::properties::WriterProperties::builder()
+                        .set_max_row_group_size(self.row_group_size)
+                        .build();
+                    BatchWriter::Parquet(
+                        parquet::arrow::ArrowWriter::try_new(
+                            Vec::new(),
+                            schema.clone(),
+                            Some(properties),
+                        )
+                        .map_err(output_error)?,
+                    )
+                }
+                _ => BatchWriter::ArrowIpc(
+                    arrow_ipc::writer::FileWriter::try_new(Vec::new(), &schema)
+                        .map_err(output_error)?,
+                ),
+            }),
+        };
+        if let Some(batch) = batch {
+            match writer {
+                #[cfg(feature = "parquet")]
+                BatchWriter::Parquet(writer) => writer.write(batch).map_err(output_error)?,
+                BatchWriter::ArrowIpc(writer) => writer.write(batch).map_err(output_error)?,
+            }
+            summary.records += batch.num_rows() as u64;
+            summary.batches += 1;
+        }
+        Ok(writer.take())
+    }
+    /// Write the footer of the file.
+    fn close(&mut self) -> Result<Vec<u8>> {
+        match &mut self.writer {
+            #[cfg(feature = "parquet")]
+            Some(BatchWriter::Parquet(writer)) => {
+                writer.finish().map_err(output_error)?;
+            }
+            Some(BatchWriter::ArrowIpc(writer)) => writer.finish().map_err(output_error)?,
+            None => (),
+        }
+        Ok(self
+            .writer
+            .as_mut()
+            .map(BatchWriter::take)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl BatchWriter {
+    /// Take the bytes already encoded, the writers keep track of the offsets themselves.
+    fn take(&mut self) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "parquet")]
+            BatchWriter::Parquet(writer) => std::mem::take(writer.inner_mut()),
+            BatchWriter::ArrowIpc(writer) => std::mem::take(writer.get_mut()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream::{self, StreamExt};
+    use rusoto_s3::{RecordsEvent, StatsEvent};
+
+    fn events(payloads: &[&str]) -> impl Stream<Item = Result<SelectObjectContentEventStreamItem>> {
+        let mut events: Vec<SelectObjectContentEventStreamItem> = payloads
+            .iter()
+            .map(|payload| {
+                SelectObjectContentEventStreamItem::Records(RecordsEvent {
+                    payload: Some(payload.as_bytes().to_vec().into()),
+                })
+            })
+            .collect();
+        events.push(SelectObjectContentEventStreamItem::Stats(StatsEvent {
+            details: Some(Stats {
+                bytes_scanned: Some(100),
+                bytes_processed: Some(100),
+                bytes_returned: Some(40),
+            }),
+        }));
+        stream::iter(events).map(Ok)
+    }
+
+    #[async_std::test]
+    async fn write_csv() {
+        let mut output = Vec::new();
+        let summary = Sink::new(SinkFormat::Csv, &mut output)
+            .write_all(events(&["a,\"multi\nline\"\nb,", "c\n"]))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            "a,\"multi\nline\"\nb,c\n",
+            String::from_utf8(output).unwrap()
+        );
+        assert_eq!(2, summary.records);
+        assert_eq!(0, summary.batches);
+        assert_eq!(19, summary.bytes_written);
+        assert_eq!(
+            Some(100),
+            summary.stats.and_then(|stats| stats.bytes_scanned)
+        );
+    }
+
+    #[async_std::test]
+    async fn write_file() {
+        let path = std::env::temp_dir().join("surf_bucket_select_sink.ndjson");
+        let summary = Sink::create(SinkFormat::Ndjson, &path)
+            .unwrap()
+            .write_all(events(&["{\"a\":1}\n{\"a\"", ":2}\n"]))
+            .await
+            .unwrap();
+
+        assert_eq!(2, summary.records);
+        assert_eq!(
+            "{\"a\":1}\n{\"a\":2}\n",
+            std::fs::read_to_string(&path).unwrap()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "arrow")]
+    #[async_std::test]
+    async fn write_arrow_ipc() {
+        use arrow_array::Int64Array;
+
+        let mut output = Vec::new();
+        let summary = Sink::new(SinkFormat::ArrowIpc, &mut output)
+            .with_batch_size(2)
+            .write_all(events(&[
+                "{\"name\":\"a\",\"size\":1}\n{\"name\":\"b\",\"size\":2}\n",
+                "{\"name\":\"c\",\"size\":3}",
+            ]))
+            .await
+            .unwrap();
+
+        assert_eq!(3, summary.records);
+        assert_eq!(2, summary.batches);
+        assert_eq!(output.len() as u64, summary.bytes_written);
+
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(output), None).unwrap();
+        let batches: Vec<_> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(
+            vec![2, 1],
+            batches
+                .iter()
+                .map(|batch| batch.num_rows())
+                .collect::<Vec<_>>()
+        );
+        let sizes = batches[1]
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(3, sizes.value(0));
+    }
+
+    #[cfg(all(feature = "arrow", feature = "parquet"))]
+    #[async_std::test]
+    async fn write_parquet() {
+        use arrow_schema::{DataType, Field, Schema};
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let schema = Schema::new(vec![
+            Field::new("name", DataType::Utf8, true),
+            Field::new("size", DataType::Int64, true),
+        ]);
+        let mut output = Vec::new();
+        let summary = Sink::new(SinkFormat::Parquet, &mut output)
+            .with_batch_size(2)
+            .with_row_group_size(2)
+            .with_schema(std::sync::Arc::new(schema))
+            .write_all(events(&[
+                "{\"name\":\"a\",\"size\":\"1\"}\n{\"name\":\"b\",\"size\":\"2\"}\n",
+                "{\"name\":\"c\"}\n",
+            ]))
+            .await
+            .unwrap();
+
+        assert_eq!(3, summary.records);
+        let reader = SerializedFileReader::new(bytes::Bytes::from(output)).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(3, metadata.file_metadata().num_rows());
+        assert_eq!(2, metadata.num_row_groups());
+    }
+}
@@ -0,0 +1,220 @@
+//! Server side encryption options of the selected objects.
+
+use std::convert::TryInto;
+
+use md5::{Digest, Md5};
+use rusoto_s3::SelectObjectContentRequest;
+
+use crate::error::{Error, Result};
+
+/// The only algorithm supported by S3 for the customer provided keys.
+pub const CUSTOMER_ALGORITHM: &str = "AES256";
+
+/// Encryption of the object to select.
+///
+/// The client doesn't hold an encryption, set it on each request with [`Self::apply`] or
+/// [`crate::request::SelectRequest::encryption`]. The client sends the customer key of the
+/// select with the `HeadObject` and `GetObject` requests it makes for it too.
+#[derive(Clone, PartialEq, Eq)]
+pub enum ServerSideEncryption {
+    /// SSE-S3 or SSE-KMS. S3 decrypts the object with the permissions of the signed request,
+    /// no key is sent.
+    Kms,
+    /// SSE-C with the raw 256 bits key used to encrypt the object.
+    CustomerKey([u8; 32]),
+}
+
+impl ServerSideEncryption {
+    /// Build a SSE-C option from a raw key. The key must be 32 bytes long.
+    pub fn customer_key(key: &[u8]) -> Result<Self> {
+        let key: [u8; 32] = key.try_into().map_err(|_| {
+            Error::InvalidRequest(format!(
+                "The customer key must be 32 bytes long, got {} bytes",
+                key.len()
+            ))
+        })?;
+
+        Ok(ServerSideEncryption::CustomerKey(key))
+    }
+    /// Set the encryption fields of the request. The key is base64 encoded and its MD5 computed.
+    pub fn apply(&self, request: &mut SelectObjectContentRequest) {
+        match self {
+            ServerSideEncryption::Kms => {
+                request.sse_customer_algorithm = None;
+                request.sse_customer_key = None;
+                request.sse_customer_key_md5 = None;
+            }
+            ServerSideEncryption::CustomerKey(key) => {
+                request.sse_customer_algorithm = Some(CUSTOMER_ALGORITHM.to_string());
+                request.sse_customer_key = Some(base64::encode(key));
+                request.sse_customer_key_md5 = Some(base64::encode(Md5::digest(key)));
+            }
+        }
+    }
+}
+
+// Never print the key.
+impl std::fmt::Debug for ServerSideEncryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerSideEncryption::Kms => write!(f, "Kms"),
+            ServerSideEncryption::CustomerKey(_) => write!(f, "CustomerKey(***)"),
+        }
+    }
+}
+
+/// Check the SSE-C fields of the request before sending it to the endpoint and compute the key
+/// MD5 if it's missing.
+///
+/// S3 rejects customer keys sent in clear, so the check is done before signing anything.
+pub(crate) fn prepare(endpoint: &str, request: &mut SelectObjectContentRequest) -> Result<()> {
+    prepare_customer_key(
+        endpoint,
+        request.sse_customer_algorithm.as_deref(),
+        request.sse_customer_key.as_deref(),
+        &mut request.sse_customer_key_md5,
+    )
+}
+
+/// Same checks as [`prepare`] for the other requests sent on the object.
+pub(crate) fn prepare_customer_key(
+    endpoint: &str,
+    algorithm: Option<&str>,
+    key: Option<&str>,
+    key_md5: &mut Option<String>,
+) -> Result<()> {
+    if algorithm.is_none() && key.is_none() && key_md5.is_none() {
+        return Ok(());
+    }
+
+    match algorithm {
+        Some(CUSTOMER_ALGORITHM) => (),
+        Some(algorithm) => {
+            return Err(Error::InvalidRequest(format!(
+                "The customer algorithm '{}' is not supported, use '{}'",
+                algorithm, CUSTOMER_ALGORITHM
+            )))
+        }
+        None => {
+            return Err(Error::InvalidRequest(
+                "The customer algorithm is missing".to_string(),
+            ))
+        }
+    }
+
+    let key =
+        key.ok_or_else(|| Error::InvalidRequest("The customer key is missing".to_string()))?;
+
+    let decoded_key = base64::decode(key).map_err(|e| {
+        Error::InvalidRequest(format!("The customer key is not base64 encoded: {}", e))
+    })?;
+    if decoded_key.len() != 32 {
+        return Err(Error::InvalidRequest(format!(
+            "The customer key must be 32 bytes long, got {} bytes",
+            decoded_key.len()
+        )));
+    }
+
+    let expected_md5 = base64::encode(Md5::digest(&decoded_key));
+    match key_md5 {
+        Some(key_md5) if *key_md5 != expected_md5 => {
+            return Err(Error::InvalidRequest(
+                "The customer key MD5 doesn't match the customer key".to_string(),
+            ))
+        }
+        Some(_) => (),
+        None => *key_md5 = Some(expected_md5),
+    }
+
+    if !endpoint.to_lowercase().starts_with("https://") {
+        return Err(Error::InvalidRequest(format!(
+            "The customer key can't be sent over an unencrypted connection to '{}'",
+            endpoint
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> [u8; 32] {
+        let mut key = [0; 32];
+        for (i, b) in key.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        key
+    }
+
+    #[test]
+    fn apply_customer_key() {
+        let mut request = SelectObjectContentRequest::default();
+        ServerSideEncryption::CustomerKey(key()).apply(&mut request);
+
+        assert_eq!(Some("AES256".to_string()), request.sse_customer_algorithm);
+        assert_eq!(
+            Some("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=".to_string()),
+            request.sse_customer_key
+        );
+        assert_eq!(
+            Some("tP/LI3N87DFaSk0aoqYgzg==".to_string()),
+            request.sse_customer_key_md5
+        );
+        assert!(prepare("https://s3.amazonaws.com", &mut request).is_ok());
+    }
+
+    #[test]
+    fn customer_key_with_wrong_length() {
+        assert!(ServerSideEncryption::customer_key(&[0; 16]).is_err());
+        assert!(ServerSideEncryption::customer_key(&key()).is_ok());
+    }
+
+    #[test]
+    fn prepare_refuse_http() {
+        let mut request = SelectObjectContentRequest::default();
+        ServerSideEncryption::CustomerKey(key()).apply(&mut request);
+
+        assert!(prepare("http://localhost:9000", &mut request).is_err());
+    }
+
+    #[test]
+    fn prepare_refuse_other_algorithm() {
+        let mut request = SelectObjectContentRequest::default();
+        ServerSideEncryption::CustomerKey(key()).apply(&mut request);
+        request.sse_customer_algorithm = Some("aws:kms".to_string());
+
+        assert!(prepare("https://s3.amazonaws.com", &mut request).is_err());
+    }
+
+    #[test]
+    fn prepare_refuse_wrong_md5() {
+        let mut request = SelectObjectContentRequest::default();
+        ServerSideEncryption::CustomerKey(key()).apply(&mut request);
+        request.sse_customer_key_md5 = Some("AAAAAAAAAAAAAAAAAAAAAA==".to_string());
+
+        assert!(prepare("https://s3.amazonaws.com", &mut request).is_err());
+    }
+
+    #[test]
+    fn prepare_compute_missing_md5() {
+        let mut request = SelectObjectContentRequest::default();
+        ServerSideEncryption::CustomerKey(key()).apply(&mut request);
+        request.sse_customer_key_md5 = None;
+
+        assert!(prepare("https://s3.amazonaws.com", &mut request).is_ok());
+        assert_eq!(
+            Some("tP/LI3N87DFaSk0aoqYgzg==".to_string()),
+            request.sse_customer_key_md5
+        );
+    }
+
+    #[test]
+    fn prepare_without_encryption() {
+        let mut request = SelectObjectContentRequest::default();
+        ServerSideEncryption::Kms.apply(&mut request);
+
+        assert!(prepare("http://localhost:9000", &mut request).is_ok());
+    }
+}
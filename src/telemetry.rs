@@ -0,0 +1,287 @@
+//! Spans, events and metrics of the selects, with the `tracing` feature.
+//!
+//! Each select runs in a `select_object_content` span with the bucket, the key, the region and
+//! a hash of the expression, so the queries can be grouped without logging them. An event is
+//! emitted for each event of the stream.
+//!
+//! The metrics are recorded with the [`metrics`] facade and labelled with the region, install
+//! a recorder to export them.
+
+use std::time::Instant;
+
+use metrics::{counter, histogram};
+use rusoto_s3::{SelectObjectContentRequest, Stats};
+use tracing::Span;
+
+use crate::error::Error;
+use crate::model::event_stream::is_invalid_crc;
+use crate::model::select_object_content::SelectObjectContentEventStreamItem;
+
+/// Histogram of the seconds between the request and the first `Records` event.
+pub const TIME_TO_FIRST_RECORD: &str = "s3select_time_to_first_record_seconds";
+/// Histogram of the seconds between the request and the end of the stream.
+pub const DURATION: &str = "s3select_duration_seconds";
+/// Counter of the `Records` events.
+pub const RECORDS_EVENTS: &str = "s3select_records_events_total";
+/// Counter of the bytes of the `Records` payloads.
+pub const PAYLOAD_BYTES: &str = "s3select_payload_bytes_total";
+/// Counters of the statistics of the `Stats` events.
+pub const BYTES_SCANNED: &str = "s3select_bytes_scanned_total";
+pub const BYTES_PROCESSED: &str = "s3select_bytes_processed_total";
+pub const BYTES_RETURNED: &str = "s3select_bytes_returned_total";
+/// Counter of the event stream messages with an invalid checksum.
+pub const CRC_FAILURES: &str = "s3select_crc_failures_total";
+/// Counter of the selects which ended with an error, labelled with the kind of error too.
+pub const ERRORS: &str = "s3select_errors_total";
+/// Counter of the retried requests, labelled with the reason of the retry too.
+pub const RETRIES: &str = "s3select_retries_total";
+
+/// Span and metrics of one select.
+#[derive(Debug)]
+pub(crate) struct Telemetry {
+    span: Span,
+    region: String,
+    started_at: Instant,
+    received_records: bool,
+    finished: bool,
+}
+
+impl Telemetry {
+    pub(crate) fn new(
+        request: &SelectObjectContentRequest,
+        region: &str,
+        started_at: Instant,
+    ) -> Self {
+        let span = tracing::info_span!(
+            "select_object_content",
+            bucket = %request.bucket,
+            key = %request.key,
+            region,
+            expression_hash = %format!("{:08x}", crc32fast::hash(request.expression.as_bytes())),
+        );
+        Telemetry {
+            span,
+            region: region.to_string(),
+            started_at,
+            received_records: false,
+            finished: false,
+        }
+    }
+    pub(crate) fn span(&self) -> &Span {
+        &self.span
+    }
+    /// Record an event of the stream, inside the span.
+    pub(crate) fn event(&mut self, event: &SelectObjectContentEventStreamItem) {
+        let region = self.region.clone();
+        match event {
+            SelectObjectContentEventStreamItem::Records(records) => {
+                let payload_bytes = records.payload.as_ref().map_or(0, |payload| payload.len());
+                tracing::debug!(payload_bytes, "Records");
+                if !self.received_records {
+                    self.received_records = true;
+                    histogram!(TIME_TO_FIRST_RECORD, "region" => region.clone())
+                        .record(self.started_at.elapsed());
+                }
+                counter!(RECORDS_EVENTS, "region" => region.clone()).increment(1);
+                counter!(PAYLOAD_BYTES, "region" => region).increment(payload_bytes as u64);
+            }
+            SelectObjectContentEventStreamItem::Stats(stats) => {
+                let stats = stats.details.clone().unwrap_or_default();
+                tracing::info!(
+                    bytes_scanned = stats.bytes_scanned,
+                    bytes_processed = stats.bytes_processed,
+                    bytes_returned = stats.bytes_returned,
+                    "Stats"
+                );
+                record_stats(&region, &stats);
+            }
+            SelectObjectContentEventStreamItem::Progress(progress) => {
+                let progress = progress.details.clone().unwrap_or_default();
+                tracing::debug!(
+                    bytes_scanned = progress.bytes_scanned,
+                    bytes_processed = progress.bytes_processed,
+                    bytes_returned = progress.bytes_returned,
+                    "Progress"
+                );
+            }
+            SelectObjectContentEventStreamItem::Cont(_) => tracing::trace!("Cont"),
+            SelectObjectContentEventStreamItem::End(_) => {
+                tracing::debug!(elapsed = ?self.started_at.elapsed(), "End");
+                self.finish();
+            }
+        }
+    }
+    /// Record the error ending the select.
+    pub(crate) fn error(&mut self, error: &Error) {
+        self.span
+            .in_scope(|| tracing::warn!(%error, "Select failed"));
+        if matches!(error, Error::Stream(err) if is_invalid_crc(err)) {
+            counter!(CRC_FAILURES, "region" => self.region.clone()).increment(1);
+        }
+        counter!(ERRORS, "region" => self.region.clone(), "kind" => error_kind(error)).increment(1);
+        self.finish();
+    }
+    /// Record the duration of the select, once, when the stream ends with or without `End`.
+    pub(crate) fn finish(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            histogram!(DURATION, "region" => self.region.clone()).record(self.started_at.elapsed());
+        }
+    }
+}
+
+fn record_stats(region: &str, stats: &Stats) {
+    let counters = [
+        (BYTES_SCANNED, stats.bytes_scanned),
+        (BYTES_PROCESSED, stats.bytes_processed),
+        (BYTES_RETURNED, stats.bytes_returned),
+    ];
+    for (name, bytes) in counters {
+        if let Some(bytes) = bytes {
+            counter!(name, "region" => region.to_string()).increment(bytes.max(0) as u64);
+        }
+    }
+}
+
+/// Count a request sent again, the credentials expired for example.
+pub(crate) fn retry(region: &str, reason: &'static str) {
+    tracing::debug!(reason, "Retry");
+    counter!(RETRIES, "region" => region.to_string(), "reason" => reason).increment(1);
+}
+
+fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::InvalidRequest(_) => "invalid_request",
+        Error::InvalidQuery(_) => "invalid_query",
+        Error::Http(_) => "http",
+        Error::Stream(_) => "stream",
+        Error::ConnectTimeout(_) => "connect_timeout",
+        Error::FirstEventTimeout(_) => "first_event_timeout",
+        Error::IdleTimeout(_) => "idle_timeout",
+        Error::TotalTimeout(_) => "total_timeout",
+        Error::InvalidRecord(_) => "invalid_record",
+        Error::InvalidResponse(_) => "invalid_response",
+        Error::Output(_) => "output",
+        Error::BudgetExceeded(..) => "budget_exceeded",
+        Error::Cancelled(_) => "cancelled",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credential::fetch_credentials;
+    use crate::model::event_stream::{encode_event, EventStream};
+    use crate::select::{SelectStream, Timeouts};
+    use futures::stream::{self, StreamExt, TryStreamExt};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use rusoto_core::credential::StaticProvider;
+    use rusoto_s3::{EndEvent, RecordsEvent, StatsEvent};
+    use std::collections::HashMap;
+
+    #[test]
+    fn record_select_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            async_std::task::block_on(async {
+                let events = stream::iter(vec![
+                    SelectObjectContentEventStreamItem::Records(RecordsEvent {
+                        payload: Some(b"1,2\n".to_vec().into()),
+                    }),
+                    SelectObjectContentEventStreamItem::Records(RecordsEvent {
+                        payload: Some(b"3,4\n5,6\n".to_vec().into()),
+                    }),
+                    SelectObjectContentEventStreamItem::Stats(StatsEvent {
+                        details: Some(Stats {
+                            bytes_scanned: Some(100),
+                            bytes_processed: Some(80),
+                            bytes_returned: Some(12),
+                        }),
+                    }),
+                    SelectObjectContentEventStreamItem::End(EndEvent {}),
+                ])
+                .map(Ok)
+                .boxed();
+                let telemetry = Telemetry::new(
+                    &SelectObjectContentRequest::default(),
+                    "eu-west-3",
+                    Instant::now(),
+                );
+                let events: Vec<_> =
+                    SelectStream::from_events(events, Timeouts::default(), Instant::now())
+                        .with_telemetry(telemetry)
+                        .try_collect()
+                        .await
+                        .unwrap();
+                assert_eq!(4, events.len());
+
+                let mut corrupted = encode_event("End", b"");
+                *corrupted.last_mut().unwrap() ^= 1;
+                let events = EventStream::<SelectObjectContentEventStreamItem>::new(corrupted)
+                    .map_err(Error::from)
+                    .boxed();
+                let telemetry = Telemetry::new(
+                    &SelectObjectContentRequest::default(),
+                    "eu-west-3",
+                    Instant::now(),
+                );
+                let mut events =
+                    SelectStream::from_events(events, Timeouts::default(), Instant::now())
+                        .with_telemetry(telemetry);
+                assert!(events.next().await.unwrap().is_err());
+
+                // The stream ends without `End`.
+                let telemetry = Telemetry::new(
+                    &SelectObjectContentRequest::default(),
+                    "eu-west-3",
+                    Instant::now(),
+                );
+                let events = SelectStream::from_events(
+                    stream::empty().boxed(),
+                    Timeouts::default(),
+                    Instant::now(),
+                )
+                .with_telemetry(telemetry);
+                assert_eq!(0, events.count().await);
+
+                let expired = StaticProvider::new("key".into(), "secret".into(), None, Some(0));
+                assert!(fetch_credentials(&expired, None, Some("eu-west-3"))
+                    .await
+                    .is_err());
+            })
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        for (key, _, _, _) in &snapshot {
+            assert!(
+                key.key().labels().any(|label| label.key() == "region"),
+                "{:?} has no region",
+                key
+            );
+        }
+        let metrics: HashMap<String, DebugValue> = snapshot
+            .into_iter()
+            .map(|(key, _, _, value)| (key.key().name().to_string(), value))
+            .collect();
+        let counter = |name: &str| match metrics.get(name) {
+            Some(DebugValue::Counter(value)) => *value,
+            value => panic!("{} is {:?}", name, value),
+        };
+        assert_eq!(2, counter(RECORDS_EVENTS));
+        assert_eq!(12, counter(PAYLOAD_BYTES));
+        assert_eq!(100, counter(BYTES_SCANNED));
+        assert_eq!(80, counter(BYTES_PROCESSED));
+        assert_eq!(12, counter(BYTES_RETURNED));
+        assert_eq!(1, counter(CRC_FAILURES));
+        assert_eq!(1, counter(ERRORS));
+        assert_eq!(1, counter(RETRIES));
+        assert!(
+            matches!(metrics.get(TIME_TO_FIRST_RECORD), Some(DebugValue::Histogram(values)) if values.len() == 1)
+        );
+        assert!(
+            matches!(metrics.get(DURATION), Some(DebugValue::Histogram(values)) if values.len() == 3)
+        );
+    }
+}